@@ -1,20 +1,144 @@
-use assembler::lexer::Token;
-use assembler::parser::Expression;
+use assembler::layout;
+use assembler::lexer::{Token, Position};
+use assembler::memory_map::Region;
+use assembler::object::{Relocation, RelocationKind};
+use assembler::parser::{Expression, Statement};
 use assembler::semantics;
+use assembler::suggest;
+use assembler::codes;
+use assembler::diagnostic::{Fix, Severity};
+use quirks::Quirks;
 
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Size of the Chip8 address space
+pub const MEMORY_SIZE: u32 = 4096;
+
+/// Address programs are loaded at by Chip8 interpreters
+pub const DEFAULT_BASE_ADDRESS: u32 = 0x200;
+
+/// Error produced while generating code
+#[derive(Debug, PartialEq)]
+pub struct CodeGenError {
+    severity: Severity,
+    code: &'static str,
+    position: Position,
+    message: String,
+    fixes: Vec<Fix>,
+    /// other statements that explain the problem
+    related: Vec<(String, Position)>
+}
+
+impl CodeGenError {
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Replacements for the statement that would fix the problem
+    pub fn fixes(&self) -> &[Fix] {
+        &self.fixes
+    }
+
+    /// Other statements involved in the problem, with what they have to do with it
+    pub fn related(&self) -> &[(String, Position)] {
+        &self.related
+    }
+}
+
+impl fmt::Display for CodeGenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.position.line != 0 {
+            write!(f, "{}: {}", self.position, self.message)
+        }
+        else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl Error for CodeGenError {}
+
+/// Controls which part of memory ends up in the output image
+#[derive(Debug, Clone, Copy)]
+pub struct ImageOptions {
+    /// Address of the first byte of the image. Code placed below it is an error
+    pub base: u32,
+    /// Output everything from the base to the end of memory instead of stopping at the last written byte
+    pub full_image: bool,
+    /// Value of bytes that are not written by the program
+    pub fill: u8,
+    /// How to report two statements writing the same address
+    pub overlap: Severity,
+    /// Size of the address space
+    pub memory_size: u32
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            base: DEFAULT_BASE_ADDRESS,
+            full_image: false,
+            fill: 0,
+            overlap: Severity::Error,
+            memory_size: MEMORY_SIZE
+        }
+    }
+}
+
+/// Whether bytes hold instructions or data
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ByteKind {
+    Code,
+    Data
+}
+
+/// A run of bytes written by a single statement
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SourceRange {
+    pub address: u32,
+    pub size: u32,
+    pub position: Position,
+    pub kind: ByteKind
+}
+
+/// Everything produced by a successful run of the code generator
+#[derive(Debug)]
+pub struct GeneratedCode {
+    pub opcodes: Vec<u8>,
+    pub labels: HashMap<String, u32>,
+    pub source_map: Vec<SourceRange>,
+    /// label operands left for the linker, when generating relocatable code
+    pub relocations: Vec<Relocation>,
+    pub warnings: Vec<CodeGenError>
+}
 
 /// Incomplete instruction
 struct IncompleteInstruction {
     pub address: u32,
+    pub position: Position,
     pub expr: Expression
 }
 
 impl IncompleteInstruction {
-    pub fn new(address: u32, expr: Expression) -> Self {
+    pub fn new(address: u32, position: Position, expr: Expression) -> Self {
         IncompleteInstruction {
-            address: address,
-            expr: expr
+            address,
+            position,
+            expr
         }
     }
 }
@@ -24,57 +148,157 @@ impl IncompleteInstruction {
 pub struct CodeGenerator {
     address_counter: u32,
     labels: HashMap<String, u32>,
+    constants: HashMap<String, u32>,
     opcodes: Vec<u8>,
     incomplete_queue: Vec<IncompleteInstruction>,
-    largest_address: u32
+    largest_address: u32,
+    image: ImageOptions,
+    /// position of the statement that last wrote each address, and what it wrote
+    written: Vec<Option<(Position, ByteKind)>>,
+    /// position of the statement being processed
+    position: Position,
+    /// what the statement being processed writes
+    kind: ByteKind,
+    /// only the first bad write of a statement is reported
+    write_reported: bool,
+    /// set once all expressions have been seen, labels that are still missing are undefined
+    second_pass: bool,
+    /// interpreter behaviour to warn about, none to not check
+    quirks: Option<Quirks>,
+    /// the instruction before the one being processed
+    previous_instruction: Option<Expression>,
+    /// label operands are recorded for the linker and labels that are not defined are imported
+    relocatable: bool,
+    relocations: Vec<Relocation>,
+    /// where code may be placed, anywhere in memory when empty
+    regions: Vec<Region>,
+    errors: Vec<CodeGenError>,
+    warnings: Vec<CodeGenError>
 }
 
 
+impl Default for CodeGenerator {
+    fn default() -> Self {
+        CodeGenerator::new()
+    }
+}
+
 impl CodeGenerator {
     pub fn new() -> Self {
+        CodeGenerator::with_image_options(ImageOptions::default())
+    }
+
+    /// Create a code generator that produces an image as described by the options.
+    /// Code starts at the base address unless moved by `org`
+    pub fn with_image_options(image: ImageOptions) -> Self {
         CodeGenerator {
-            address_counter: 0,
+            address_counter: image.base,
             labels: HashMap::new(),
-            opcodes: vec![0; 4096],
+            constants: HashMap::new(),
+            opcodes: vec![image.fill; image.memory_size as usize],
             incomplete_queue: vec![],
-            largest_address: 0
+            largest_address: image.base,
+            image,
+            written: vec![None; image.memory_size as usize],
+            position: Position::default(),
+            kind: ByteKind::Code,
+            write_reported: false,
+            second_pass: false,
+            quirks: None,
+            previous_instruction: None,
+            relocatable: false,
+            relocations: vec![],
+            regions: vec![],
+            errors: vec![],
+            warnings: vec![]
+        }
+    }
+
+    /// Define a constant that can be used as a label operand
+    pub fn define(&mut self, name: &str, value: u32) {
+        self.constants.insert(String::from(name), value);
+    }
+
+    /// Warn about code whose behaviour depends on these interpreter quirks
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = Some(quirks);
+    }
+
+    /// Generate code to be placed by the linker. Every label operand becomes a relocation
+    /// and labels that are not defined are left for other files to define
+    pub fn set_relocatable(&mut self) {
+        self.relocatable = true;
+    }
+
+    /// Only allow code inside these regions, filling the bytes of a region not written
+    /// by the program with its own fill
+    pub fn set_regions(&mut self, regions: Vec<Region>) {
+        for region in regions.iter() {
+            if let Some(fill) = region.fill {
+                let end = region.end().min(self.image.memory_size);
+                for byte in self.opcodes[region.start.min(end) as usize..end as usize].iter_mut() {
+                    *byte = fill;
+                }
+            }
         }
+        self.regions = regions;
     }
 
     /// Consumes the code generator and the expressions and return a vetor containing the generated opecodes
-    pub fn generate(mut self, exprs: Vec<Expression>) -> Vec<u8> {
+    pub fn generate(self, exprs: Vec<Expression>) -> Result<Vec<u8>, CodeGenError> {
+        let statements = exprs.into_iter().map(|expr| Statement{position: Position::default(), expr}).collect();
+        self.generate_statements(statements).map(|code| code.opcodes).map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Consumes the code generator and the statements. Problems are reported at the position of the statement.
+    /// On failure all problems found are returned, warnings included
+    pub fn generate_statements(mut self, statements: Vec<Statement>) -> Result<GeneratedCode, Vec<CodeGenError>> {
         // iterate over the expressions
-        for expr in exprs.iter() {
-            self.process_expression(expr);
+        for statement in statements.iter() {
+            self.position = statement.position;
+            self.process_expression(&statement.expr);
         }
 
         // perform a second pass of the expressions to add the ones that could not be completed
+        self.second_pass = true;
         while !self.incomplete_queue.is_empty() {
             let item = self.incomplete_queue.remove(0);
             self.address_counter = item.address;
+            self.position = item.position;
             self.process_expression(&item.expr);
         }
 
-        let reduced_mem = self.reduce_memory_size();
-
-        if reduced_mem.len() < 0x200 {
-            return reduced_mem
+        if !self.errors.is_empty() {
+            let mut problems = self.errors;
+            problems.append(&mut self.warnings);
+            problems.sort_by_key(|problem| problem.position);
+            return Err(problems);
         }
-        else {
-            let mut output = vec![0; reduced_mem.len() - 0x200];
 
-            for i in 0..output.len() {
-                output[i] = reduced_mem[i + 0x200];
-            }
-            return output
-        }
+        let output = self.reduce_memory_size();
+        let source_map = self.source_map();
+
+        Ok(GeneratedCode {
+            opcodes: output,
+            labels: self.labels,
+            source_map,
+            relocations: self.relocations,
+            warnings: self.warnings
+        })
     }
 
     /// Process a new expression
     fn process_expression(&mut self, expr: &Expression) {
+        self.write_reported = false;
+
         // check that the expression is valid
-        semantics::check(expr).unwrap();
-        
+        if let Err(e) = semantics::check(expr) {
+            self.report(Severity::Error, e.code(), String::from(e.message()));
+            return;
+        }
+
+        self.kind = if expr[0] == Token::Directive(String::from("db")) { ByteKind::Data } else { ByteKind::Code };
+
         match expr[0] {
             Token::Directive(_) => {
                 self.process_directive(expr);
@@ -86,14 +310,20 @@ impl CodeGenerator {
                 self.process_instruction(expr);
             },
             _ => {
-                panic!("Invalid token for start of expression");
+                self.report(Severity::Error, codes::INVALID_STATEMENT, String::from("Invalid token for start of expression"));
             }
         }
     }
 
     fn process_directive(&mut self, expr: &Expression) {
+        // data or a new location separates the instructions on either side
+        self.previous_instruction = None;
+
         if let Token::Directive(ref directive) = expr[0] {
             match directive.as_ref() {
+                "org" if self.relocatable => {
+                    self.report(Severity::Error, codes::ORG_IN_OBJECT, String::from("org can not be used in an object file, the linker places the code"));
+                },
                 "org" => {
                     if let Token::NumericLiteral(address) = expr[1] {
                         // set the new address location
@@ -103,8 +333,7 @@ impl CodeGenerator {
                 "db" => {
                     for i in 1..expr.len() {
                         if let Token::NumericLiteral(n) = expr[i] {
-                            self.opcodes[self.address_counter as usize] = n as u8;
-                            self.increment_address_counter(1);
+                            self.write_byte(n as u8);
                         }
                     }
                 }
@@ -115,16 +344,23 @@ impl CodeGenerator {
 
     fn process_label(&mut self, expr: &Expression) {
         if let Token::Label(ref label) = expr[0] {
-            if !self.labels.contains_key(label) {
+            if !self.labels.contains_key(label) && !self.constants.contains_key(label) {
                 self.labels.insert((*label).clone(), self.address_counter);
             }
             else {
-                panic!("The label: {} has already been used", label);
+                self.report(Severity::Error, codes::DUPLICATE_LABEL, format!("The label: {} has already been used", label));
             }
         }
     }
 
     fn process_instruction(&mut self, expr: &Expression) {
+        if !self.second_pass {
+            if let Some(quirks) = self.quirks {
+                self.check_quirks(quirks, expr);
+            }
+            self.previous_instruction = Some(expr.clone());
+        }
+
         if let Token::Instruction(ref instr) = expr[0] {
             match instr.as_ref() {
                 "CLS" => self.append_opcode(0x00, 0xE0),
@@ -155,6 +391,7 @@ impl CodeGenerator {
     fn process_jump_instruction(&mut self, first: u8, expr: &Expression) {
         // if the operand is a numeric literal the opcode can be generated now
         if let Token::NumericLiteral(nnn) = expr[1] {
+            let nnn = self.address_operand(nnn, None);
             let msb: u8 = first | (((nnn & 0xF00) >> 8) as u8);
             let lsb: u8 = (nnn & 0x0FF) as u8;
             self.append_opcode(msb, lsb);
@@ -162,16 +399,12 @@ impl CodeGenerator {
         // if the operand is a label operand...
         if let Token::LabelOperand(ref label) = expr[1] {
             // see if the address has been stored
-            if self.labels.contains_key(label) {
-                let address = self.labels[label];
+            if let Some(address) = self.operand_value(label, expr, RelocationKind::Address) {
+                let address = self.address_operand(address, Some(label));
                 let msb: u8 = first | (((address & 0xF00) >> 8) as u8);
                 let lsb: u8 = (address & 0xFF) as u8;
                 self.append_opcode(msb, lsb); 
             }
-            else {
-                // if the address has not been encountered, queue as incomplete
-                self.queue_incomplete_instruction(expr);
-            }
         }
     }
 
@@ -182,7 +415,7 @@ impl CodeGenerator {
             if let Token::NumericLiteral(kk) = expr[2] {
                 self.append_opcode(first | reg_num, kk as u8);
             }
-            else if let Token::Register(ref reg) = expr[3] {
+            else if let Token::Register(ref reg) = expr[2] {
                 let operand_reg_num = self.register_name_to_u8(reg);
                 self.append_opcode(second | reg_num, operand_reg_num << 4);
             }
@@ -273,6 +506,11 @@ impl CodeGenerator {
                    // println!("{:X} {:X}", 0x60 | reg1_num, kk as u8);
                     self.append_opcode(0x60 | reg1_num, kk as u8);
                 }
+                else if let Token::LabelOperand(ref label) = expr[2] {
+                    if let Some(kk) = self.operand_value(label, expr, RelocationKind::Byte) {
+                        self.append_opcode(0x60 | reg1_num, kk as u8);
+                    }
+                }
                 else if let Token::Register(ref reg2) = expr[2] {
                     if expr[2].is_general_purpose_register() {
                         let reg2_num = self.register_name_to_u8(reg2);
@@ -284,7 +522,7 @@ impl CodeGenerator {
                             "K" => self.append_opcode(0xF0 | reg1_num, 0x0A),
                             "[I]" => self.append_opcode(0xF0 | reg1_num, 0x65),
                             _ => {
-                                panic!("Invalid operand");
+                                self.report(Severity::Error, codes::INVALID_OPERAND, String::from("Invalid operand for instruction LD"));
                             }
                         }
                     }
@@ -302,31 +540,30 @@ impl CodeGenerator {
                         "F" => self.append_opcode(0xF0 | reg2_num, 0x29),
                         "B" => self.append_opcode(0xF0 | reg2_num, 0x33),
                         "[I]" => self.append_opcode(0xF0 | reg2_num, 0x55),
-                        _ => panic!("Invalid operand for instruction LD")
+                        _ => self.report(Severity::Error, codes::INVALID_OPERAND, String::from("Invalid operand for instruction LD"))
                     }
                 }
                 else if let Token::NumericLiteral(nnn) = expr[2] {
                     match reg1.as_ref() {
-                        "I" => self.append_opcode(0xA0 | (nnn >> 8) as u8, (nnn & 0xFF) as u8),
+                        "I" => {
+                            let nnn = self.address_operand(nnn, None);
+                            self.append_opcode(0xA0 | (nnn >> 8) as u8, (nnn & 0xFF) as u8);
+                        },
                         _ => {
-                            panic!("Invalid operand for instruction LD");
+                            self.report(Severity::Error, codes::INVALID_OPERAND, String::from("Invalid operand for instruction LD"));
                         }
                     }
                 } else if let Token::LabelOperand(ref label) = expr[2] {
                     match reg1.as_ref() {
                         "I" => {
                             // see if the address has been stored
-                            if self.labels.contains_key(label) {
-                                let address = self.labels[label];
-                                self.append_opcode(0xA0 | (address >> 8) as u8, (address & 0xFF) as u8); 
-                            }
-                            else {
-                                // if the address has not been encountered, queue as incomplete
-                                self.queue_incomplete_instruction(expr);
+                            if let Some(address) = self.operand_value(label, expr, RelocationKind::Address) {
+                                let address = self.address_operand(address, Some(label));
+                                self.append_opcode(0xA0 | (address >> 8) as u8, (address & 0xFF) as u8);
                             }
                         },
                         _ => {
-                            panic!("Invalid operand for instruction LD");
+                            self.report(Severity::Error, codes::INVALID_OPERAND, String::from("Invalid operand for instruction LD"));
                         }
                     }
                 }
@@ -334,31 +571,252 @@ impl CodeGenerator {
         }
     }
 
-    fn queue_incomplete_instruction(&mut self, expr: &Expression) {
-        let incomplete = IncompleteInstruction::new(self.address_counter, expr.clone());
+    /// Warn when the instruction does something other than what it says, or something
+    /// interpreters disagree on, under the given quirks
+    fn check_quirks(&mut self, quirks: Quirks, expr: &Expression) {
+        let instr = match expr[0] {
+            Token::Instruction(ref instr) => instr.clone(),
+            _ => return
+        };
+        let register = |i: usize| match expr.get(i) {
+            Some(Token::Register(reg)) => reg.clone(),
+            _ => String::new()
+        };
+
+        match instr.as_ref() {
+            "SHR" | "SHL" if !quirks.shift_vy && register(1) != register(2) => {
+                let message = format!("{} {}, {} shifts {} in place with the selected quirks, {} is ignored",
+                    instr, register(1), register(2), register(1), register(2));
+                let fix = Fix::new(&format!("Shift {} in place", register(1)), &format!("{} {}, {}", instr, register(1), register(1)));
+                self.report_with_fixes(Severity::Warning, codes::QUIRK_DEPENDENT, message, vec![fix]);
+            },
+            "JR" if quirks.jump_vx => {
+                let message = String::from("JR adds the register named by the high digit of the address instead of V0 with the selected quirks");
+                self.report(Severity::Warning, codes::QUIRK_DEPENDENT, message);
+            },
+            "OR" | "AND" | "XOR" if quirks.vf_reset && register(1) == "VF" => {
+                let message = format!("{} VF, {} leaves 0 in VF with the selected quirks", instr, register(2));
+                self.report(Severity::Warning, codes::QUIRK_DEPENDENT, message);
+            },
+            _ => {}
+        }
+
+        // LD [I], Vx and LD Vx, [I] only sometimes move I
+        let moves_i = |expr: &Expression| expr[0] == Token::Instruction(String::from("LD")) &&
+            expr.iter().skip(1).any(|token| *token == Token::Register(String::from("[I]")));
+        let reads_i = match instr.as_ref() {
+            "DRW" => true,
+            "ADD" => register(1) == "I",
+            "LD" => register(1) == "B" || moves_i(expr),
+            _ => false
+        };
+
+        let after_load_store = self.previous_instruction.as_ref().map(moves_i).unwrap_or(false);
+        if reads_i && after_load_store {
+            let effect = if quirks.load_store_increment { "has moved I past the registers" } else { "has left I unchanged" };
+            let message = format!("{} uses I after a register load or store, which {} with the selected quirks", instr, effect);
+            self.report(Severity::Warning, codes::QUIRK_DEPENDENT, message);
+        }
+    }
+
+    /// Value of a label or constant
+    fn lookup(&self, name: &str) -> Option<u32> {
+        self.labels.get(name).or_else(|| self.constants.get(name)).cloned()
+    }
+
+    /// Value to encode for a label operand. The instruction is queued until the second pass
+    /// when the label has not been seen yet
+    fn operand_value(&mut self, label: &str, expr: &Expression, kind: RelocationKind) -> Option<u32> {
+        if self.relocatable && !self.constants.contains_key(label) && (self.labels.contains_key(label) || self.second_pass) {
+            // the linker fills in the address, labels not defined here come from other files
+            self.relocations.push(Relocation{offset: self.address_counter, kind, symbol: String::from(label)});
+            return Some(self.labels.get(label).cloned().unwrap_or(0));
+        }
+
+        let value = self.lookup(label);
+        if value.is_none() {
+            // if the address has not been encountered, queue as incomplete
+            self.queue_incomplete_instruction(label, expr);
+        }
+        value
+    }
+
+    /// The 12 bits of an address operand of JP, JR, CALL or LD I. Addresses past $FFF,
+    /// which only XO-CHIP has memory at, do not fit and are an error
+    fn address_operand(&mut self, address: u32, label: Option<&str>) -> u32 {
+        if address > 0xFFF {
+            let message = match label {
+                Some(label) => format!("Address of {}, ${:X}, does not fit in 12 bits", label, address),
+                None => format!("Address ${:X} does not fit in 12 bits", address)
+            };
+            self.report(Severity::Error, codes::INVALID_OPERAND, message);
+        }
+
+        address & 0xFFF
+    }
+
+    fn queue_incomplete_instruction(&mut self, label: &str, expr: &Expression) {
+        // everything has been seen by the second pass, so the label does not exist
+        if self.second_pass {
+            let mut names: Vec<&str> = self.labels.keys().chain(self.constants.keys()).map(|name| name.as_ref()).collect();
+            names.sort();
+            match suggest::closest(label, names).map(String::from) {
+                Some(name) => {
+                    let replaced: Expression = expr.iter()
+                        .map(|token| if *token == Token::LabelOperand(String::from(label)) { Token::LabelOperand(name.clone()) } else { token.clone() })
+                        .collect();
+                    let fix = Fix::new(&format!("Use #{}", name), &semantics::to_source(&replaced));
+                    let message = format!("Undefined label: {}, did you mean {}?", label, name);
+                    self.report_with_fixes(Severity::Error, codes::UNDEFINED_LABEL, message, vec![fix]);
+                },
+                None => self.report(Severity::Error, codes::UNDEFINED_LABEL, format!("Undefined label: {}", label))
+            }
+            return;
+        }
+
+        let incomplete = IncompleteInstruction::new(self.address_counter, self.position, expr.clone());
         self.incomplete_queue.push(incomplete);
-        self.increment_address_counter(2);
+
+        // reserve the space so other statements placed here are reported now
+        for _ in 0..layout::size(expr) {
+            self.claim_address();
+            self.increment_address_counter(1);
+        }
     }
 
+    /// Cut the image out of memory, from the base address to either the last written byte or the end of memory
     fn reduce_memory_size(&mut self) -> Vec<u8> {
-        self.opcodes.drain(..self.largest_address as usize).collect()
+        let end = if self.image.full_image {
+            self.image.memory_size
+        }
+        else {
+            self.largest_address
+        };
+
+        if end <= self.image.base {
+            return vec![]
+        }
+
+        self.opcodes.drain(self.image.base as usize..end as usize).collect()
     }
 
     fn append_opcode(&mut self, msb: u8, lsb: u8) {
-        self.opcodes[self.address_counter as usize] = msb;
-        self.opcodes[(self.address_counter + 1) as usize] = lsb;
-        self.increment_address_counter(2);
+        self.write_byte(msb);
+        self.write_byte(lsb);
+    }
+
+    /// Write a byte at the current address and advance
+    fn write_byte(&mut self, value: u8) {
+        if self.claim_address() {
+            self.opcodes[self.address_counter as usize] = value;
+        }
+
+        self.increment_address_counter(1);
+    }
+
+    /// Group written addresses into runs belonging to the same statement
+    fn source_map(&self) -> Vec<SourceRange> {
+        let mut ranges: Vec<SourceRange> = Vec::new();
+
+        for (address, written) in self.written.iter().enumerate() {
+            if let Some((position, kind)) = *written {
+                let address = address as u32;
+                if let Some(last) = ranges.last_mut() {
+                    if last.position == position && last.address + last.size == address {
+                        last.size += 1;
+                        continue;
+                    }
+                }
+                ranges.push(SourceRange{address, size: 1, position, kind});
+            }
+        }
+
+        ranges
+    }
+
+    /// Mark the current address as written by the current statement.
+    /// Returns false if the address is outside of the image
+    fn claim_address(&mut self) -> bool {
+        let address = self.address_counter;
+
+        if address < self.image.base {
+            let message = format!("Code placed at ${:03X}, below the base address ${:03X}", address, self.image.base);
+            self.report_write(Severity::Error, codes::OUTSIDE_MEMORY, message);
+            return false;
+        }
+        if address >= self.image.memory_size {
+            let message = format!("Code placed at ${:03X}, past the end of memory at ${:03X}", address, self.image.memory_size);
+            self.report_write(Severity::Error, codes::OUTSIDE_MEMORY, message);
+            return false;
+        }
+        if !self.regions.is_empty() && !self.regions.iter().any(|region| region.contains(address)) {
+            let message = format!("Code placed at ${:03X}, outside of every memory region", address);
+            self.report_write(Severity::Error, codes::OUTSIDE_MEMORY, message);
+            return false;
+        }
+
+        if let Some((previous, _)) = self.written[address as usize] {
+            // overlaps with space reserved for forward references were reported in the first pass
+            if previous != self.position && !self.second_pass {
+                let message = format!("Write to ${:03X} overlaps earlier code", address);
+                let related = vec![(format!("Earlier code writing ${:03X}", address), previous)];
+                let severity = self.image.overlap;
+                self.report_write_related(severity, codes::OVERLAPPING_WRITE, message, related);
+            }
+        }
+        self.written[address as usize] = Some((self.position, self.kind));
+
+        true
+    }
+
+    fn report_write(&mut self, severity: Severity, code: &'static str, message: String) {
+        self.report_write_related(severity, code, message, vec![]);
+    }
+
+    /// Record a problem with a write along with the other statements involved
+    fn report_write_related(&mut self, severity: Severity, code: &'static str, message: String, related: Vec<(String, Position)>) {
+        if self.write_reported {
+            return;
+        }
+        self.write_reported = true;
+
+        self.push(CodeGenError{severity, code, position: self.position, message, fixes: vec![], related});
+    }
+
+    /// Record a problem with the statement being processed
+    fn report(&mut self, severity: Severity, code: &'static str, message: String) {
+        self.report_with_fixes(severity, code, message, vec![]);
+    }
+
+    /// Record a problem along with changes to the statement that would fix it
+    fn report_with_fixes(&mut self, severity: Severity, code: &'static str, message: String, fixes: Vec<Fix>) {
+        self.push(CodeGenError{severity, code, position: self.position, message, fixes, related: vec![]});
+    }
+
+    fn push(&mut self, error: CodeGenError) {
+        match error.severity {
+            Severity::Warning => self.warnings.push(error),
+            Severity::Error => self.errors.push(error)
+        }
     }
 
     fn increment_address_counter(&mut self, i: u32) {
-        self.address_counter += i;
+        self.address_counter = match self.address_counter.checked_add(i) {
+            Some(address) => address,
+            None => {
+                let message = format!("Code runs past the end of the address space at ${:X}", self.address_counter);
+                self.report_write(Severity::Error, codes::OUTSIDE_MEMORY, message);
+                u32::MAX
+            }
+        };
         if self.address_counter > self.largest_address {
             self.largest_address = self.address_counter;
         }
     }
 
-    fn register_name_to_u8(&mut self, name: &String) -> u8 {
-        name[1..].to_string().parse::<u8>().unwrap()
+    fn register_name_to_u8(&mut self, name: &str) -> u8 {
+        // register names are hexidecimal: V0 to VF
+        u8::from_str_radix(&name[1..], 16).unwrap()
     }
 }
 
@@ -377,7 +835,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x00);
         assert_eq!(opcodes[1], 0x01);
@@ -393,7 +851,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x12);
         assert_eq!(opcodes[1], 0x00);
@@ -408,7 +866,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x11);
@@ -423,7 +881,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x12);
@@ -438,7 +896,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x13);
@@ -453,7 +911,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x14);
@@ -468,7 +926,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x70);
         assert_eq!(opcodes[1], 0xFF);
@@ -483,7 +941,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x1E);
@@ -498,7 +956,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x15);
@@ -513,7 +971,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x16);
@@ -528,7 +986,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x1E);
@@ -543,7 +1001,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x17);
@@ -557,7 +1015,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xE0);
         assert_eq!(opcodes[1], 0x9E);
@@ -571,7 +1029,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xE0);
         assert_eq!(opcodes[1], 0xA1);
@@ -586,7 +1044,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xC0);
         assert_eq!(opcodes[1], 0xFF);
@@ -602,12 +1060,26 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xD0);
         assert_eq!(opcodes[1], 0x1F);
     }
 
+    #[test]
+    fn test_se_registers() {
+        let expr = vec![
+            Token::Instruction(String::from("SE")),
+            Token::Register(String::from("V0")),
+            Token::Register(String::from("V1"))
+        ];
+
+        let codegen = CodeGenerator::new();
+        let opcodes = codegen.generate(vec![expr]).unwrap();
+
+        assert_eq!(opcodes, vec![0x50, 0x10]);
+    }
+
     #[test]
     fn test_ld1() {
         let expr = vec![
@@ -617,7 +1089,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x80);
         assert_eq!(opcodes[1], 0x10);
@@ -632,7 +1104,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0x60);
         assert_eq!(opcodes[1], 0xFF);
@@ -647,7 +1119,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xAF);
         assert_eq!(opcodes[1], 0xFF);
@@ -662,7 +1134,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x07);
@@ -677,7 +1149,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x0A);
@@ -692,7 +1164,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x15);
@@ -707,7 +1179,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x18);
@@ -722,7 +1194,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x29);
@@ -737,7 +1209,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x33);
@@ -752,7 +1224,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x55);
@@ -767,7 +1239,7 @@ mod tests {
         ];
 
         let codegen = CodeGenerator::new();
-        let opcodes = codegen.generate(vec![expr]);
+        let opcodes = codegen.generate(vec![expr]).unwrap();
 
         assert_eq!(opcodes[0], 0xF0);
         assert_eq!(opcodes[1], 0x65);
@@ -785,9 +1257,341 @@ mod tests {
         let opcodes = codegen.generate(vec![
             vec![Token::Label(String::from("label"))],
             expr
-        ]);
+        ]).unwrap();
 
-        assert_eq!(opcodes[0], 0xA0);
+        assert_eq!(opcodes[0], 0xA2);
         assert_eq!(opcodes[1], 0x00);
     }
+
+    #[test]
+    fn test_org_at_base() {
+        let codegen = CodeGenerator::new();
+        let opcodes = codegen.generate(vec![
+            vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x200)],
+            vec![Token::Instruction(String::from("CLS"))]
+        ]).unwrap();
+
+        assert_eq!(opcodes, vec![0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_org_below_base() {
+        let codegen = CodeGenerator::new();
+        let result = codegen.generate(vec![
+            vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x100)],
+            vec![Token::Instruction(String::from("CLS"))]
+        ]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_custom_base() {
+        let codegen = CodeGenerator::with_image_options(ImageOptions {
+            base: 0x000,
+            ..ImageOptions::default()
+        });
+        let opcodes = codegen.generate(vec![
+            vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x002)],
+            vec![Token::Instruction(String::from("CLS"))]
+        ]).unwrap();
+
+        assert_eq!(opcodes, vec![0x00, 0x00, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        let codegen = CodeGenerator::with_image_options(ImageOptions {
+            fill: 0xFF,
+            ..ImageOptions::default()
+        });
+        let opcodes = codegen.generate(vec![
+            vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x202)],
+            vec![Token::Instruction(String::from("CLS"))]
+        ]).unwrap();
+
+        assert_eq!(opcodes, vec![0xFF, 0xFF, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_full_image() {
+        let codegen = CodeGenerator::with_image_options(ImageOptions {
+            full_image: true,
+            fill: 0xAA,
+            ..ImageOptions::default()
+        });
+        let opcodes = codegen.generate(vec![
+            vec![Token::Instruction(String::from("CLS"))]
+        ]).unwrap();
+
+        assert_eq!(opcodes.len(), (MEMORY_SIZE - DEFAULT_BASE_ADDRESS) as usize);
+        assert_eq!(opcodes[0], 0x00);
+        assert_eq!(opcodes[1], 0xE0);
+        assert_eq!(opcodes[2], 0xAA);
+    }
+
+    fn statement(line: usize, expr: Expression) -> Statement {
+        Statement{position: Position::new(line, 1), expr}
+    }
+
+    #[test]
+    fn test_overlap_error() {
+        let codegen = CodeGenerator::new();
+        let result = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("CLS"))]),
+            statement(2, vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x201)]),
+            statement(3, vec![Token::Directive(String::from("db")), Token::NumericLiteral(0xFF)])
+        ]);
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors[0].to_string(), "3:1: Write to $201 overlaps earlier code");
+        assert_eq!(errors[0].related(), &[(String::from("Earlier code writing $201"), Position::new(1, 1))]);
+    }
+
+    #[test]
+    fn test_overlap_warning() {
+        let codegen = CodeGenerator::with_image_options(ImageOptions {
+            overlap: Severity::Warning,
+            ..ImageOptions::default()
+        });
+        let code = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("CLS"))]),
+            statement(2, vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x200)]),
+            statement(3, vec![Token::Instruction(String::from("RET"))])
+        ]).unwrap();
+
+        assert_eq!(code.opcodes, vec![0x00, 0xEE]);
+        assert_eq!(code.warnings.len(), 1);
+        assert_eq!(code.warnings[0].to_string(), "3:1: Write to $200 overlaps earlier code");
+    }
+
+    #[test]
+    fn test_overlap_forward_reference() {
+        let codegen = CodeGenerator::new();
+        let result = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("JP")), Token::LabelOperand(String::from("end"))]),
+            statement(2, vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x200)]),
+            statement(3, vec![Token::Label(String::from("end"))]),
+            statement(4, vec![Token::Instruction(String::from("CLS"))])
+        ]);
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors[0].to_string(), "4:1: Write to $200 overlaps earlier code");
+        assert_eq!(errors[0].related()[0].1, Position::new(1, 1));
+    }
+
+    #[test]
+    fn test_forward_reference_no_overlap() {
+        let codegen = CodeGenerator::new();
+        let code = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("JP")), Token::LabelOperand(String::from("end"))]),
+            statement(2, vec![Token::Label(String::from("end"))]),
+            statement(3, vec![Token::Instruction(String::from("CLS"))])
+        ]).unwrap();
+
+        assert_eq!(code.opcodes, vec![0x12, 0x02, 0x00, 0xE0]);
+        assert!(code.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_write_past_end_of_memory() {
+        let codegen = CodeGenerator::new();
+        let result = codegen.generate_statements(vec![
+            statement(1, vec![Token::Directive(String::from("org")), Token::NumericLiteral(0xFFF)]),
+            statement(2, vec![Token::Instruction(String::from("CLS"))])
+        ]);
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors[0].to_string(), "2:1: Code placed at $1000, past the end of memory at $1000");
+    }
+
+    #[test]
+    fn test_write_past_end_of_address_space() {
+        let codegen = CodeGenerator::new();
+        let result = codegen.generate_statements(vec![
+            statement(1, vec![Token::Directive(String::from("org")), Token::NumericLiteral(0xFFFFFFFF)]),
+            statement(2, vec![Token::Instruction(String::from("CLS"))]),
+            statement(3, vec![Token::Instruction(String::from("RET"))])
+        ]);
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code(), codes::OUTSIDE_MEMORY);
+        assert_eq!(errors[0].to_string(), "2:1: Code placed at $FFFFFFFF, past the end of memory at $1000");
+        assert_eq!(errors[1].to_string(), "3:1: Code placed at $FFFFFFFF, past the end of memory at $1000");
+    }
+
+    #[test]
+    fn test_write_outside_regions() {
+        let mut codegen = CodeGenerator::new();
+        codegen.set_regions(vec![Region{name: String::from("ram"), start: 0x200, size: 0x100, fill: None}]);
+        let result = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("CLS"))]),
+            statement(2, vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x300)]),
+            statement(3, vec![Token::Instruction(String::from("CLS"))])
+        ]);
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors[0].to_string(), "3:1: Code placed at $300, outside of every memory region");
+    }
+
+    #[test]
+    fn test_undefined_label() {
+        let codegen = CodeGenerator::new();
+        let result = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("JP")), Token::LabelOperand(String::from("nowhere"))])
+        ]);
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "1:1: Undefined label: nowhere");
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let codegen = CodeGenerator::new();
+        let result = codegen.generate_statements(vec![
+            statement(1, vec![Token::Label(String::from("start"))]),
+            statement(2, vec![Token::Label(String::from("start"))])
+        ]);
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors[0].to_string(), "2:1: The label: start has already been used");
+    }
+
+    #[test]
+    fn test_semantics_error() {
+        let codegen = CodeGenerator::new();
+        let result = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("CLS")), Token::NumericLiteral(0)])
+        ]);
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors[0].severity(), Severity::Error);
+        assert_eq!(errors[0].message(), "CLS has no operands");
+    }
+
+    #[test]
+    fn test_define() {
+        let mut codegen = CodeGenerator::new();
+        codegen.define("LIVES", 3);
+        codegen.define("ENTRY", 0x2A0);
+
+        let opcodes = codegen.generate(vec![
+            vec![Token::Instruction(String::from("LD")), Token::Register(String::from("V0")), Token::LabelOperand(String::from("LIVES"))],
+            vec![Token::Instruction(String::from("JP")), Token::LabelOperand(String::from("ENTRY"))]
+        ]).unwrap();
+
+        assert_eq!(opcodes, vec![0x60, 0x03, 0x12, 0xA0]);
+    }
+
+    #[test]
+    fn test_high_registers() {
+        let codegen = CodeGenerator::new();
+        let opcodes = codegen.generate(vec![
+            vec![Token::Instruction(String::from("LD")), Token::Register(String::from("VA")), Token::Register(String::from("VF"))]
+        ]).unwrap();
+
+        assert_eq!(opcodes, vec![0x8A, 0xF0]);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let codegen = CodeGenerator::new();
+        let code = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("CLS"))]),
+            statement(2, vec![Token::Directive(String::from("db")), Token::NumericLiteral(1), Token::NumericLiteral(2)])
+        ]).unwrap();
+
+        assert_eq!(code.source_map, vec![
+            SourceRange{address: 0x200, size: 2, position: Position::new(1, 1), kind: ByteKind::Code},
+            SourceRange{address: 0x202, size: 2, position: Position::new(2, 1), kind: ByteKind::Data}
+        ]);
+    }
+
+    #[test]
+    fn test_source_map_org_and_forward_reference() {
+        let codegen = CodeGenerator::new();
+        let code = codegen.generate_statements(vec![
+            statement(1, vec![Token::Instruction(String::from("JP")), Token::LabelOperand(String::from("end"))]),
+            statement(2, vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x300)]),
+            statement(3, vec![Token::Label(String::from("end"))]),
+            statement(4, vec![Token::Instruction(String::from("CLS"))])
+        ]).unwrap();
+
+        assert_eq!(code.source_map, vec![
+            SourceRange{address: 0x200, size: 2, position: Position::new(1, 1), kind: ByteKind::Code},
+            SourceRange{address: 0x300, size: 2, position: Position::new(4, 1), kind: ByteKind::Code}
+        ]);
+    }
+
+    fn quirk_warnings(quirks: Quirks, statements: Vec<Statement>) -> Vec<String> {
+        let mut codegen = CodeGenerator::new();
+        codegen.set_quirks(quirks);
+
+        codegen.generate_statements(statements).unwrap().warnings.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn instruction(instr: &str, registers: &[&str]) -> Expression {
+        let mut expr = vec![Token::Instruction(String::from(instr))];
+        expr.extend(registers.iter().map(|reg| Token::Register(String::from(*reg))));
+        expr
+    }
+
+    #[test]
+    fn test_quirks_shift() {
+        let statements = vec![
+            statement(1, instruction("SHR", &["V0", "V1"])),
+            statement(2, instruction("SHL", &["V2", "V2"]))
+        ];
+
+        assert_eq!(quirk_warnings(Quirks::schip(), statements.clone()), vec!["1:1: SHR V0, V1 shifts V0 in place with the selected quirks, V1 is ignored"]);
+        assert!(quirk_warnings(Quirks::vip(), statements).is_empty());
+    }
+
+    #[test]
+    fn test_quirks_jump() {
+        let statements = vec![
+            statement(1, vec![Token::Instruction(String::from("JR")), Token::NumericLiteral(0x300)])
+        ];
+
+        assert_eq!(quirk_warnings(Quirks::schip(), statements.clone()).len(), 1);
+        assert!(quirk_warnings(Quirks::vip(), statements).is_empty());
+    }
+
+    #[test]
+    fn test_quirks_vf_reset() {
+        let statements = vec![
+            statement(1, instruction("OR", &["VF", "V1"])),
+            statement(2, instruction("AND", &["V1", "VF"]))
+        ];
+
+        assert_eq!(quirk_warnings(Quirks::vip(), statements.clone()), vec!["1:1: OR VF, V1 leaves 0 in VF with the selected quirks"]);
+        assert!(quirk_warnings(Quirks::schip(), statements).is_empty());
+    }
+
+    #[test]
+    fn test_quirks_load_store() {
+        let statements = vec![
+            statement(1, instruction("LD", &["[I]", "V2"])),
+            statement(2, instruction("LD", &["V2", "[I]"])),
+            statement(3, vec![Token::Directive(String::from("db")), Token::NumericLiteral(0)]),
+            statement(4, instruction("ADD", &["I", "V0"]))
+        ];
+
+        assert_eq!(quirk_warnings(Quirks::vip(), statements.clone()), vec![
+            "2:1: LD uses I after a register load or store, which has moved I past the registers with the selected quirks"
+        ]);
+        assert_eq!(quirk_warnings(Quirks::schip(), statements), vec![
+            "2:1: LD uses I after a register load or store, which has left I unchanged with the selected quirks"
+        ]);
+    }
+
+    #[test]
+    fn test_quirks_not_checked_by_default() {
+        let codegen = CodeGenerator::new();
+        let code = codegen.generate_statements(vec![statement(1, instruction("SHR", &["V0", "V1"]))]).unwrap();
+
+        assert!(code.warnings.is_empty());
+    }
 }
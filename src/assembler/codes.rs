@@ -0,0 +1,496 @@
+//! Stable codes identifying each kind of problem silica reports. Codes are never
+//! reused or renumbered, so tools can rely on them across versions
+
+/// Text the lexer does not recognise
+pub const UNRECOGNIZED_SYNTAX: &str = "E001";
+/// Tokens that do not form a statement
+pub const MALFORMED_STATEMENT: &str = "E002";
+/// A statement that does not start with an instruction, directive or label
+pub const INVALID_STATEMENT: &str = "E003";
+/// Too many or too few operands
+pub const OPERAND_COUNT: &str = "E004";
+/// Operands of the wrong kind
+pub const INVALID_OPERAND: &str = "E005";
+/// A section name other than code, data or font
+pub const UNKNOWN_SECTION: &str = "E006";
+/// A source, include or binary file that could not be read
+pub const UNREADABLE_FILE: &str = "E007";
+/// A file that includes itself
+pub const RECURSIVE_INCLUDE: &str = "E008";
+/// A label operand naming no label or constant
+pub const UNDEFINED_LABEL: &str = "E009";
+/// A label defined twice
+pub const DUPLICATE_LABEL: &str = "E010";
+/// `org` in a file assembled into an object file
+pub const ORG_IN_OBJECT: &str = "E011";
+/// Code below the base address, past the end of memory or outside every memory region
+pub const OUTSIDE_MEMORY: &str = "E012";
+/// Two statements writing the same address
+pub const OVERLAPPING_WRITE: &str = "E013";
+/// More placed in a memory region than it holds
+pub const REGION_OVERFLOW: &str = "E014";
+/// Sections placed below the base address or on top of each other
+pub const SECTION_PLACEMENT: &str = "E015";
+/// A symbol defined by two object files
+pub const DUPLICATE_SYMBOL: &str = "E016";
+/// A symbol used by an object file and defined by none
+pub const UNDEFINED_SYMBOL: &str = "E017";
+/// A relocation that can not be applied
+pub const BAD_RELOCATION: &str = "E018";
+/// A command line option, manifest or memory map that can not be used
+pub const INVALID_CONFIGURATION: &str = "E019";
+
+/// An instruction that behaves differently under the selected quirks
+pub const QUIRK_DEPENDENT: &str = "W001";
+/// Lints run by `silica check`
+pub const UNREACHABLE: &str = "W010";
+pub const SKIP_LONG_LOAD: &str = "W011";
+pub const CALL_DEPTH: &str = "W012";
+pub const JUMP_INTO_DATA: &str = "W013";
+pub const DRAW_ZERO_HEIGHT: &str = "W014";
+pub const FALL_THROUGH_INTO_DATA: &str = "W015";
+pub const UNINITIALIZED: &str = "W016";
+pub const VF_CLOBBER: &str = "W017";
+
+/// Every code with a short description of the problem
+pub const CODES: [(&str, &str); 28] = [
+    (UNRECOGNIZED_SYNTAX, "unrecognized syntax"),
+    (MALFORMED_STATEMENT, "malformed statement"),
+    (INVALID_STATEMENT, "invalid statement"),
+    (OPERAND_COUNT, "wrong number of operands"),
+    (INVALID_OPERAND, "invalid operand"),
+    (UNKNOWN_SECTION, "unknown section"),
+    (UNREADABLE_FILE, "file could not be read"),
+    (RECURSIVE_INCLUDE, "file includes itself"),
+    (UNDEFINED_LABEL, "undefined label"),
+    (DUPLICATE_LABEL, "duplicate label"),
+    (ORG_IN_OBJECT, "org in an object file"),
+    (OUTSIDE_MEMORY, "code outside of memory"),
+    (OVERLAPPING_WRITE, "overlapping write"),
+    (REGION_OVERFLOW, "memory region overflow"),
+    (SECTION_PLACEMENT, "invalid section placement"),
+    (DUPLICATE_SYMBOL, "duplicate symbol"),
+    (UNDEFINED_SYMBOL, "undefined symbol"),
+    (BAD_RELOCATION, "relocation can not be applied"),
+    (INVALID_CONFIGURATION, "invalid configuration"),
+    (QUIRK_DEPENDENT, "quirk dependent instruction"),
+    (UNREACHABLE, "unreachable code"),
+    (SKIP_LONG_LOAD, "skip over a long load"),
+    (CALL_DEPTH, "call stack overflow"),
+    (JUMP_INTO_DATA, "jump into data"),
+    (DRAW_ZERO_HEIGHT, "sprite of height zero"),
+    (FALL_THROUGH_INTO_DATA, "code runs into data"),
+    (UNINITIALIZED, "use of an uninitialized register"),
+    (VF_CLOBBER, "flag in VF overwritten")
+];
+
+/// Longer description of a code with examples of code that causes it and how to fix it,
+/// for `silica --explain`
+pub fn explain(code: &str) -> Option<String> {
+    let &(code, title) = CODES.iter().find(|&&(c, _)| c.eq_ignore_ascii_case(code))?;
+    Some(format!("{}: {}\n\n{}", code, title, explanation(code)))
+}
+
+fn explanation(code: &str) -> &'static str {
+    match code {
+        UNRECOGNIZED_SYNTAX => "\
+The lexer found text that is not an instruction, register, number, label,
+string or comment where it expected one, or a line missing an operand.
+Registers are V0 to VF, I, DT, ST, F and [I], numbers are decimal or start
+with $, and label operands start with #.
+
+Incorrect:
+
+            LD V0, 0xFF         ; hex is written $FF
+            LD V0, @lives
+            org
+
+Correct:
+
+            LD V0, $FF
+            LD V0, #lives
+            org $200
+",
+        MALFORMED_STATEMENT => "\
+The tokens of a line do not form a statement: a label, or an instruction or
+directive followed by its operands. Most lines that do not form a statement
+are already reported by the lexer as E001.
+",
+        INVALID_STATEMENT => "\
+A statement starts with something other than a label, an instruction or a
+directive, such as a register or a number. Most lines like this are already
+reported by the lexer as E001.
+",
+        OPERAND_COUNT => "\
+An instruction or directive has more or fewer operands than it takes. CLS
+and RET take none, JP and CALL take an address, DRW takes two registers
+and a height, and include takes a single file name.
+
+Incorrect:
+
+            CLS V0
+            DRW V0, V1          ; missing the height
+            SE V0
+
+Correct:
+
+            CLS
+            DRW V0, V1, 5
+            SE V0, 1
+",
+        INVALID_OPERAND => "\
+An operand is of the wrong kind for the instruction. Each instruction takes
+registers, numbers or labels in set places, e.g. RND takes a register and
+a number, and JP an address or a label. org only takes a number.
+
+Incorrect:
+
+            RND 5, V0
+            JP V0               ; jumping to V0 + address is JR
+            org V0
+
+Correct:
+
+            RND V0, 5
+            JR #table
+            org $300
+",
+        UNKNOWN_SECTION => "\
+The section directive names a section that does not exist. Programs have a
+code, a data and a font section.
+
+Incorrect:
+
+            section sprites
+
+Correct:
+
+            section data
+",
+        UNREADABLE_FILE => "\
+A file to assemble, include or incbin could not be read. Included files are
+looked for next to the file including them, then in the include directories
+of the project manifest.
+
+Incorrect:
+
+            include \"sprits.asm\"
+
+Correct:
+
+            include \"sprites.asm\"
+",
+        RECURSIVE_INCLUDE => "\
+A file includes itself, directly or through other files, which would never
+end. Move what both files need into a third file and include it from each.
+
+Incorrect, in a.asm:
+
+            include \"b.asm\"
+
+and in b.asm:
+
+            include \"a.asm\"
+",
+        UNDEFINED_LABEL => "\
+A label operand names a label that is not defined anywhere in the program,
+and is not a constant given with -D. Labels are defined at the start of a
+line and used with a #.
+
+Incorrect:
+
+start       CLS
+            JP #strat
+
+Correct:
+
+start       CLS
+            JP #start
+",
+        DUPLICATE_LABEL => "\
+The same label is defined twice, so operands using it would be ambiguous.
+Labels share one namespace with the constants given with -D, and with
+every included file.
+
+Incorrect:
+
+loop        CLS
+loop        JP #loop
+
+Correct:
+
+clear       CLS
+loop        JP #loop
+",
+        ORG_IN_OBJECT => "\
+org is used in a file assembled with -c. Object files are placed by the
+linker, so they can not choose their own addresses. Place whole sections
+with --place or a memory map instead.
+
+Incorrect, with -c:
+
+            org $300
+sprite
+            db $F0
+
+Correct, with --place=data=$300:
+
+            section data
+sprite
+            db $F0
+",
+        OUTSIDE_MEMORY => "\
+Code or data is placed below the base address, past the end of the target's
+memory, or outside every region of the memory map. Chip8 has 4KB of memory
+and programs start at $200.
+
+Incorrect:
+
+            org $100
+            CLS
+
+Correct:
+
+            org $200
+            CLS
+",
+        OVERLAPPING_WRITE => "\
+Two statements write the same address, usually because of an org that goes
+back over earlier code. --allow-overlap turns this into a warning.
+
+Incorrect:
+
+            CLS
+            org $200
+            RET                 ; replaces CLS
+
+Correct:
+
+            CLS
+            RET
+",
+        REGION_OVERFLOW => "\
+The sections placed in a region of the memory map hold more bytes than the
+region has. Move a section to another region in the memory map, or make
+the region bigger.
+
+Incorrect:
+
+region ram start=$200 size=$2
+section code ram
+
+Correct:
+
+region ram start=$200 size=$600
+section code ram
+",
+        SECTION_PLACEMENT => "\
+A section is placed below the base address, or on top of another section.
+Check the addresses given with --place or in the memory map.
+
+Incorrect, with --base=$200:
+
+silica link --place=data=$100 main.o
+
+Correct:
+
+silica link --place=data=$300 main.o
+",
+        DUPLICATE_SYMBOL => "\
+Two object files define the same label, so the linker does not know which
+one is meant. Every label can be used from other files, so labels must be
+unique across the whole program.
+
+Incorrect, in a.asm and b.asm:
+
+loop        JP #loop
+
+Correct, in b.asm:
+
+wait        JP #wait
+",
+        UNDEFINED_SYMBOL => "\
+An object file uses a label that no object file defines. Add the object
+file that defines it to the link, or fix the name of the label.
+
+Incorrect:
+
+silica link main.o
+
+Correct:
+
+silica link main.o sprites.o
+",
+        BAD_RELOCATION => "\
+A label operand in an object file can not be filled in: the address of the
+label does not fit in the 12 bits an instruction holds, or the object file
+is damaged. Place the section holding the label below $1000.
+",
+        INVALID_CONFIGURATION => "\
+A command line option, the project manifest or the memory map has a value
+that can not be used, such as an unknown target, quirk or lint, or a
+number that does not parse.
+
+Incorrect:
+
+silica --target=chip-8 game.asm
+
+Correct:
+
+silica --target=chip8 game.asm
+",
+        QUIRK_DEPENDENT => "\
+The instruction does something other than it says, or something that
+interpreters disagree on, with the quirks selected by --quirks and
+--quirk. SHR and SHL ignore Vy unless shift-vy is on, JR adds VX instead
+of V0 with jump-vx, OR, AND and XOR clear VF with vf-reset, and
+LD [I], Vx may or may not move I.
+
+Incorrect, with --quirks=schip:
+
+            SHR V0, V1          ; shifts V0, V1 is ignored
+
+Correct:
+
+            SHR V0, V0
+",
+        UNREACHABLE => "\
+No path from the start of the program runs this code. It usually follows an
+unconditional jump or a return without a label in front of it.
+
+Incorrect:
+
+end         JP #end
+            CLS
+
+Correct:
+
+            CLS
+end         JP #end
+",
+        SKIP_LONG_LOAD => "\
+A skip is in front of XO-CHIP's 4-byte LD I, long (F000 NNNN). Skips jump
+over 2 bytes, so the second half of the load runs as an instruction on its
+own. Skip a jump over the load instead.
+
+Incorrect:
+
+            SE V0, 1
+            db $F0 $00 $03 $00  ; LD I, long $0300
+",
+        CALL_DEPTH => "\
+Calls are nested deeper than the 16 return addresses the stack holds, or a
+subroutine calls itself. Turn the recursion into a loop, or call fewer
+subroutines from subroutines.
+
+Incorrect:
+
+count       CALL #count
+
+Correct:
+
+count       ADD V0, 1
+            SE V0, 0
+            JP #count
+",
+        JUMP_INTO_DATA => "\
+A JP, CALL or JR goes to data, or to an address nothing is placed at, so
+the bytes there would run as instructions.
+
+Incorrect:
+
+            JP #sprite
+sprite
+            db $F0 $90
+
+Correct:
+
+            LD I, #sprite
+            ...
+sprite
+            db $F0 $90
+",
+        DRAW_ZERO_HEIGHT => "\
+DRW with a height of 0 draws nothing on Chip8. Only SCHIP and XO-CHIP draw
+a 16x16 sprite for it.
+
+Incorrect, with --target=chip8:
+
+            DRW V0, V1, 0
+
+Correct:
+
+            DRW V0, V1, 15
+",
+        FALL_THROUGH_INTO_DATA => "\
+Code runs on into db data without a jump or return, so the data would run
+as instructions.
+
+Incorrect:
+
+start       CLS
+sprite
+            db $F0
+
+Correct:
+
+start       CLS
+end         JP #end
+sprite
+            db $F0
+",
+        UNINITIALIZED => "\
+A register, I, DT or ST may be read before anything is put in it.
+Interpreters start with different values in them, so set them first.
+
+Incorrect:
+
+start       DRW V0, V1, 5
+
+Correct:
+
+start       LD I, #digit
+            LD V0, 0
+            LD V1, 0
+            DRW V0, V1, 5
+",
+        VF_CLOBBER => "\
+VF is read as the carry or borrow flag of an earlier instruction, but
+something in between overwrote it, such as VF being used as a scratch
+register. Copy the flag to another register before VF is used again.
+
+Incorrect:
+
+            ADD V0, V1
+            LD VF, 5
+            SE VF, 1            ; meant to test the carry
+
+Correct:
+
+            ADD V0, V1
+            LD V4, VF
+            LD VF, 5
+            SE V4, 1
+",
+        _ => ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_code_is_explained() {
+        for (i, &(code, _)) in CODES.iter().enumerate() {
+            assert!(!explanation(code).is_empty(), "{} has no explanation", code);
+            assert!(CODES[i + 1..].iter().all(|&(other, _)| other != code), "{} is listed twice", code);
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        assert!(explain("e009").unwrap().starts_with("E009: undefined label\n\n"));
+        assert_eq!(explain("E999"), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+
+/// How seriously a problem found while assembling is taken
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error")
+        }
+    }
+}
+
+/// A position in a named source file. Columns count bytes from 1, as the lexer does,
+/// and `end_column` is the column just past the end of what the location covers, on the same line
+#[derive(Debug, PartialEq, Clone)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_column: usize
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// A change to the source that fixes a problem: `replacement` takes the place of
+/// the text the diagnostic's location covers
+#[derive(Debug, PartialEq, Clone)]
+pub struct Fix {
+    pub message: String,
+    pub replacement: String
+}
+
+impl Fix {
+    pub fn new(message: &str, replacement: &str) -> Self {
+        Fix {
+            message: String::from(message),
+            replacement: String::from(replacement)
+        }
+    }
+}
+
+/// Another place in the source that explains a problem, such as the earlier of two
+/// statements writing the same address
+#[derive(Debug, PartialEq, Clone)]
+pub struct Related {
+    pub message: String,
+    pub location: Location
+}
+
+impl Related {
+    pub fn new(message: &str, location: Location) -> Self {
+        Related {
+            message: String::from(message),
+            location
+        }
+    }
+}
+
+/// A problem found while assembling, along with where it was found if known.
+/// `code` identifies the kind of problem, see `assembler::codes`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub location: Option<Location>,
+    pub fixes: Vec<Fix>,
+    pub related: Vec<Related>
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: &str, location: Option<Location>) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            message: String::from(message),
+            location,
+            fixes: vec![],
+            related: vec![]
+        }
+    }
+
+    pub fn with_fixes(mut self, fixes: Vec<Fix>) -> Self {
+        self.fixes = fixes;
+        self
+    }
+
+    pub fn with_related(mut self, related: Vec<Related>) -> Self {
+        self.related = related;
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(ref location) => write!(f, "{}: {}[{}]: {}", location, self.severity, self.code, self.message)?,
+            None => write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?
+        }
+        // related locations follow as notes, one per line
+        for related in self.related.iter() {
+            write!(f, "\n{}: note: {}", related.location, related.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::codes;
+
+    #[test]
+    fn test_display_with_location() {
+        let location = Location{file: String::from("game.asm"), line: 3, column: 5, end_column: 13};
+        let diagnostic = Diagnostic::new(Severity::Warning, codes::OVERLAPPING_WRITE, "Write to $200 overlaps code", Some(location));
+
+        assert_eq!(diagnostic.to_string(), "game.asm:3:5: warning[E013]: Write to $200 overlaps code");
+    }
+
+    #[test]
+    fn test_display_with_related() {
+        let location = Location{file: String::from("game.asm"), line: 3, column: 5, end_column: 13};
+        let earlier = Location{file: String::from("lib.asm"), line: 1, column: 2, end_column: 5};
+        let diagnostic = Diagnostic::new(Severity::Error, codes::OVERLAPPING_WRITE, "Write to $200 overlaps earlier code", Some(location))
+            .with_related(vec![Related::new("Earlier code writing $200", earlier)]);
+
+        assert_eq!(diagnostic.to_string(), "game.asm:3:5: error[E013]: Write to $200 overlaps earlier code\n\
+                                            lib.asm:1:2: note: Earlier code writing $200");
+    }
+
+    #[test]
+    fn test_display_without_location() {
+        let diagnostic = Diagnostic::new(Severity::Error, codes::UNREADABLE_FILE, "Could not read file", None);
+
+        assert_eq!(diagnostic.to_string(), "error[E007]: Could not read file");
+    }
+}
@@ -0,0 +1,96 @@
+use assembler::lexer::Token;
+use assembler::parser::{Expression, Statement};
+
+use std::collections::HashMap;
+
+/// Where each statement of a program ends up in memory
+#[derive(Debug, PartialEq)]
+pub struct Layout {
+    /// address of each statement, in the order of the statements
+    pub addresses: Vec<u32>,
+    /// address of each label. Only the first definition of a label counts
+    pub labels: HashMap<String, u32>
+}
+
+impl Layout {
+    /// Index of the first statement writing bytes at `address`
+    pub fn statement_at(&self, statements: &[Statement], address: u32) -> Option<usize> {
+        self.addresses.iter().zip(statements.iter())
+            .position(|(&start, statement)| start == address && size(&statement.expr) > 0)
+    }
+
+    /// Address a label or numeric operand refers to. Constants are not known here
+    pub fn resolve(&self, operand: &Token) -> Option<u32> {
+        match *operand {
+            Token::NumericLiteral(address) => Some(address),
+            Token::LabelOperand(ref label) => self.labels.get(label).cloned(),
+            _ => None
+        }
+    }
+}
+
+/// Number of bytes a statement writes
+pub fn size(expr: &Expression) -> u32 {
+    match expr.first() {
+        Some(Token::Instruction(_)) => 2,
+        Some(Token::Directive(ref directive)) if directive == "db" => expr.len() as u32 - 1,
+        _ => 0
+    }
+}
+
+/// Work out the address of every statement and label the way the code generator
+/// will place them, starting at `base`
+pub fn layout(statements: &[Statement], base: u32) -> Layout {
+    let mut address = base;
+    let mut addresses = Vec::with_capacity(statements.len());
+    let mut labels = HashMap::new();
+
+    for statement in statements.iter() {
+        match (statement.expr.first(), statement.expr.get(1)) {
+            (Some(Token::Directive(ref directive)), Some(&Token::NumericLiteral(org))) if directive == "org" => address = org,
+            (Some(Token::Label(ref label)), _) => {
+                labels.entry(label.clone()).or_insert(address);
+            },
+            _ => {}
+        }
+
+        addresses.push(address);
+        // the code generator reports code running past the end of the address space
+        address = address.saturating_add(size(&statement.expr));
+    }
+
+    Layout {
+        addresses,
+        labels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::lexer::Position;
+
+    fn statement(expr: Expression) -> Statement {
+        Statement{position: Position::default(), expr}
+    }
+
+    #[test]
+    fn test_layout() {
+        let statements = vec![
+            statement(vec![Token::Instruction(String::from("CLS"))]),
+            statement(vec![Token::Label(String::from("sprite"))]),
+            statement(vec![Token::Directive(String::from("db")), Token::NumericLiteral(0xF0), Token::NumericLiteral(0x90)]),
+            statement(vec![Token::Directive(String::from("org")), Token::NumericLiteral(0x300)]),
+            statement(vec![Token::Label(String::from("end"))]),
+            statement(vec![Token::Instruction(String::from("JP")), Token::LabelOperand(String::from("end"))])
+        ];
+
+        let layout = layout(&statements, 0x200);
+
+        assert_eq!(layout.addresses, vec![0x200, 0x202, 0x202, 0x300, 0x300, 0x300]);
+        assert_eq!(layout.labels.get("sprite"), Some(&0x202));
+        assert_eq!(layout.statement_at(&statements, 0x300), Some(5));
+        assert_eq!(layout.resolve(&Token::LabelOperand(String::from("end"))), Some(0x300));
+        assert_eq!(layout.resolve(&Token::LabelOperand(String::from("missing"))), None);
+    }
+}
@@ -1,12 +1,72 @@
 use std::str::{from_utf8, FromStr};
 use std::u32;
+use std::error::Error;
+use std::fmt;
 
 use nom::*;
 
+use assembler::codes;
+use assembler::diagnostic::Fix;
+use assembler::suggest;
+
 /// Error type if lexer encounters an error in the bit stream
 #[derive(Debug)]
 pub struct LexerError {
-    message: String
+    position: Position,
+    message: String,
+    fixes: Vec<Fix>
+}
+
+impl LexerError {
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn code(&self) -> &'static str {
+        codes::UNRECOGNIZED_SYNTAX
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Replacements for the rest of the line that would fix the problem
+    pub fn fixes(&self) -> &[Fix] {
+        &self.fixes
+    }
+}
+
+impl fmt::Display for LexerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+impl Error for LexerError {}
+
+/// Location of a token in the source. Lines and columns start at 1, zero means unknown.
+/// `file` is an index into the list of files being assembled, the main file being 0
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub file: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Self {
+        Position {
+            file: 0,
+            line,
+            column
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
 }
 
 /// Possible tokens that can exist in the Chip8 assembly file
@@ -18,6 +78,7 @@ pub enum Token {
     Register(String),
     NumericLiteral(u32),
     LabelOperand(String),
+    StringLiteral(String),
     Comma
 }
 
@@ -55,8 +116,32 @@ impl Token {
             _ => false
         }
     }
+
+    pub fn is_string_literal(&self) -> bool {
+        matches!(*self, Token::StringLiteral(_))
+    }
+}
+
+impl fmt::Display for Token {
+    /// Write the token as it would appear in source. Numbers of 16 and up are written in hex
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Directive(ref name) | Token::Label(ref name) | Token::Instruction(ref name) | Token::Register(ref name) => write!(f, "{}", name),
+            Token::NumericLiteral(n) if n < 16 => write!(f, "{}", n),
+            Token::NumericLiteral(n) => write!(f, "${:X}", n),
+            Token::LabelOperand(ref label) => write!(f, "#{}", label),
+            Token::StringLiteral(ref value) => write!(f, "\"{}\"", value),
+            Token::Comma => write!(f, ",")
+        }
+    }
 }
 
+pub const INSTRUCTIONS: [&str; 21] = ["CLS", "RET", "SYS", "JP", "JR", "CALL", "SE", "SNE", "LD", "ADD", "SUBN",
+    "SUB", "OR", "AND", "XOR", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP"];
+pub const DIRECTIVES: [&str; 5] = ["org", "db", "include", "incbin", "section"];
+pub const REGISTERS: [&str; 21] = ["V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD",
+    "VE", "VF", "I", "DT", "ST", "F", "[I]"];
+
 /// parse a label
 named!(lex_label<&[u8], Token>,
     do_parse!(
@@ -137,7 +222,10 @@ named!(lex_directives<&[u8], Token>,
     do_parse!(
         directive: map_res!(map_res!(alt!(
             tag!("org") |
-            tag!("db")
+            tag!("db")      |
+            tag!("include") |
+            tag!("incbin")  |
+            tag!("section")
         ), from_utf8), FromStr::from_str) >>
         (Token::Directive(directive))
     )
@@ -182,6 +270,16 @@ named!(lex_label_operand<&[u8], Token>,
     )
 );
 
+/// Parse a string literal
+named!(lex_string_literal<&[u8], Token>,
+    do_parse!(
+        tag!("\"") >>
+        value: map_res!(map_res!(is_not!("\"\r\n"), from_utf8), FromStr::from_str) >>
+        tag!("\"") >>
+        (Token::StringLiteral(value))
+    )
+);
+
 /// Parse an instruction
 named!(lex_instruction<&[u8], Vec<Token>>,
     do_parse!(
@@ -346,6 +444,32 @@ named!(lex_line7<&[u8], Vec<Token>>,
     )
 );
 
+/// Parse line combination 8
+/// include "file.asm"
+named!(lex_line8<&[u8], Vec<Token>>,
+    do_parse!(
+        lex_column_sep >>
+        directive: lex_directives >>
+        lex_column_sep >>
+        path: lex_string_literal >>
+        lex_line_termination >>
+        (vec![directive, path])
+    )
+);
+
+/// Parse line combination 9
+/// section data
+named!(lex_line9<&[u8], Vec<Token>>,
+    do_parse!(
+        lex_column_sep >>
+        directive: lex_directives >>
+        lex_column_sep >>
+        name: map_res!(map_res!(alphanumeric, from_utf8), FromStr::from_str) >>
+        lex_line_termination >>
+        (vec![directive, Token::LabelOperand(name)])
+    )
+);
+
 /// Combined line parser
 named!(lex_lines<&[u8], Vec<Token>>,
     do_parse!(
@@ -357,7 +481,9 @@ named!(lex_lines<&[u8], Vec<Token>>,
                 lex_line4 |
                 lex_line5 |
                 lex_line6 |
-                lex_line7
+                lex_line7 |
+                lex_line8 |
+                lex_line9
             )
         ) >>
         ({
@@ -375,19 +501,261 @@ named!(lex_lines<&[u8], Vec<Token>>,
 
 /// Convert input bytes into tokens
 pub fn tokenize(input: &[u8]) -> Result<Vec<Token>, LexerError> {
-    let lexer_result = lex_lines(input);
+    let tokens = tokenize_with_positions(input)?;
+    Ok(tokens.into_iter().map(|(_, token)| token).collect())
+}
 
-    match lexer_result {
-        IResult::Done(_, tokens) => {
-            Ok(tokens)
-        },
-        IResult::Error(_) => {
-            Err(LexerError{message: String::from("Error in lexer")})
+/// Convert input bytes into tokens, keeping the position each token was found at
+pub fn tokenize_with_positions(input: &[u8]) -> Result<Vec<(Position, Token)>, LexerError> {
+    let mut ret = Vec::new();
+
+    for (line_index, line) in input.split(|&c| c == b'\n').enumerate() {
+        for (token, (start, _)) in lex_line(line, line_index + 1)? {
+            ret.push((Position::new(line_index + 1, start + 1), token));
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Text around tokens that does not change what the program means
+#[derive(Debug, PartialEq, Clone)]
+pub enum Trivia {
+    /// spaces and tabs
+    Whitespace(String),
+    /// a comment, starting with its `;`
+    Comment(String),
+    /// `\n` or `\r\n`
+    LineBreak(String)
+}
+
+impl fmt::Display for Trivia {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Trivia::Whitespace(ref text) | Trivia::Comment(ref text) | Trivia::LineBreak(ref text) => write!(f, "{}", text)
+        }
+    }
+}
+
+/// A token as it was written, with the trivia around it. Trailing trivia runs up to
+/// the next token on the same line or up to and including the line break, leading
+/// trivia is whatever comes before the token on earlier lines and its own
+#[derive(Debug, PartialEq, Clone)]
+pub struct TriviaToken {
+    pub position: Position,
+    pub token: Token,
+    pub text: String,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>
+}
+
+/// Tokens of a source with every comment, space and line break kept. Trivia after
+/// the last token, such as comments at the end of the file, is in `end`
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LosslessTokens {
+    pub tokens: Vec<TriviaToken>,
+    pub end: Vec<Trivia>
+}
+
+impl LosslessTokens {
+    /// The source the tokens were read from
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        for token in self.tokens.iter() {
+            for trivia in token.leading.iter() {
+                out.push_str(&trivia.to_string());
+            }
+            out.push_str(&token.text);
+            for trivia in token.trailing.iter() {
+                out.push_str(&trivia.to_string());
+            }
+        }
+        for trivia in self.end.iter() {
+            out.push_str(&trivia.to_string());
+        }
+
+        out
+    }
+}
+
+/// Convert input bytes into tokens without losing anything: comments, whitespace
+/// and line breaks are kept as trivia, so `to_source` gives back the input exactly
+/// when it is valid UTF-8. The tokens are the same as those of `tokenize`
+pub fn tokenize_with_trivia(input: &[u8]) -> Result<LosslessTokens, LexerError> {
+    let mut tokens: Vec<TriviaToken> = Vec::new();
+    // trivia waiting for the next token
+    let mut pending = Vec::new();
+    let lines: Vec<&[u8]> = input.split(|&c| c == b'\n').collect();
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+
+    for (line_index, line) in lines.iter().enumerate() {
+        // the last line is the one not ended by a line break
+        let (code, line_break) = if line_index + 1 == lines.len() {
+            (*line, None)
+        }
+        else if line.ends_with(b"\r") {
+            (&line[..line.len() - 1], Some("\r\n"))
+        }
+        else {
+            (*line, Some("\n"))
+        };
+        let lexed = lex_line(code, line_index + 1)?;
+        let has_tokens = !lexed.is_empty();
+
+        let mut end = 0;
+        for (i, (token, (start, finish))) in lexed.into_iter().enumerate() {
+            let gap = Trivia::Whitespace(text(&code[end..start]));
+            if start > end {
+                if i == 0 {
+                    pending.push(gap);
+                }
+                else if let Some(previous) = tokens.last_mut() {
+                    previous.trailing.push(gap);
+                }
+            }
+            tokens.push(TriviaToken {
+                position: Position::new(line_index + 1, start + 1),
+                token,
+                text: text(&code[start..finish]),
+                leading: pending.split_off(0),
+                trailing: Vec::new()
+            });
+            end = finish;
+        }
+
+        // the rest of the line follows the last token on it, or waits for the next
+        let mut rest = Vec::new();
+        let comment = code[end..].iter().position(|&c| c == b';').map(|i| end + i).unwrap_or(code.len());
+        if comment > end {
+            rest.push(Trivia::Whitespace(text(&code[end..comment])));
+        }
+        if comment < code.len() {
+            rest.push(Trivia::Comment(text(&code[comment..])));
+        }
+        if let Some(line_break) = line_break {
+            rest.push(Trivia::LineBreak(String::from(line_break)));
+        }
+        match tokens.last_mut() {
+            Some(last) if has_tokens => last.trailing.extend(rest),
+            _ => pending.extend(rest)
+        }
+    }
+
+    Ok(LosslessTokens{tokens, end: pending})
+}
+
+/// Range of bytes a token covers in its line, the end being just past it
+type Span = (usize, usize);
+
+/// Lex a line without its line ending, giving each token with the bytes it covers
+fn lex_line(line: &[u8], number: usize) -> Result<Vec<(Token, Span)>, LexerError> {
+    // every line combination expects a line ending
+    let mut line = line.to_vec();
+    line.push(b'\n');
+
+    let tokens = match lex_lines(&line[..]) {
+        IResult::Done(&[], tokens) => tokens,
+        _ => {
+            let (column, message, fixes) = diagnose(&line[..]);
+            return Err(LexerError{position: Position::new(number, column), message, fixes})
+        }
+    };
+
+    let spans = token_spans(&line[..], &tokens);
+    Ok(tokens.into_iter().zip(spans).collect())
+}
+
+/// Find the word of a line the lexer stumbled on and what was likely meant, giving its
+/// column, a message and fixes replacing the rest of the line up to any comment
+fn diagnose(line: &[u8]) -> (usize, String, Vec<Fix>) {
+    let line = String::from_utf8_lossy(line);
+    let code = line.split(';').next().unwrap_or("").trim_end();
+
+    // words with the index they start at, the first is a label when the line does not start with a space
+    let mut words: Vec<(usize, &str)> = Vec::new();
+    let mut start = None;
+    for (i, c) in code.char_indices().chain(Some((code.len(), ' '))) {
+        let separator = c.is_whitespace() || c == ',';
+        match start {
+            Some(s) if separator => {
+                words.push((s, &code[s..i]));
+                start = None;
+            },
+            None if !separator => start = Some(i),
+            _ => {}
+        }
+    }
+    let statement = if code.starts_with(|c: char| !c.is_whitespace()) { 1 } else { 0 };
+
+    let unknown = |i: usize, what: &str, suggestion: Option<String>| {
+        let (at, word) = words[i];
+        let message = match suggestion {
+            Some(ref label) if label.starts_with('#') => format!("{} is not a {}, did you mean the label {}?", word, what, label),
+            Some(ref suggestion) => format!("Unknown {} {}, did you mean {}?", what, word, suggestion),
+            None => format!("Unknown {} {}", what, word)
+        };
+        let fixes = suggestion.iter()
+            .map(|suggestion| Fix::new(&format!("Replace with {}", suggestion), &format!("{}{}", suggestion, &code[at + word.len()..])))
+            .collect();
+        (at + 1, message, fixes)
+    };
+
+    match words.get(statement) {
+        Some(&(_, word)) if DIRECTIVES.contains(&word) => return (1, String::from("Error in lexer"), vec![]),
+        Some(&(at, word)) if !word.chars().all(|c| c.is_ascii_alphanumeric()) => return (at + 1, format!("Unexpected {}", word), vec![]),
+        Some(&(_, word)) if !INSTRUCTIONS.contains(&word) => {
+            let suggestion = suggest::closest(word, INSTRUCTIONS.iter().chain(DIRECTIVES.iter()).cloned());
+            return unknown(statement, "instruction", suggestion.map(String::from));
         },
-        IResult::Incomplete(_) => {
-            Err(LexerError{message: String::from("Error in lexer")})
+        _ => {}
+    }
+
+    for (i, &(_, word)) in words.iter().enumerate().skip(statement + 1) {
+        if word.starts_with(|c: char| c == '#' || c == '$' || c == '"' || c.is_ascii_digit()) || REGISTERS.contains(&word) {
+            continue;
+        }
+        if !word.chars().all(|c| c.is_ascii_alphanumeric() || c == '[' || c == ']') {
+            return (words[i].0 + 1, format!("Unexpected {}", word), vec![]);
+        }
+        // a word that is not close to a register is likely a label missing its #
+        let suggestion = match suggest::closest(word, REGISTERS.iter().cloned()) {
+            Some(register) => Some(String::from(register)),
+            None if word.len() > 1 => Some(format!("#{}", word)),
+            None => None
+        };
+        return unknown(i, "register", suggestion);
+    }
+
+    (1, String::from("Error in lexer"), vec![])
+}
+
+/// Find the range of bytes each token of a line covers.
+/// Tokens never contain separators outside of strings so the line is scanned from one token to the next
+fn token_spans(line: &[u8], tokens: &[Token]) -> Vec<Span> {
+    let is_separator = |c: u8| c == b' ' || c == b'\t' || c == b',' || c == b';' || c == b'\r' || c == b'\n';
+
+    let mut spans = Vec::new();
+    let mut i = 0;
+
+    for token in tokens.iter() {
+        while i < line.len() && (line[i] == b' ' || line[i] == b'\t') {
+            i += 1;
+        }
+        let start = i;
+
+        match *token {
+            Token::Comma => i += 1,
+            Token::StringLiteral(ref value) => i += value.len() + 2,
+            _ => {
+                while i < line.len() && !is_separator(line[i]) {
+                    i += 1;
+                }
+            }
         }
+        spans.push((start, i));
     }
+
+    spans
 }
 
 #[cfg(test)]
@@ -478,7 +846,7 @@ mod tests {
 
     #[test]
     fn test_lex_directives() {
-        let directives = vec!["org", "db"];
+        let directives = vec!["org", "db", "include", "incbin", "section"];
 
         for directive in directives.iter() {
             let result = lex_directives(directive.as_bytes());
@@ -797,4 +1165,114 @@ mod tests {
 
         assert_eq!(result, IResult::Done(&b""[..], expected_tokens));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tokenize_with_trivia() {
+        let result = tokenize_with_trivia(b"; header\nstart\tLD V0,5 ; five\n").unwrap();
+        let whitespace = |text: &str| Trivia::Whitespace(String::from(text));
+        let line_break = Trivia::LineBreak(String::from("\n"));
+
+        let start = &result.tokens[0];
+        assert_eq!(start.token, Token::Label(String::from("start")));
+        assert_eq!(start.leading, vec![Trivia::Comment(String::from("; header")), line_break.clone()]);
+        assert_eq!(start.trailing, vec![whitespace("\t")]);
+        assert_eq!(result.tokens[2].trailing, vec![]);
+        assert_eq!(result.tokens[2].position, Position::new(2, 10));
+        assert_eq!(result.tokens[4].trailing, vec![whitespace(" "), Trivia::Comment(String::from("; five")), line_break]);
+        assert_eq!(result.end, vec![]);
+    }
+
+    #[test]
+    fn test_tokenize_with_trivia_is_lossless() {
+        let source = "; sprites\r\n\r\n  \t\nstart\tLD  V0 , $0a\r\n\tsection data ; after\n\tinclude \"a ;b.asm\"\nship\n\tdb $18 $3C\n; the end";
+        let result = tokenize_with_trivia(source.as_bytes()).unwrap();
+
+        assert_eq!(result.to_source(), source);
+        assert_eq!(result.tokens.iter().map(|token| token.token.clone()).collect::<Vec<_>>(), tokenize(source.as_bytes()).unwrap());
+        let texts: Vec<&str> = result.tokens.iter().map(|token| token.text.as_ref()).collect();
+        assert_eq!(texts, vec!["start", "LD", "V0", ",", "$0a", "section", "data", "include", "\"a ;b.asm\"", "ship", "db", "$18", "$3C"]);
+        assert_eq!(result.end, vec![Trivia::Comment(String::from("; the end"))]);
+
+        assert_eq!(tokenize_with_trivia(b"").unwrap(), LosslessTokens::default());
+        assert_eq!(tokenize_with_trivia(b"\tDRAW\n").unwrap_err().to_string(), "1:2: Unknown instruction DRAW, did you mean DRW?");
+    }
+
+    #[test]
+    fn test_tokenize_with_positions() {
+        let input = "start\tLD V0, $FF ; comment\n\n\t\tJP #start".as_bytes();
+        let result = tokenize_with_positions(input).unwrap();
+
+        assert_eq!(result, vec![
+            (Position::new(1, 1), Token::Label(String::from("start"))),
+            (Position::new(1, 7), Token::Instruction(String::from("LD"))),
+            (Position::new(1, 10), Token::Register(String::from("V0"))),
+            (Position::new(1, 12), Token::Comma),
+            (Position::new(1, 14), Token::NumericLiteral(0xFF)),
+            (Position::new(3, 3), Token::Instruction(String::from("JP"))),
+            (Position::new(3, 6), Token::LabelOperand(String::from("start")))
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize_error_line() {
+        let input = "\t\tCLS\n\t\t???\n".as_bytes();
+        let result = tokenize(input);
+
+        assert_eq!(result.unwrap_err().to_string(), "2:3: Unexpected ???");
+    }
+
+    #[test]
+    fn test_tokenize_error_suggestion() {
+        let error = tokenize(b"start\tDRAW V0, V1, 5 ; ship\n").unwrap_err();
+        assert_eq!(error.to_string(), "1:7: Unknown instruction DRAW, did you mean DRW?");
+        assert_eq!(error.fixes(), &[Fix::new("Replace with DRW", "DRW V0, V1, 5")]);
+
+        let error = tokenize(b"\tLD VG, 1\n").unwrap_err();
+        assert_eq!(error.to_string(), "1:5: Unknown register VG, did you mean V0?");
+        assert_eq!(error.fixes(), &[Fix::new("Replace with V0", "V0, 1")]);
+
+        let error = tokenize(b"\tJP start\n").unwrap_err();
+        assert_eq!(error.to_string(), "1:5: start is not a register, did you mean the label #start?");
+    }
+
+    #[test]
+    fn test_display_token() {
+        assert_eq!(Token::NumericLiteral(5).to_string(), "5");
+        assert_eq!(Token::NumericLiteral(0x200).to_string(), "$200");
+        assert_eq!(Token::LabelOperand(String::from("start")).to_string(), "#start");
+    }
+
+    #[test]
+    fn test_lex_string_literal() {
+        let input = "\"sprites.asm\"".as_bytes();
+        let result = lex_string_literal(input);
+
+        assert_eq!(result, IResult::Done(&b""[..], Token::StringLiteral(String::from("sprites.asm"))));
+    }
+
+    #[test]
+    fn test_lex_line8() {
+        let input = "\t\tinclude \"sprites.asm\" ; sprite data\n".as_bytes();
+        let result = lex_line8(input);
+
+        let expected_tokens = vec![
+            Token::Directive(String::from("include")),
+            Token::StringLiteral(String::from("sprites.asm"))
+        ];
+
+        assert_eq!(result, IResult::Done(&b""[..], expected_tokens));
+    }
+
+    #[test]
+    fn test_lex_line9() {
+        let input = "\tsection data ; sprites\n".as_bytes();
+        let result = lex_line9(input);
+
+        let expected_tokens = vec![
+            Token::Directive(String::from("section")),
+            Token::LabelOperand(String::from("data"))
+        ];
+
+        assert_eq!(result, IResult::Done(&b""[..], expected_tokens));
+    }
+}
@@ -0,0 +1,339 @@
+use assembler::codes;
+use assembler::codegenerator::{DEFAULT_BASE_ADDRESS, MEMORY_SIZE};
+use assembler::diagnostic::{Diagnostic, Severity};
+use assembler::memory_map::{MemoryMap, RegionUsage};
+use assembler::object::{ObjectFile, RelocationKind};
+use assembler::section;
+use assembler::target::Target;
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+/// Linking failed. Contains every problem found
+#[derive(Debug)]
+pub struct LinkError {
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl LinkError {
+    fn new(errors: &[(&'static str, String)]) -> Self {
+        let diagnostics = errors.iter().map(|&(code, ref message)| Diagnostic::new(Severity::Error, code, message, None)).collect();
+        LinkError{diagnostics}
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.diagnostics.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl error::Error for LinkError {}
+
+/// The program produced by linking object files
+#[derive(Debug)]
+pub struct LinkOutput {
+    pub bytes: Vec<u8>,
+    pub symbols: HashMap<String, u32>,
+    /// how full each region of the memory map is, empty without a memory map
+    pub regions: Vec<RegionUsage>
+}
+
+/// Places the sections of object files and fills in the addresses they use.
+/// Sections of the same name are placed together, one object after the other
+pub struct Linker {
+    objects: Vec<(String, ObjectFile)>,
+    /// sections placed at fixed addresses
+    section_addresses: HashMap<String, u32>,
+    memory_map: Option<MemoryMap>,
+    base: u32,
+    fill: u8,
+    memory_size: u32
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Linker::new()
+    }
+}
+
+impl Linker {
+    /// Linker for plain Chip8, placing code from `$200`
+    pub fn new() -> Self {
+        Linker {
+            objects: Vec::new(),
+            section_addresses: HashMap::new(),
+            memory_map: None,
+            base: DEFAULT_BASE_ADDRESS,
+            fill: 0,
+            memory_size: MEMORY_SIZE
+        }
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.memory_size = target.memory_size();
+        self
+    }
+
+    pub fn base_address(mut self, base: u32) -> Self {
+        self.base = base;
+        self
+    }
+
+    pub fn fill(mut self, fill: u8) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// Place a section at a fixed address instead of after the sections before it
+    pub fn section_address(mut self, section: &str, address: u32) -> Self {
+        self.section_addresses.insert(String::from(section), address);
+        self
+    }
+
+    /// Place sections in the regions of a memory map, which also replaces the memory of the target
+    pub fn memory_map(mut self, map: MemoryMap) -> Self {
+        self.memory_map = Some(map);
+        self
+    }
+
+    /// Add an object file. Objects are placed in the order they are added, `name` identifies it in errors
+    pub fn object(mut self, name: &str, object: ObjectFile) -> Self {
+        self.objects.push((String::from(name), object));
+        self
+    }
+
+    /// Place every section, resolve symbols and apply relocations
+    pub fn link(&self) -> Result<LinkOutput, LinkError> {
+        let mut errors = Vec::new();
+
+        // address of each section, by object and section index
+        let mut placements: Vec<Vec<u32>> = self.objects.iter().map(|(_, object)| vec![0; object.sections.len()]).collect();
+        let names = self.objects.iter().flat_map(|(_, object)| object.sections.iter().map(|section| section.name.as_ref()));
+        let mut ranges: Vec<(u32, u32, String)> = Vec::new();
+        let mut address = self.base;
+        let placement = match self.memory_map {
+            Some(ref map) => map.placement(names),
+            None => section::place(names, &self.section_addresses)
+        };
+        for (name, fixed) in placement.into_iter() {
+            if let Some(fixed) = fixed {
+                if fixed < self.base {
+                    errors.push((codes::SECTION_PLACEMENT, format!("Section {} is placed at ${:03X}, below the base address ${:03X}", name, fixed, self.base)));
+                    continue;
+                }
+                address = fixed;
+            }
+
+            let start = address;
+            for (i, (_, object)) in self.objects.iter().enumerate() {
+                for (j, section) in object.sections.iter().enumerate().filter(|&(_, section)| section.name == name) {
+                    placements[i][j] = address;
+                    address += section.bytes.len() as u32;
+                }
+            }
+            if address > start {
+                ranges.push((start, address, name));
+            }
+        }
+
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            if pair[0].1 > pair[1].0 {
+                errors.push((codes::SECTION_PLACEMENT, format!("Section {} at ${:03X} overlaps section {}, which ends at ${:03X}", pair[1].2, pair[1].0, pair[0].2, pair[0].1)));
+            }
+        }
+
+        // a memory map replaces the memory of the target, overflowing a region is overflowing memory
+        let end = ranges.iter().map(|&(_, end, _)| end).max().unwrap_or(self.base);
+        let regions = match self.memory_map {
+            Some(ref map) => map.usage(&ranges).unwrap_or_else(|overflows| {
+                errors.extend(overflows.into_iter().map(|overflow| (codes::REGION_OVERFLOW, overflow)));
+                vec![]
+            }),
+            None => {
+                if end > self.memory_size {
+                    errors.push((codes::OUTSIDE_MEMORY, format!("Program ends at ${:03X}, past the end of memory at ${:03X}", end, self.memory_size)));
+                }
+                vec![]
+            }
+        };
+        if !errors.is_empty() {
+            return Err(LinkError::new(&errors));
+        }
+
+        // where each symbol is and which object defined it
+        let mut symbols: HashMap<String, (u32, &str)> = HashMap::new();
+        for (i, (name, object)) in self.objects.iter().enumerate() {
+            for symbol in object.symbols.iter() {
+                let value = placements[i][symbol.section] + symbol.offset;
+                match symbols.get(&symbol.name) {
+                    Some(&(_, first)) => errors.push((codes::DUPLICATE_SYMBOL, format!("Duplicate symbol {}, defined in {} and {}", symbol.name, first, name))),
+                    None => {
+                        symbols.insert(symbol.name.clone(), (value, name));
+                    }
+                }
+            }
+        }
+
+        let mut bytes = vec![self.fill; (end - self.base) as usize];
+        for region in self.memory_map.iter().flat_map(|map| map.regions.iter()) {
+            if let Some(fill) = region.fill {
+                let (start, end) = (region.start.max(self.base).min(end), region.end().min(end));
+                for byte in bytes[(start - self.base) as usize..(end.max(start) - self.base) as usize].iter_mut() {
+                    *byte = fill;
+                }
+            }
+        }
+        let mut undefined: Vec<(&str, &str)> = Vec::new();
+        for (i, (name, object)) in self.objects.iter().enumerate() {
+            for (section, &start) in object.sections.iter().zip(placements[i].iter()) {
+                let offset = (start - self.base) as usize;
+                bytes[offset..offset + section.bytes.len()].copy_from_slice(&section.bytes);
+
+                for relocation in section.relocations.iter() {
+                    let value = match symbols.get(&relocation.symbol) {
+                        Some(&(value, _)) => value,
+                        None => {
+                            if !undefined.contains(&(&relocation.symbol, name)) {
+                                undefined.push((&relocation.symbol, name));
+                            }
+                            continue;
+                        }
+                    };
+
+                    let at = offset + relocation.offset as usize;
+                    if relocation.offset as usize + 1 >= section.bytes.len() {
+                        errors.push((codes::BAD_RELOCATION, format!("Relocation for {} in {} is outside its section", relocation.symbol, name)));
+                        continue;
+                    }
+                    match relocation.kind {
+                        RelocationKind::Address if value > 0xFFF => {
+                            errors.push((codes::BAD_RELOCATION, format!("Address of {}, ${:X}, does not fit in 12 bits", relocation.symbol, value)));
+                        },
+                        RelocationKind::Address => {
+                            bytes[at] = (bytes[at] & 0xF0) | (value >> 8) as u8;
+                            bytes[at + 1] = value as u8;
+                        },
+                        RelocationKind::Byte => bytes[at + 1] = value as u8
+                    }
+                }
+            }
+        }
+        errors.extend(undefined.iter().map(|&(symbol, name)| (codes::UNDEFINED_SYMBOL, format!("Undefined symbol {}, used in {}", symbol, name))));
+
+        if !errors.is_empty() {
+            return Err(LinkError::new(&errors));
+        }
+
+        Ok(LinkOutput {
+            bytes,
+            symbols: symbols.into_iter().map(|(symbol, (value, _))| (symbol, value)).collect(),
+            regions
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    fn object(source: &[u8]) -> ObjectFile {
+        Assembler::new().assemble_object("game.asm", source).unwrap().object
+    }
+
+    fn link_errors(linker: Linker) -> Vec<String> {
+        linker.link().unwrap_err().diagnostics.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_link() {
+        let output = Linker::new()
+            .object("main.o", object(b"start\tCALL #draw\n\tLD I, #sprite\nend\tJP #end\n"))
+            .object("draw.o", object(b"draw\tLD V0, #sprite\n\tRET\nsprite\n\tdb $F0\n"))
+            .link()
+            .unwrap();
+
+        let program = Assembler::new().assemble("game.asm", b"start\tCALL #draw\n\tLD I, #sprite\nend\tJP #end\n\
+                                                               draw\tLD V0, #sprite\n\tRET\nsprite\n\tdb $F0\n").unwrap();
+
+        assert_eq!(output.bytes, program.bytes);
+        assert_eq!(output.symbols, program.symbols);
+    }
+
+    #[test]
+    fn test_sections() {
+        let output = Linker::new()
+            .section_address("font", 0x300)
+            .object("main.o", object(b"start\tLD I, #digits\n\tsection data\nsprite\n\tdb $F0\n\tsection code\n\tLD I, #sprite\n"))
+            .object("font.o", object(b"\tsection font\ndigits\n\tdb $60\n\tsection data\nship\n\tdb $18\n"))
+            .link()
+            .unwrap();
+
+        assert_eq!(output.symbols["sprite"], 0x204);
+        assert_eq!(output.symbols["ship"], 0x205);
+        assert_eq!(output.symbols["digits"], 0x300);
+        assert_eq!(&output.bytes[..6], &[0xA3, 0x00, 0xA2, 0x04, 0xF0, 0x18]);
+        assert_eq!(output.bytes[0x100], 0x60);
+    }
+
+    #[test]
+    fn test_overlapping_sections() {
+        let linker = Linker::new()
+            .section_address("data", 0x202)
+            .object("a.o", object(b"start\tCLS\n\tJP #start\n\tsection data\n\tdb $F0\n"));
+
+        assert_eq!(link_errors(linker), vec!["error[E015]: Section data at $202 overlaps section code, which ends at $204"]);
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let map = MemoryMap::parse("region ram start=$200 size=$10 fill=$FF\nregion rom start=$210 size=$10\nsection data rom\n", Target::Chip8).unwrap();
+        let output = Linker::new()
+            .memory_map(map)
+            .object("a.o", object(b"start\tLD I, #sprite\n\tsection data\nsprite\n\tdb $F0\n"))
+            .link()
+            .unwrap();
+
+        assert_eq!(output.symbols["sprite"], 0x210);
+        assert_eq!(&output.bytes[..4], &[0xA2, 0x10, 0xFF, 0xFF]);
+        assert_eq!(output.bytes[0x10], 0xF0);
+        assert_eq!(output.regions[1].used, 1);
+    }
+
+    #[test]
+    fn test_region_overflow() {
+        let map = MemoryMap::parse("region ram start=$200 size=2\n", Target::Chip8).unwrap();
+        let linker = Linker::new().memory_map(map).object("a.o", object(b"start\tCLS\n\tRET\n\tdb $F0\n"));
+
+        assert_eq!(link_errors(linker), vec![
+            "error[E014]: Region ram overflows by 3 bytes, it ends at $202 but its sections end at $205"
+        ]);
+    }
+
+    #[test]
+    fn test_duplicate_symbol() {
+        let linker = Linker::new()
+            .object("a.o", object(b"end\tJP #end\n"))
+            .object("b.o", object(b"end\tJP #end\n"));
+
+        assert_eq!(link_errors(linker), vec!["error[E016]: Duplicate symbol end, defined in a.o and b.o"]);
+    }
+
+    #[test]
+    fn test_undefined_symbol() {
+        let linker = Linker::new().object("a.o", object(b"start\tCALL #draw\n\tJP #draw\n"));
+
+        assert_eq!(link_errors(linker), vec!["error[E017]: Undefined symbol draw, used in a.o"]);
+    }
+
+    #[test]
+    fn test_does_not_fit() {
+        let linker = Linker::new().base_address(0xFFE).object("a.o", object(b"start\tCLS\n\tRET\n"));
+
+        assert_eq!(link_errors(linker), vec!["error[E012]: Program ends at $1002, past the end of memory at $1000"]);
+    }
+}
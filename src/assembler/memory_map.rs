@@ -0,0 +1,299 @@
+use assembler::section::{Placement, SECTIONS};
+use assembler::target::Target;
+use options::parse_number;
+
+use std::error::Error;
+use std::fmt;
+
+/// Problem in a memory map, with the line it is on
+#[derive(Debug, PartialEq)]
+pub struct MemoryMapError {
+    line: usize,
+    message: String
+}
+
+impl MemoryMapError {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for MemoryMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+impl Error for MemoryMapError {}
+
+/// A range of memory sections can be placed in
+#[derive(Debug, PartialEq, Clone)]
+pub struct Region {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    /// value of the bytes in the region not written by the program, instead of the global fill
+    pub fill: Option<u8>
+}
+
+impl Region {
+    /// First address after the region
+    pub fn end(&self) -> u32 {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.start && address < self.end()
+    }
+}
+
+/// How much of a region the program uses
+#[derive(Debug, PartialEq, Clone)]
+pub struct RegionUsage {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    /// bytes from the start of the region to the end of the last section in it
+    pub used: u32
+}
+
+impl fmt::Display for RegionUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<8} ${:03X}-${:03X} {:>5} / {:>5} bytes ({}%)",
+               self.name, self.start, self.start + self.size - 1, self.used, self.size, self.used * 100 / self.size)
+    }
+}
+
+/// Regions of memory and the sections placed in each. Written one declaration per line,
+/// with `;` starting a comment:
+///
+/// ```text
+/// region ram start=$200 size=$600
+/// region rom start=$800 size=$800 fill=$FF
+/// section code ram
+/// section font rom
+/// ```
+///
+/// Sections of a region are placed one after the other from its start, in the order
+/// they are listed. Sections not listed go in the first region, after the listed ones.
+/// Every region has to fit in the memory of the target
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+    /// each section and the name of the region it is in
+    pub sections: Vec<(String, String)>
+}
+
+impl MemoryMap {
+    pub fn parse(source: &str, target: Target) -> Result<MemoryMap, MemoryMapError> {
+        let mut map = MemoryMap::default();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let error = |message: String| MemoryMapError{line: line_number, message};
+
+            let line = line.split(';').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.first() {
+                None => continue,
+                Some(&"region") if words.len() >= 2 => {
+                    let region = parse_region(words[1], &words[2..], target).map_err(&error)?;
+                    if map.region(&region.name).is_some() {
+                        return Err(error(format!("Region {} is declared twice", region.name)));
+                    }
+                    if let Some(other) = map.regions.iter().find(|other| other.start < region.end() && region.start < other.end()) {
+                        return Err(error(format!("Region {} overlaps region {}", region.name, other.name)));
+                    }
+                    map.regions.push(region);
+                },
+                Some(&"section") if words.len() == 3 => {
+                    let (section, region) = (words[1], words[2]);
+                    if !SECTIONS.contains(&section) {
+                        return Err(error(format!("Unknown section {}, expected {}", section, SECTIONS.join(", "))));
+                    }
+                    if map.region(region).is_none() {
+                        return Err(error(format!("Unknown region {}", region)));
+                    }
+                    if map.sections.iter().any(|(name, _)| name == section) {
+                        return Err(error(format!("Section {} is assigned twice", section)));
+                    }
+                    map.sections.push((String::from(section), String::from(region)));
+                },
+                Some(&"region") => return Err(error(String::from("Expected region NAME start=ADDRESS size=SIZE [fill=BYTE]"))),
+                Some(&"section") => return Err(error(String::from("Expected section NAME REGION"))),
+                Some(word) => return Err(error(format!("Unknown declaration {}, expected region or section", word)))
+            }
+        }
+
+        if map.regions.is_empty() {
+            return Err(MemoryMapError{line: 0, message: String::from("A memory map needs at least one region")});
+        }
+
+        Ok(map)
+    }
+
+    pub fn region(&self, name: &str) -> Option<&Region> {
+        self.regions.iter().find(|region| region.name == name)
+    }
+
+    /// Region a section is placed in
+    pub fn region_of(&self, section: &str) -> &Region {
+        self.sections.iter()
+            .find(|(name, _)| name == section)
+            .and_then(|(_, region)| self.region(region))
+            .unwrap_or(&self.regions[0])
+    }
+
+    /// First address after every region
+    pub fn end(&self) -> u32 {
+        self.regions.iter().map(|region| region.end()).max().unwrap_or(0)
+    }
+
+    /// Where the sections go: regions in address order, the first section of each at its start
+    pub fn placement<'a, I: Iterator<Item = &'a str>>(&self, names: I) -> Placement {
+        let names: Vec<&str> = names.collect();
+        let mut regions: Vec<&Region> = self.regions.iter().collect();
+        regions.sort_by_key(|region| region.start);
+
+        let mut placement = Vec::new();
+        for region in regions.into_iter() {
+            let mut in_region: Vec<&str> = self.sections.iter()
+                .filter(|(_, name)| *name == region.name)
+                .map(|(section, _)| section.as_ref())
+                .collect();
+            if region.name == self.regions[0].name {
+                in_region.extend(SECTIONS.iter().filter(|section| !self.sections.iter().any(|(name, _)| name == *section)));
+            }
+
+            for (i, section) in in_region.into_iter().filter(|section| names.contains(section)).enumerate() {
+                let address = if i == 0 { Some(region.start) } else { None };
+                placement.push((String::from(section), address));
+            }
+        }
+
+        placement
+    }
+
+    /// How much of each region is used by sections occupying `(start, end, name)`.
+    /// Fails with a message for each region that is overflowed
+    pub fn usage(&self, sections: &[(u32, u32, String)]) -> Result<Vec<RegionUsage>, Vec<String>> {
+        let mut usage = Vec::new();
+        let mut errors = Vec::new();
+
+        for region in self.regions.iter() {
+            let end = sections.iter()
+                .filter(|(_, _, name)| self.region_of(name).name == region.name)
+                .map(|&(_, end, _)| end)
+                .max()
+                .unwrap_or(region.start);
+
+            if end > region.end() {
+                errors.push(format!("Region {} overflows by {} bytes, it ends at ${:03X} but its sections end at ${:03X}",
+                                    region.name, end - region.end(), region.end(), end));
+            }
+            usage.push(RegionUsage {
+                name: region.name.clone(),
+                start: region.start,
+                size: region.size,
+                used: end.saturating_sub(region.start)
+            });
+        }
+
+        if errors.is_empty() { Ok(usage) } else { Err(errors) }
+    }
+}
+
+/// The name and `key=value` settings of a `region` declaration
+fn parse_region(name: &str, settings: &[&str], target: Target) -> Result<Region, String> {
+    let (mut start, mut size, mut fill) = (None, None, None);
+
+    for setting in settings.iter() {
+        let mut parts = setting.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().and_then(parse_number).ok_or_else(|| format!("Invalid setting {}, expected KEY=NUMBER", setting))?;
+        match key {
+            "start" => start = Some(value),
+            "size" => size = Some(value),
+            "fill" if value <= 0xFF => fill = Some(value as u8),
+            "fill" => return Err(format!("Fill of region {} does not fit in a byte", name)),
+            _ => return Err(format!("Unknown setting {}, expected start, size or fill", key))
+        }
+    }
+
+    match (start, size) {
+        (Some(_), Some(0)) => Err(format!("Region {} is empty", name)),
+        (Some(start), Some(size)) if start.checked_add(size).filter(|&end| end <= target.memory_size()).is_none() =>
+            Err(format!("Region {} ends past the end of memory at ${:03X}", name, target.memory_size())),
+        (Some(start), Some(size)) => Ok(Region{name: String::from(name), start, size, fill}),
+        _ => Err(format!("Region {} needs a start and a size", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARTRIDGE: &str = "; two ROMs\n\
+                             region ram start=$200 size=$600\n\
+                             region rom start=$800 size=$800 fill=$FF\n\
+                             section font rom\n\
+                             section data rom\n";
+
+    #[test]
+    fn test_parse() {
+        let map = MemoryMap::parse(CARTRIDGE, Target::Chip8).unwrap();
+
+        assert_eq!(map.regions[1], Region{name: String::from("rom"), start: 0x800, size: 0x800, fill: Some(0xFF)});
+        assert_eq!(map.region_of("font").name, "rom");
+        assert_eq!(map.region_of("code").name, "ram");
+        assert_eq!(map.end(), 0x1000);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |source: &str| MemoryMap::parse(source, Target::Chip8).unwrap_err().to_string();
+
+        assert_eq!(error("region ram start=$200\n"), "1: Region ram needs a start and a size");
+        assert_eq!(error("region a start=0 size=$100\nregion b start=$80 size=$100\n"), "2: Region b overlaps region a");
+        assert_eq!(error("region a start=0 size=$100\nsection code b\n"), "2: Unknown region b");
+        assert_eq!(error("region a start=0 size=$100\nsection bss a\n"), "2: Unknown section bss, expected code, data, font");
+        assert_eq!(error("; nothing\n"), "0: A memory map needs at least one region");
+    }
+
+    #[test]
+    fn test_region_past_end_of_memory() {
+        let error = |source: &str, target: Target| MemoryMap::parse(source, target).unwrap_err().to_string();
+
+        assert_eq!(error("region ram start=$FFFFFFF0 size=$100\n", Target::XoChip), "1: Region ram ends past the end of memory at $10000");
+        assert_eq!(error("region ram start=$200 size=$FFFFFFFF\n", Target::Chip8), "1: Region ram ends past the end of memory at $1000");
+        assert_eq!(error("region rom start=$F000 size=$100\n", Target::Chip8), "1: Region rom ends past the end of memory at $1000");
+        assert_eq!(MemoryMap::parse("region rom start=$F000 size=$1000\n", Target::XoChip).unwrap().end(), 0x10000);
+    }
+
+    #[test]
+    fn test_placement() {
+        let map = MemoryMap::parse(CARTRIDGE, Target::Chip8).unwrap();
+
+        assert_eq!(map.placement(vec!["code", "data", "font"].into_iter()), vec![
+            (String::from("code"), Some(0x200)),
+            (String::from("font"), Some(0x800)),
+            (String::from("data"), None)
+        ]);
+    }
+
+    #[test]
+    fn test_usage() {
+        let map = MemoryMap::parse(CARTRIDGE, Target::Chip8).unwrap();
+        let usage = map.usage(&[(0x200, 0x300, String::from("code")), (0x800, 0x850, String::from("font"))]).unwrap();
+
+        assert_eq!(usage[0].used, 0x100);
+        assert_eq!(usage[1].to_string(), "rom      $800-$FFF    80 /  2048 bytes (3%)");
+
+        let errors = map.usage(&[(0x200, 0x810, String::from("code"))]).unwrap_err();
+        assert_eq!(errors, vec!["Region ram overflows by 16 bytes, it ends at $800 but its sections end at $810"]);
+    }
+}
@@ -1,23 +1,901 @@
-mod lexer;
-mod parser;
-mod semantics;
-mod codegenerator;
+pub mod lexer;
+pub mod parser;
+pub mod semantics;
+pub mod codegenerator;
+pub mod codes;
+pub mod diagnostic;
+pub mod target;
+pub mod source;
+pub mod layout;
+pub mod optimizer;
+pub mod object;
+pub mod linker;
+pub mod section;
+pub mod memory_map;
+pub mod suggest;
 
-use self::codegenerator::CodeGenerator;
+use self::codegenerator::{CodeGenerator, CodeGenError, GeneratedCode};
+use self::diagnostic::{Diagnostic, Fix, Location, Related};
+use self::lexer::{Position, Token};
+use self::memory_map::RegionUsage;
+use self::object::{Relocation, Section, Symbol};
+use self::parser::Statement;
+use quirks::Quirks;
+pub use self::codegenerator::{ByteKind, ImageOptions, MEMORY_SIZE};
+pub use self::diagnostic::Severity;
+pub use self::linker::{Linker, LinkOutput};
+pub use self::memory_map::MemoryMap;
+pub use self::object::ObjectFile;
+pub use self::source::{SourceProvider, FileSystemProvider, MemoryProvider};
+pub use self::target::Target;
 
-use std::io::Error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io::{Error, ErrorKind};
 
 /// consume input data and assemble the code
 pub fn assemble(input_data: Vec<u8>) -> Result<Vec<u8>, Error> {
-    // TODO: better error handling
-    // transform input data into tokens
-    let tokens = lexer::tokenize(&input_data[..]).unwrap();
-    // transform tokens into expressions
-    let exprs = parser::parse(tokens).unwrap();
+    Assembler::new().assemble("<input>", &input_data[..])
+        .map(|output| output.bytes)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Bytes written by a single statement and where that statement is in the source
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceMapEntry {
+    pub address: u32,
+    pub size: u32,
+    pub location: Location,
+    pub kind: ByteKind,
+    /// closest label at or before the address
+    pub label: Option<String>
+}
+
+/// How much of the address space the program uses
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SizeStats {
+    /// bytes written by the program
+    pub used_bytes: usize,
+    /// size of the output image, including gaps and padding
+    pub image_bytes: usize,
+    /// bytes between the base address and the end of memory
+    pub available_bytes: usize,
+    pub lowest_address: Option<u32>,
+    pub highest_address: Option<u32>
+}
+
+/// Everything produced by assembling a program
+#[derive(Debug)]
+pub struct AssemblyOutput {
+    pub bytes: Vec<u8>,
+    pub symbols: HashMap<String, u32>,
+    pub source_map: Vec<SourceMapEntry>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub stats: SizeStats,
+    /// how full each region of the memory map is, empty without a memory map
+    pub regions: Vec<RegionUsage>
+}
+
+/// An object file and the warnings found while assembling it
+#[derive(Debug)]
+pub struct ObjectOutput {
+    pub object: ObjectFile,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+/// Assembly failed. Contains every problem found, warnings included
+#[derive(Debug)]
+pub struct AssemblyError {
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.diagnostics.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl error::Error for AssemblyError {}
+
+/// Code generated for a program along with what it was generated from
+struct Generated {
+    code: GeneratedCode,
+    /// files read, indexed by the file of a position
+    files: Vec<SourceFile>,
+    statements: Vec<Statement>,
+    regions: Vec<RegionUsage>,
+    /// warnings found
+    diagnostics: Vec<Diagnostic>
+}
+
+/// A file read while loading a program, kept to find the extent of statements in it
+struct SourceFile {
+    name: String,
+    source: Vec<u8>
+}
+
+/// Statements of the files an assembler has read, kept so that files whose
+/// contents have not changed are not lexed and parsed again
+#[derive(Default)]
+struct SourceCache {
+    /// contents and statements of each file by id. Statements are in file 0
+    files: HashMap<String, (Vec<u8>, Vec<Statement>)>,
+    /// files read by the last assembly, the main input first
+    read: Vec<String>,
+    /// how many of them were lexed and parsed
+    parsed: usize
+}
+
+/// Configures and runs the assembler
+pub struct Assembler {
+    target: Target,
+    defines: HashMap<String, u32>,
+    provider: Box<dyn SourceProvider>,
+    image: ImageOptions,
+    quirks: Option<Quirks>,
+    /// sections placed at fixed addresses
+    section_addresses: HashMap<String, u32>,
+    memory_map: Option<MemoryMap>,
+    optimize: bool,
+    warnings_as_errors: bool,
+    cache: RefCell<SourceCache>
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new()
+    }
+}
+
+impl Assembler {
+    /// Assembler for plain Chip8 reading files from disk
+    pub fn new() -> Self {
+        Assembler {
+            target: Target::default(),
+            defines: HashMap::new(),
+            provider: Box::new(FileSystemProvider::new()),
+            image: ImageOptions::default(),
+            quirks: None,
+            section_addresses: HashMap::new(),
+            memory_map: None,
+            optimize: false,
+            warnings_as_errors: false,
+            cache: RefCell::new(SourceCache::default())
+        }
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Define a constant that can be used as a label operand
+    pub fn define(mut self, name: &str, value: u32) -> Self {
+        self.defines.insert(String::from(name), value);
+        self
+    }
+
+    /// Where source files are read from
+    pub fn source_provider<P: SourceProvider + 'static>(mut self, provider: P) -> Self {
+        self.provider = Box::new(provider);
+        self
+    }
+
+    pub fn base_address(mut self, base: u32) -> Self {
+        self.image.base = base;
+        self
+    }
+
+    pub fn fill(mut self, fill: u8) -> Self {
+        self.image.fill = fill;
+        self
+    }
+
+    pub fn full_image(mut self, full_image: bool) -> Self {
+        self.image.full_image = full_image;
+        self
+    }
+
+    /// How to report two statements writing the same address
+    pub fn overlap(mut self, severity: Severity) -> Self {
+        self.image.overlap = severity;
+        self
+    }
+
+    /// Place a section at a fixed address instead of after the sections before it
+    pub fn section_address(mut self, section: &str, address: u32) -> Self {
+        self.section_addresses.insert(String::from(section), address);
+        self
+    }
+
+    /// Place sections in the regions of a memory map, which also replaces the memory of the target
+    pub fn memory_map(mut self, map: MemoryMap) -> Self {
+        self.memory_map = Some(map);
+        self
+    }
+
+    /// Warn about code that depends on these interpreter quirks
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// Run the peephole optimiser before generating code
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    pub fn warnings_as_errors(mut self, warnings_as_errors: bool) -> Self {
+        self.warnings_as_errors = warnings_as_errors;
+        self
+    }
+
+    /// Assemble the program in the file `name`, read through the source provider
+    pub fn assemble_file(&self, name: &str) -> Result<AssemblyOutput, AssemblyError> {
+        let (id, source) = self.read_file(name)?;
+        self.assemble(&id, &source[..])
+    }
+
+    /// Assemble the file `name` into an object file, read through the source provider
+    pub fn assemble_object_file(&self, name: &str) -> Result<ObjectOutput, AssemblyError> {
+        let (id, source) = self.read_file(name)?;
+        self.assemble_object(&id, &source[..])
+    }
+
+    /// Assemble a program. `name` identifies the source in diagnostics and is
+    /// what files it includes are resolved against
+    pub fn assemble(&self, name: &str, source: &[u8]) -> Result<AssemblyOutput, AssemblyError> {
+        let mut image = self.image;
+        image.memory_size = match self.memory_map {
+            Some(ref map) => map.end(),
+            None => self.target.memory_size()
+        };
+
+        let Generated{code, files, regions, diagnostics, ..} = self.generate(name, source, image, false)?;
+
+        let mut labels: Vec<(&String, u32)> = code.labels.iter().map(|(label, &address)| (label, address)).collect();
+        labels.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+
+        let source_map: Vec<SourceMapEntry> = code.source_map.iter()
+            .filter_map(|range| {
+                let label = labels.iter().rev().find(|&&(_, address)| address <= range.address).map(|&(label, _)| label.clone());
+                location(&files, range.position).map(|location| SourceMapEntry {
+                    address: range.address,
+                    size: range.size,
+                    location,
+                    kind: range.kind,
+                    label
+                })
+            })
+            .collect();
+
+        let stats = SizeStats {
+            used_bytes: code.source_map.iter().map(|range| range.size as usize).sum(),
+            image_bytes: code.opcodes.len(),
+            available_bytes: image.memory_size.saturating_sub(image.base) as usize,
+            lowest_address: code.source_map.first().map(|range| range.address),
+            highest_address: code.source_map.last().map(|range| range.address + range.size - 1)
+        };
+
+        Ok(AssemblyOutput {
+            bytes: code.opcodes,
+            symbols: code.labels,
+            source_map,
+            diagnostics,
+            stats,
+            regions
+        })
+    }
+
+    /// Assemble a program into an object file for the linker, with one relocatable
+    /// section for each section of the program. It may use labels defined by other files
+    pub fn assemble_object(&self, name: &str, source: &[u8]) -> Result<ObjectOutput, AssemblyError> {
+        let image = ImageOptions {
+            base: 0,
+            full_image: false,
+            fill: 0,
+            memory_size: self.target.memory_size(),
+            ..self.image
+        };
+
+        let Generated{code, statements, diagnostics, ..} = self.generate(name, source, image, true)?;
+
+        // sections are generated one after the other, each starts at the first statement in it
+        let layout = layout::layout(&statements, 0);
+        let mut starts: Vec<(String, u32)> = Vec::new();
+        let mut symbols = Vec::new();
+        let mut current = String::from("code");
+        for (statement, &address) in statements.iter().zip(layout.addresses.iter()) {
+            if let Some(name) = section::section_name(statement) {
+                current = String::from(name);
+            }
+            if starts.last().map(|(name, _)| *name != current).unwrap_or(true) {
+                starts.push((current.clone(), address));
+            }
+            if let Token::Label(ref label) = statement.expr[0] {
+                let start = starts[starts.len() - 1].1;
+                symbols.push(Symbol{name: label.clone(), section: starts.len() - 1, offset: address - start});
+            }
+        }
+
+        let mut imports: Vec<String> = code.relocations.iter()
+            .filter(|relocation| !code.labels.contains_key(&relocation.symbol))
+            .map(|relocation| relocation.symbol.clone())
+            .collect();
+        imports.sort();
+        imports.dedup();
+
+        let mut relocations = code.relocations;
+        relocations.sort_by_key(|relocation| relocation.offset);
+
+        let opcodes = code.opcodes;
+        let sections = starts.iter().enumerate().map(|(i, &(ref name, start))| {
+            let end = starts.get(i + 1).map(|&(_, end)| end).unwrap_or(opcodes.len() as u32);
+            Section {
+                name: name.clone(),
+                bytes: opcodes[start as usize..end as usize].to_vec(),
+                relocations: relocations.iter()
+                    .filter(|relocation| relocation.offset >= start && relocation.offset < end)
+                    .map(|relocation| Relocation{offset: relocation.offset - start, ..relocation.clone()})
+                    .collect()
+            }
+        }).collect();
+
+        Ok(ObjectOutput {
+            object: ObjectFile{sections, symbols, imports},
+            diagnostics
+        })
+    }
+
+    /// Files read by the last assembly: the main input, then the files it includes
+    /// and binary files. Empty if the main input could not be read
+    pub fn dependencies(&self) -> Vec<String> {
+        self.cache.borrow().read.clone()
+    }
+
+    /// Contents of a source file as this assembler last read it, by its id. Binary
+    /// files added with `incbin` are not kept
+    pub fn source(&self, id: &str) -> Option<Vec<u8>> {
+        self.cache.borrow().files.get(id).map(|(source, _)| source.clone())
+    }
+
+    /// How many files the last assembly lexed and parsed. The others had not
+    /// changed since an earlier assembly by this assembler
+    pub fn files_parsed(&self) -> usize {
+        self.cache.borrow().parsed
+    }
+
+    /// Read a file through the source provider, giving its id and contents
+    fn read_file(&self, name: &str) -> Result<(String, Vec<u8>), AssemblyError> {
+        self.provider.resolve(name, None)
+            .and_then(|id| self.provider.read(&id).map(|source| (id, source)))
+            .map_err(|e| {
+                let message = format!("Could not read {}: {}", name, e);
+                AssemblyError{diagnostics: vec![Diagnostic::new(Severity::Error, codes::UNREADABLE_FILE, &message, None)]}
+            })
+    }
+
+    /// Load, check and generate code for a program
+    fn generate(&self, name: &str, source: &[u8], image: ImageOptions, relocatable: bool) -> Result<Generated, AssemblyError> {
+        let mut files = Vec::new();
+        let mut diagnostics = Vec::new();
+
+        {
+            let mut cache = self.cache.borrow_mut();
+            cache.read.clear();
+            cache.parsed = 0;
+        }
+        let statements = self.load(name, source, &mut files, &mut vec![], &mut diagnostics);
+        if diagnostics.iter().any(|d| d.is_error()) {
+            return Err(AssemblyError{diagnostics});
+        }
+
+        // the linker places the sections of object files
+        let sections = section::split(statements);
+        let names = sections.iter().map(|(name, _)| name.as_ref());
+        let memory_map = if relocatable { None } else { self.memory_map.as_ref() };
+        let placement = if relocatable {
+            section::place(names, &HashMap::new())
+        }
+        else if let Some(map) = memory_map {
+            map.placement(names)
+        }
+        else {
+            section::place(names, &self.section_addresses)
+        };
+        let statements = section::arrange(sections, &placement);
+
+        // statements that do not pass the checks are left for the code generator to report
+        let statements = if self.optimize && statements.iter().all(|statement| semantics::check(&statement.expr).is_ok()) {
+            optimizer::optimize(statements, image.base)
+        }
+        else {
+            statements
+        };
+
+        let regions = match memory_map {
+            Some(map) => match map.usage(&section::ranges(&statements, image.base)) {
+                Ok(regions) => regions,
+                Err(errors) => {
+                    diagnostics.extend(errors.iter().map(|message| Diagnostic::new(Severity::Error, codes::REGION_OVERFLOW, message, None)));
+                    return Err(AssemblyError{diagnostics});
+                }
+            },
+            None => vec![]
+        };
+
+        let mut codegen = CodeGenerator::with_image_options(image);
+        if let Some(map) = memory_map {
+            codegen.set_regions(map.regions.clone());
+        }
+        for (name, value) in self.defines.iter() {
+            codegen.define(name, *value);
+        }
+        if let Some(quirks) = self.quirks {
+            codegen.set_quirks(quirks);
+        }
+        if relocatable {
+            codegen.set_relocatable();
+        }
+
+        let code = match codegen.generate_statements(statements.clone()) {
+            Ok(code) => code,
+            Err(errors) => {
+                diagnostics.extend(errors.iter().map(|e| to_diagnostic(&files, e)));
+                return Err(AssemblyError{diagnostics});
+            }
+        };
+
+        diagnostics.extend(code.warnings.iter().map(|e| to_diagnostic(&files, e)));
+        if self.warnings_as_errors {
+            for diagnostic in diagnostics.iter_mut() {
+                diagnostic.severity = Severity::Error;
+            }
+            if !diagnostics.is_empty() {
+                return Err(AssemblyError{diagnostics});
+            }
+        }
+
+        Ok(Generated {
+            code,
+            files,
+            statements,
+            regions,
+            diagnostics
+        })
+    }
+
+    /// Turn a file into statements, expanding includes and binary files in place.
+    /// `stack` holds the files currently being included to catch recursion
+    fn load(&self, name: &str, source: &[u8], files: &mut Vec<SourceFile>, stack: &mut Vec<String>, diagnostics: &mut Vec<Diagnostic>) -> Vec<Statement> {
+        let file = files.len();
+        files.push(SourceFile{name: String::from(name), source: source.to_vec()});
+
+        self.read(name);
+        let statements = match self.parse(name, source) {
+            Ok(statements) => statements,
+            Err((position, code, message, fixes)) => {
+                let position = Position{file, ..position};
+                diagnostics.push(Diagnostic::new(Severity::Error, code, &message, location(files, position)).with_fixes(fixes));
+                return vec![];
+            }
+        };
+        let statements = statements.into_iter()
+            .map(|statement| Statement{position: Position{file, ..statement.position}, ..statement});
+
+        stack.push(String::from(name));
+
+        let mut ret = Vec::new();
+        for statement in statements {
+            let (directive, path) = match (statement.expr.first(), statement.expr.get(1)) {
+                (Some(Token::Directive(directive)), Some(Token::StringLiteral(path))) => (directive.clone(), path.clone()),
+                _ => {
+                    ret.push(statement);
+                    continue;
+                }
+            };
+
+            let id = self.provider.resolve(&path, Some(name));
+            if let Ok(ref id) = id {
+                if directive == "include" && stack.contains(id) {
+                    let message = format!("{} includes itself", id);
+                    diagnostics.push(Diagnostic::new(Severity::Error, codes::RECURSIVE_INCLUDE, &message, location(files, statement.position)));
+                    continue;
+                }
+            }
+
+            let contents = id.and_then(|id| self.provider.read(&id).map(|contents| (id, contents)));
+            let (id, contents) = match contents {
+                Ok(contents) => contents,
+                Err(e) => {
+                    let message = format!("Could not {} {}: {}", directive, path, e);
+                    diagnostics.push(Diagnostic::new(Severity::Error, codes::UNREADABLE_FILE, &message, location(files, statement.position)));
+                    continue;
+                }
+            };
+
+            if directive == "incbin" {
+                self.read(&id);
+                // binary files become data in place of the directive
+                let mut expr = vec![Token::Directive(String::from("db"))];
+                expr.extend(contents.iter().map(|&b| Token::NumericLiteral(b as u32)));
+                ret.push(Statement{position: statement.position, expr});
+            }
+            else {
+                ret.extend(self.load(&id, &contents[..], files, stack, diagnostics));
+            }
+        }
+
+        stack.pop();
+
+        ret
+    }
+
+    /// Note that the current assembly read a file
+    fn read(&self, id: &str) {
+        let read = &mut self.cache.borrow_mut().read;
+        if !read.iter().any(|file| file == id) {
+            read.push(String::from(id));
+        }
+    }
+
+    /// Lex and parse a file, or take its statements from the cache if its contents
+    /// are the same as when it was last parsed. Positions are in file 0
+    fn parse(&self, name: &str, source: &[u8]) -> Result<Vec<Statement>, (Position, &'static str, String, Vec<Fix>)> {
+        if let Some((cached, statements)) = self.cache.borrow().files.get(name) {
+            if &cached[..] == source {
+                return Ok(statements.clone());
+            }
+        }
+
+        self.cache.borrow_mut().parsed += 1;
+        let tokens = lexer::tokenize_with_positions(source)
+            .map_err(|e| (e.position(), e.code(), String::from(e.message()), e.fixes().to_vec()))?;
+        let statements = parser::parse_statements(tokens)
+            .map_err(|e| (e.position(), e.code(), String::from(e.message()), vec![]))?;
+
+        self.cache.borrow_mut().files.insert(String::from(name), (source.to_vec(), statements.clone()));
+        Ok(statements)
+    }
+}
+
+/// Where a position is, if it is known. The location covers the label starting at
+/// the position, or for anything else the rest of the line up to a comment
+fn location(files: &[SourceFile], position: Position) -> Option<Location> {
+    if position.line == 0 {
+        return None;
+    }
+
+    files.get(position.file).map(|file| {
+        let source = String::from_utf8_lossy(&file.source);
+        let line = source.lines().nth(position.line - 1).unwrap_or("");
+        let rest = line.get(position.column - 1..).unwrap_or("");
+        let length = if position.column == 1 {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        }
+        else {
+            rest.split(';').next().unwrap_or("").trim_end().len()
+        };
+
+        Location {
+            file: file.name.clone(),
+            line: position.line,
+            column: position.column,
+            end_column: position.column + length
+        }
+    })
+}
+
+fn to_diagnostic(files: &[SourceFile], error: &CodeGenError) -> Diagnostic {
+    let related = error.related().iter()
+        .filter_map(|(message, position)| location(files, *position).map(|location| Related::new(message, location)))
+        .collect();
+
+    Diagnostic::new(error.severity(), error.code(), error.message(), location(files, error.position()))
+        .with_fixes(error.fixes().to_vec())
+        .with_related(related)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::diagnostic::Fix;
+    use assembler::object::RelocationKind;
+
+    fn provider(files: &[(&str, &str)]) -> MemoryProvider {
+        let mut provider = MemoryProvider::new();
+        for (name, source) in files.iter() {
+            provider.insert(name, source.as_bytes());
+        }
+
+        provider
+    }
+
+    #[test]
+    fn test_assemble() {
+        let output = Assembler::new().assemble("main.asm", b"start\tCLS\n\tJP #start\n").unwrap();
+
+        assert_eq!(output.bytes, vec![0x00, 0xE0, 0x12, 0x00]);
+        assert_eq!(output.symbols["start"], 0x200);
+        assert!(output.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let output = Assembler::new().assemble("main.asm", b"\tCLS\n\torg $210\n\tdb $01 $02\n").unwrap();
+
+        assert_eq!(output.stats, SizeStats {
+            used_bytes: 4,
+            image_bytes: 0x12,
+            available_bytes: 0xE00,
+            lowest_address: Some(0x200),
+            highest_address: Some(0x211)
+        });
+    }
+
+    #[test]
+    fn test_source_map() {
+        let output = Assembler::new().assemble("main.asm", b"\tCLS\nloop\tJP #loop\n").unwrap();
+
+        assert_eq!(output.source_map, vec![
+            SourceMapEntry{address: 0x200, size: 2, location: Location{file: String::from("main.asm"), line: 1, column: 2, end_column: 5}, kind: ByteKind::Code, label: None},
+            SourceMapEntry{address: 0x202, size: 2, location: Location{file: String::from("main.asm"), line: 2, column: 6, end_column: 14}, kind: ByteKind::Code, label: Some(String::from("loop"))}
+        ]);
+    }
+
+    #[test]
+    fn test_source_map_enclosing_label() {
+        let output = Assembler::new().assemble("main.asm", b"start\n\tCLS\n\tRET\nsprite\n\tdb $F0 $90\n").unwrap();
+
+        let labels: Vec<Option<&str>> = output.source_map.iter().map(|entry| entry.label.as_ref().map(|l| l.as_ref())).collect();
+        let kinds: Vec<ByteKind> = output.source_map.iter().map(|entry| entry.kind).collect();
+
+        assert_eq!(labels, vec![Some("start"), Some("start"), Some("sprite")]);
+        assert_eq!(kinds, vec![ByteKind::Code, ByteKind::Code, ByteKind::Data]);
+    }
+
+    #[test]
+    fn test_defines() {
+        let output = Assembler::new().define("LIVES", 3).assemble("main.asm", b"\tLD V0, #LIVES\n").unwrap();
+
+        assert_eq!(output.bytes, vec![0x60, 0x03]);
+    }
+
+    #[test]
+    fn test_include() {
+        let assembler = Assembler::new().source_provider(provider(&[("sprites.asm", "sprite\n\tdb $F0 $90\n")]));
+        let output = assembler.assemble("main.asm", b"\tLD I, #sprite\n\tinclude \"sprites.asm\"\n").unwrap();
+
+        assert_eq!(output.bytes, vec![0xA2, 0x02, 0xF0, 0x90]);
+        assert_eq!(output.source_map[1].location.file, "sprites.asm");
+    }
+
+    #[test]
+    fn test_include_missing() {
+        let assembler = Assembler::new().source_provider(provider(&[]));
+        let error = assembler.assemble("main.asm", b"\tinclude \"missing.asm\"\n").unwrap_err();
+
+        assert_eq!(error.diagnostics.len(), 1);
+        assert_eq!(error.diagnostics[0].location, Some(Location{file: String::from("main.asm"), line: 1, column: 2, end_column: 23}));
+    }
+
+    #[test]
+    fn test_include_recursive() {
+        let assembler = Assembler::new().source_provider(provider(&[("a.asm", "\tinclude \"main.asm\"\n")]));
+        let error = assembler.assemble("main.asm", b"\tinclude \"a.asm\"\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "a.asm:1:2: error[E008]: main.asm includes itself");
+    }
+
+    #[test]
+    fn test_codegen_errors_located_in_included_file() {
+        let assembler = Assembler::new().source_provider(provider(&[("a.asm", "\n\tJP #nowhere\n")]));
+        let error = assembler.assemble("main.asm", b"\tCLS\n\tinclude \"a.asm\"\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "a.asm:2:2: error[E009]: Undefined label: nowhere");
+    }
+
+    #[test]
+    fn test_overlap_cites_earlier_file() {
+        let assembler = Assembler::new().source_provider(provider(&[("a.asm", "\tCLS\n")]));
+        let error = assembler.assemble("main.asm", b"\tinclude \"a.asm\"\n\torg $200\n\tRET\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "main.asm:3:2: error[E013]: Write to $200 overlaps earlier code\n\
+                                       a.asm:1:2: note: Earlier code writing $200");
+    }
+
+    #[test]
+    fn test_undefined_label_suggestion() {
+        let error = Assembler::new().assemble("main.asm", b"start\tCLS\n\tJP #strat\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "main.asm:2:2: error[E009]: Undefined label: strat, did you mean start?");
+        assert_eq!(error.diagnostics[0].fixes, vec![Fix::new("Use #start", "JP #start")]);
+    }
+
+    #[test]
+    fn test_quirk_warnings() {
+        let source = b"\tSHR V0, V1\n";
+
+        let output = Assembler::new().quirks(Quirks::schip()).assemble("main.asm", source).unwrap();
+        assert_eq!(output.diagnostics[0].to_string(), "main.asm:1:2: warning[W001]: SHR V0, V1 shifts V0 in place with the selected quirks, V1 is ignored");
+
+        let output = Assembler::new().quirks(Quirks::vip()).assemble("main.asm", source).unwrap();
+        assert!(output.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_diagnostic_codes_and_spans() {
+        let error = Assembler::new().assemble("main.asm", b"start\tJP #nowhere ; skip\nstart\n").unwrap_err();
+
+        let codes: Vec<&str> = error.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![codes::UNDEFINED_LABEL, codes::DUPLICATE_LABEL]);
+        assert_eq!(error.diagnostics[0].location, Some(Location{file: String::from("main.asm"), line: 1, column: 7, end_column: 18}));
+        assert_eq!(error.diagnostics[1].location, Some(Location{file: String::from("main.asm"), line: 2, column: 1, end_column: 6}));
+
+        let output = Assembler::new().quirks(Quirks::schip()).assemble("main.asm", b"\tSHR V0, V1\n").unwrap();
+        assert_eq!(output.diagnostics[0].code, codes::QUIRK_DEPENDENT);
+        assert_eq!(output.diagnostics[0].fixes, vec![Fix::new("Shift V0 in place", "SHR V0, V0")]);
+    }
+
+    #[test]
+    fn test_warnings_as_errors() {
+        let source = b"\tCLS\n\torg $200\n\tRET\n";
+
+        let output = Assembler::new().overlap(Severity::Warning).assemble("main.asm", source).unwrap();
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].severity, Severity::Warning);
+
+        let error = Assembler::new().overlap(Severity::Warning).warnings_as_errors(true).assemble("main.asm", source).unwrap_err();
+        assert_eq!(error.diagnostics[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn test_target_memory_size() {
+        let source = b"\torg $1000\n\tCLS\n";
+
+        assert!(Assembler::new().assemble("main.asm", source).is_err());
+        assert!(Assembler::new().target(Target::XoChip).assemble("main.asm", source).is_ok());
+    }
+
+    #[test]
+    fn test_address_operand_past_12_bits() {
+        let source = b"\tJP #far\n\tLD I, $1234\n\torg $1000\nfar\tCLS\n";
+        let error = Assembler::new().target(Target::XoChip).assemble("main.asm", source).unwrap_err();
+
+        assert_eq!(error.diagnostics.len(), 2);
+        assert_eq!(error.diagnostics[0].to_string(), "main.asm:1:2: error[E005]: Address of far, $1000, does not fit in 12 bits");
+        assert_eq!(error.diagnostics[1].to_string(), "main.asm:2:2: error[E005]: Address $1234 does not fit in 12 bits");
+    }
+
+    #[test]
+    fn test_assemble_file() {
+        let assembler = Assembler::new().source_provider(provider(&[("main.asm", "\tCLS\n")]));
+        let output = assembler.assemble_file("main.asm").unwrap();
+
+        assert_eq!(output.bytes, vec![0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_assemble_file_missing() {
+        let assembler = Assembler::new().source_provider(provider(&[]));
+        let error = assembler.assemble_file("main.asm").unwrap_err();
+
+        assert_eq!(error.to_string(), "error[E007]: Could not read main.asm: file not found");
+    }
+
+    #[test]
+    fn test_incbin() {
+        let provider = MemoryProvider::new()
+            .with_file("main.asm", b"\tCLS\ntitle\n\tincbin \"title.bin\"\n")
+            .with_file("title.bin", &[0xDE, 0xAD]);
+        let output = Assembler::new().source_provider(provider).assemble_file("main.asm").unwrap();
+
+        assert_eq!(output.bytes, vec![0x00, 0xE0, 0xDE, 0xAD]);
+        assert_eq!(output.symbols["title"], 0x202);
+        assert_eq!(output.source_map[1].location, Location{file: String::from("main.asm"), line: 3, column: 2, end_column: 20});
+    }
+
+    #[test]
+    fn test_unchanged_files_are_not_parsed_again() {
+        let provider = MemoryProvider::new()
+            .with_file("lib.asm", b"draw\tLD V0, 1\n\tRET\n")
+            .with_file("title.bin", &[0xDE, 0xAD]);
+        let assembler = Assembler::new().source_provider(provider);
+
+        assembler.assemble("main.asm", b"\tinclude \"lib.asm\"\n\tincbin \"title.bin\"\n").unwrap();
+        assert_eq!(assembler.dependencies(), vec!["main.asm", "lib.asm", "title.bin"]);
+        assert_eq!(assembler.files_parsed(), 2);
+
+        let output = assembler.assemble("main.asm", b"\tCLS\n\tinclude \"lib.asm\"\n").unwrap();
+        assert_eq!(assembler.dependencies(), vec!["main.asm", "lib.asm"]);
+        assert_eq!(assembler.files_parsed(), 1);
+        assert_eq!(output.bytes, vec![0x00, 0xE0, 0x60, 0x01, 0x00, 0xEE]);
+        assert_eq!(output.source_map[1].location.file, "lib.asm");
+
+        let error = assembler.assemble("main.asm", b"\tCLS\n\tJP #missing\n").unwrap_err();
+        assert_eq!(error.to_string(), "main.asm:2:2: error[E009]: Undefined label: missing");
+        assert_eq!(assembler.files_parsed(), 1);
+    }
+
+    #[test]
+    fn test_source() {
+        let provider = MemoryProvider::new()
+            .with_file("lib.asm", b"draw\tRET\n")
+            .with_file("title.bin", &[0xDE, 0xAD]);
+        let assembler = Assembler::new().source_provider(provider);
+
+        assembler.assemble("main.asm", b"\tinclude \"lib.asm\"\n\tincbin \"title.bin\"\n").unwrap();
+        assert_eq!(assembler.source("lib.asm"), Some(b"draw\tRET\n".to_vec()));
+        assert_eq!(assembler.source("main.asm"), Some(b"\tinclude \"lib.asm\"\n\tincbin \"title.bin\"\n".to_vec()));
+        assert_eq!(assembler.source("title.bin"), None);
+    }
+
+    #[test]
+    fn test_assemble_object() {
+        let output = Assembler::new().assemble_object("main.asm", b"start\tCALL #draw\nend\tJP #end\n").unwrap();
+        let object = output.object;
+
+        assert_eq!(object.sections[0].bytes, vec![0x20, 0x00, 0x10, 0x02]);
+        assert_eq!(object.sections[0].relocations, vec![
+            Relocation{offset: 0, kind: RelocationKind::Address, symbol: String::from("draw")},
+            Relocation{offset: 2, kind: RelocationKind::Address, symbol: String::from("end")}
+        ]);
+        assert_eq!(object.symbols, vec![
+            Symbol{name: String::from("start"), section: 0, offset: 0},
+            Symbol{name: String::from("end"), section: 0, offset: 2}
+        ]);
+        assert_eq!(object.imports, vec![String::from("draw")]);
+    }
+
+    #[test]
+    fn test_object_with_org() {
+        let error = Assembler::new().assemble_object("main.asm", b"\torg $300\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "main.asm:1:2: error[E011]: org can not be used in an object file, the linker places the code");
+    }
+
+    #[test]
+    fn test_sections() {
+        let source = b"start\tLD I, #ship\n\tsection data\nship\n\tdb $18\n\tsection code\nend\tJP #end\n";
+
+        let output = Assembler::new().assemble("main.asm", source).unwrap();
+        assert_eq!(output.bytes, vec![0xA2, 0x04, 0x12, 0x02, 0x18]);
+        assert_eq!(output.source_map[2].location.line, 4);
+
+        let output = Assembler::new().section_address("data", 0x208).assemble("main.asm", source).unwrap();
+        assert_eq!(output.symbols["ship"], 0x208);
+        assert_eq!(&output.bytes[4..], &[0x00, 0x00, 0x00, 0x00, 0x18]);
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let source = b"start\tLD I, #ship\nend\tJP #end\n\tsection data\nship\n\tdb $18\n";
+        let map = MemoryMap::parse("region ram start=$200 size=8 fill=$FF\nregion rom start=$208 size=8\nsection data rom\n", Target::Chip8).unwrap();
+
+        let output = Assembler::new().memory_map(map).full_image(true).assemble("main.asm", source).unwrap();
+        assert_eq!(output.symbols["ship"], 0x208);
+        assert_eq!(output.bytes, vec![0xA2, 0x08, 0x12, 0x02, 0xFF, 0xFF, 0xFF, 0xFF, 0x18, 0, 0, 0, 0, 0, 0, 0]);
+        let used: Vec<(&str, u32)> = output.regions.iter().map(|region| (region.name.as_ref(), region.used)).collect();
+        assert_eq!(used, vec![("ram", 4), ("rom", 1)]);
+
+        let map = MemoryMap::parse("region ram start=$200 size=2\n", Target::Chip8).unwrap();
+        let errors = Assembler::new().memory_map(map).assemble("main.asm", source).unwrap_err();
+        assert_eq!(errors.to_string(), "error[E014]: Region ram overflows by 3 bytes, it ends at $202 but its sections end at $205");
+    }
 
-    // generate opcodes from the expressions
-    let codegen = CodeGenerator::new();
-    let opcodes = codegen.generate(exprs);
+    #[test]
+    fn test_object_sections() {
+        let object = Assembler::new().assemble_object("main.asm", b"\tsection data\nship\n\tdb $18\n\tsection code\nstart\tLD I, #ship\n").unwrap().object;
 
-    Ok(opcodes)
+        let sections: Vec<(&str, usize)> = object.sections.iter().map(|section| (section.name.as_ref(), section.bytes.len())).collect();
+        assert_eq!(sections, vec![("code", 2), ("data", 1)]);
+        assert_eq!(object.symbols, vec![
+            Symbol{name: String::from("start"), section: 0, offset: 0},
+            Symbol{name: String::from("ship"), section: 1, offset: 0}
+        ]);
+    }
 }
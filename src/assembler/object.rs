@@ -0,0 +1,232 @@
+use std::error::Error;
+use std::fmt;
+
+/// First bytes of every object file
+const MAGIC: &[u8] = b"SILO";
+/// Version of the layout below, bumped when it changes
+const VERSION: u8 = 1;
+
+/// Problem reading an object file
+#[derive(Debug, PartialEq)]
+pub struct ObjectError {
+    message: String
+}
+
+impl ObjectError {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ObjectError {}
+
+/// Which bits of an instruction a relocation fills in
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RelocationKind {
+    /// the 12-bit address of JP, CALL, JR and LD I
+    Address,
+    /// the low byte of the address, as loaded by LD Vx
+    Byte
+}
+
+/// An instruction operand to fill in with the address of a symbol once it is known
+#[derive(Debug, PartialEq, Clone)]
+pub struct Relocation {
+    /// offset of the instruction in its section
+    pub offset: u32,
+    pub kind: RelocationKind,
+    pub symbol: String
+}
+
+/// Bytes that are placed in memory as a whole by the linker
+#[derive(Debug, PartialEq, Clone)]
+pub struct Section {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    pub relocations: Vec<Relocation>
+}
+
+/// A label defined by an object file
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    /// index of the section the label is in
+    pub section: usize,
+    pub offset: u32
+}
+
+/// Code assembled without knowing where it will end up
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ObjectFile {
+    pub sections: Vec<Section>,
+    /// every label of the file, all of them can be used by other files
+    pub symbols: Vec<Symbol>,
+    /// symbols used but not defined by the file
+    pub imports: Vec<String>
+}
+
+impl ObjectFile {
+    /// Encode the object. Numbers are big endian, names are prefixed by their length
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+
+        push_u16(&mut out, self.sections.len());
+        for section in self.sections.iter() {
+            push_name(&mut out, &section.name);
+            push_u16(&mut out, section.bytes.len());
+            out.extend_from_slice(&section.bytes);
+
+            push_u16(&mut out, section.relocations.len());
+            for relocation in section.relocations.iter() {
+                push_u16(&mut out, relocation.offset as usize);
+                out.push(match relocation.kind {
+                    RelocationKind::Address => 0,
+                    RelocationKind::Byte => 1
+                });
+                push_name(&mut out, &relocation.symbol);
+            }
+        }
+
+        push_u16(&mut out, self.symbols.len());
+        for symbol in self.symbols.iter() {
+            push_name(&mut out, &symbol.name);
+            push_u16(&mut out, symbol.section);
+            push_u16(&mut out, symbol.offset as usize);
+        }
+
+        push_u16(&mut out, self.imports.len());
+        for import in self.imports.iter() {
+            push_name(&mut out, import);
+        }
+
+        out
+    }
+
+    /// Decode an object written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, ObjectError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(ObjectError{message: String::from("Not a silica object file")});
+        }
+        if bytes.get(MAGIC.len()) != Some(&VERSION) {
+            return Err(ObjectError{message: String::from("Unsupported object file version")});
+        }
+
+        let mut reader = Reader{bytes, position: MAGIC.len() + 1};
+        let mut object = ObjectFile::default();
+
+        for _ in 0..reader.u16()? {
+            let name = reader.name()?;
+            let size = reader.u16()?;
+            let bytes = reader.take(size)?.to_vec();
+
+            let mut relocations = Vec::new();
+            for _ in 0..reader.u16()? {
+                let offset = reader.u16()? as u32;
+                let kind = match reader.take(1)?[0] {
+                    0 => RelocationKind::Address,
+                    1 => RelocationKind::Byte,
+                    kind => return Err(ObjectError{message: format!("Unknown relocation kind {}", kind)})
+                };
+                let symbol = reader.name()?;
+                relocations.push(Relocation{offset, kind, symbol});
+            }
+
+            object.sections.push(Section{name, bytes, relocations});
+        }
+
+        for _ in 0..reader.u16()? {
+            let name = reader.name()?;
+            let section = reader.u16()?;
+            let offset = reader.u16()? as u32;
+            if section >= object.sections.len() {
+                return Err(ObjectError{message: format!("Symbol {} is in a section that does not exist", name)});
+            }
+            object.symbols.push(Symbol{name, section, offset});
+        }
+
+        for _ in 0..reader.u16()? {
+            object.imports.push(reader.name()?);
+        }
+
+        Ok(object)
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, value: usize) {
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+fn push_name(out: &mut Vec<u8>, name: &str) {
+    push_u16(out, name.len());
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Reads the parts of an object file in order
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], ObjectError> {
+        let end = self.position + size;
+        if end > self.bytes.len() {
+            return Err(ObjectError{message: String::from("Object file is truncated")});
+        }
+
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<usize, ObjectError> {
+        let bytes = self.take(2)?;
+        Ok(((bytes[0] as usize) << 8) | bytes[1] as usize)
+    }
+
+    fn name(&mut self) -> Result<String, ObjectError> {
+        let size = self.u16()?;
+        String::from_utf8(self.take(size)?.to_vec()).map_err(|_| ObjectError{message: String::from("Symbol name is not valid UTF-8")})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object() -> ObjectFile {
+        ObjectFile {
+            sections: vec![Section {
+                name: String::from("code"),
+                bytes: vec![0x22, 0x00, 0x12, 0x02],
+                relocations: vec![Relocation{offset: 0, kind: RelocationKind::Address, symbol: String::from("draw")}]
+            }],
+            symbols: vec![Symbol{name: String::from("end"), section: 0, offset: 2}],
+            imports: vec![String::from("draw")]
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = object().to_bytes();
+
+        assert!(bytes.starts_with(b"SILO\x01"));
+        assert_eq!(ObjectFile::from_bytes(&bytes), Ok(object()));
+    }
+
+    #[test]
+    fn test_bad_objects() {
+        let bytes = object().to_bytes();
+
+        assert_eq!(ObjectFile::from_bytes(b"\x12\x00").unwrap_err().message(), "Not a silica object file");
+        assert_eq!(ObjectFile::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err().message(), "Object file is truncated");
+    }
+}
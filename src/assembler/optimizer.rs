@@ -0,0 +1,290 @@
+use assembler::layout::{self, Layout};
+use assembler::lexer::Token;
+use assembler::parser::{Expression, Statement};
+
+use std::collections::{HashMap, HashSet};
+
+/// Rewrite checked statements into shorter equivalents, until nothing more can be done:
+///
+/// * `LD Vx, Vx` and `ADD Vx, 0` are removed
+/// * `JP` to the address right after it is removed
+/// * `JP` to another `JP` goes straight to the final target
+/// * `CALL x` followed by `RET` becomes `JP x`
+///
+/// Nothing is removed right after a skip or inside a jump table used by `JR`.
+/// Labels follow the statements they are in front of. Addresses written as numbers
+/// that point at something which moved are changed to match
+pub fn optimize(statements: Vec<Statement>, base: u32) -> Vec<Statement> {
+    let mut statements = statements;
+
+    loop {
+        let before = layout::layout(&statements, base);
+        let rewrites = rewrites(&statements, &before);
+        if rewrites.is_empty() {
+            return statements;
+        }
+
+        let mut survivors = Vec::new();
+        let mut optimized = Vec::new();
+        for (i, mut statement) in statements.into_iter().enumerate() {
+            match rewrites.get(&i) {
+                Some(None) => continue,
+                Some(Some(expr)) => statement.expr = expr.clone(),
+                None => {}
+            }
+            survivors.push(i);
+            optimized.push(statement);
+        }
+
+        relocate(&mut optimized, &survivors, &before, base);
+        statements = optimized;
+    }
+}
+
+/// Statements to replace, or to remove when there is no replacement
+fn rewrites(statements: &[Statement], layout: &Layout) -> HashMap<usize, Option<Expression>> {
+    let protected = jump_tables(statements, layout);
+    let targets: HashSet<u32> = statements.iter()
+        .filter_map(|statement| numeric_address(&statement.expr))
+        .collect();
+
+    let mut rewrites = HashMap::new();
+    let mut after_skip = false;
+
+    for (i, statement) in statements.iter().enumerate() {
+        let expr = &statement.expr;
+        match expr[0] {
+            Token::Instruction(_) => {},
+            // data or a new location separates the instructions on either side
+            Token::Directive(_) => {
+                after_skip = false;
+                continue;
+            },
+            _ => continue
+        }
+
+        let removable = !after_skip && !protected.contains(&i);
+        after_skip = is_instruction(expr, &["SE", "SNE", "SKP", "SKNP"]);
+        if rewrites.contains_key(&i) {
+            continue;
+        }
+
+        let next = layout.addresses[i].saturating_add(2);
+        let jumps_to_next = is_instruction(expr, &["JP"]) && layout.resolve(&expr[1]) == Some(next);
+
+        if removable && (is_self_load(expr) || is_add_zero(expr) || jumps_to_next) {
+            rewrites.insert(i, None);
+        }
+        else if let Some(operand) = thread_jump(statements, layout, i) {
+            rewrites.insert(i, Some(vec![expr[0].clone(), operand]));
+        }
+        else if removable && is_instruction(expr, &["CALL"]) && is_return(statements.get(i + 1)) && !targets.contains(&next) {
+            rewrites.insert(i, Some(vec![Token::Instruction(String::from("JP")), expr[1].clone()]));
+            rewrites.insert(i + 1, None);
+        }
+    }
+
+    rewrites
+}
+
+/// Final operand of a chain of jumps starting at statement `i`, if it goes somewhere else
+fn thread_jump(statements: &[Statement], layout: &Layout, i: usize) -> Option<Token> {
+    if !is_instruction(&statements[i].expr, &["JP"]) {
+        return None;
+    }
+
+    let mut seen = vec![i];
+    let mut operand: Option<Token> = None;
+    loop {
+        let current = operand.as_ref().unwrap_or(&statements[i].expr[1]);
+        let next = match layout.resolve(current).and_then(|address| layout.statement_at(statements, address)) {
+            Some(next) => next,
+            None => break
+        };
+        // a jump to itself ends the chain, a jump back to anything else is an endless loop best left alone
+        if next == seen[seen.len() - 1] || !is_instruction(&statements[next].expr, &["JP"]) {
+            break;
+        }
+        if seen.contains(&next) {
+            return None;
+        }
+
+        seen.push(next);
+        operand = Some(statements[next].expr[1].clone());
+    }
+
+    let original = layout.resolve(&statements[i].expr[1]);
+    operand.filter(|operand| layout.resolve(operand) != original || original.is_none())
+}
+
+/// Statements reached through `JR`. The jumps in them have to stay where they are
+fn jump_tables(statements: &[Statement], layout: &Layout) -> HashSet<usize> {
+    let mut protected = HashSet::new();
+
+    for statement in statements.iter().filter(|statement| is_instruction(&statement.expr, &["JR"])) {
+        let start = match layout.resolve(&statement.expr[1]).and_then(|address| layout.statement_at(statements, address)) {
+            Some(start) => start,
+            None => continue
+        };
+
+        for (i, entry) in statements.iter().enumerate().skip(start) {
+            match entry.expr[0] {
+                Token::Label(_) => {},
+                _ if is_instruction(&entry.expr, &["JP"]) => {
+                    protected.insert(i);
+                },
+                _ => break
+            }
+        }
+    }
+
+    protected
+}
+
+/// Point addresses written as numbers at where things went after statements were removed.
+/// `survivors` holds the index each statement had when `before` was worked out
+fn relocate(statements: &mut [Statement], survivors: &[usize], before: &Layout, base: u32) {
+    let after = layout::layout(statements, base);
+
+    // removed statements are replaced by whatever follows them
+    let mut moved = HashMap::new();
+    let mut next = after.addresses.last()
+        .map(|&address| address.saturating_add(statements.last().map(|statement| layout::size(&statement.expr)).unwrap_or(0)))
+        .unwrap_or(base);
+    let mut survivor = survivors.len();
+    for old in (0..before.addresses.len()).rev() {
+        if survivor > 0 && survivors[survivor - 1] == old {
+            survivor -= 1;
+            next = after.addresses[survivor];
+        }
+        moved.insert(before.addresses[old], next);
+    }
+
+    for statement in statements.iter_mut() {
+        if let Some(address) = numeric_address(&statement.expr) {
+            if let Some(&new) = moved.get(&address) {
+                let last = statement.expr.len() - 1;
+                statement.expr[last] = Token::NumericLiteral(new);
+            }
+        }
+    }
+}
+
+/// Address of the code or data a jump, call or `LD I` refers to, when written as a number
+fn numeric_address(expr: &Expression) -> Option<u32> {
+    let refers_to_address = is_instruction(expr, &["JP", "JR", "CALL"]) ||
+        (is_instruction(expr, &["LD"]) && expr.get(1) == Some(&Token::Register(String::from("I"))));
+
+    match expr.last() {
+        Some(&Token::NumericLiteral(address)) if refers_to_address => Some(address),
+        _ => None
+    }
+}
+
+fn is_instruction(expr: &Expression, names: &[&str]) -> bool {
+    match expr[0] {
+        Token::Instruction(ref instr) => names.contains(&instr.as_ref()),
+        _ => false
+    }
+}
+
+fn is_return(statement: Option<&Statement>) -> bool {
+    statement.map(|statement| is_instruction(&statement.expr, &["RET"])).unwrap_or(false)
+}
+
+/// `LD Vx, Vx`
+fn is_self_load(expr: &Expression) -> bool {
+    is_instruction(expr, &["LD"]) && expr[1].is_general_purpose_register() && expr.get(2) == Some(&expr[1])
+}
+
+/// `ADD Vx, 0`, which leaves VF alone
+fn is_add_zero(expr: &Expression) -> bool {
+    is_instruction(expr, &["ADD"]) && expr[1].is_general_purpose_register() && expr.get(2) == Some(&Token::NumericLiteral(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+
+    fn assemble(source: &[u8]) -> Vec<u8> {
+        Assembler::new().optimize(true).assemble("game.asm", source).unwrap().bytes
+    }
+
+    #[test]
+    fn test_removes_no_ops() {
+        assert_eq!(assemble(b"start\tLD V1, V1\n\tADD V2, 0\n\tCLS\n"), vec![0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_jump_to_next() {
+        assert_eq!(assemble(b"start\tJP #next\nnext\tCLS\nend\tJP #end\n"), vec![0x00, 0xE0, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn test_nothing_removed_after_skip() {
+        assert_eq!(assemble(b"start\tSE V0, 1\n\tLD V1, V1\n\tJP #next\nnext\tCLS\n"), vec![0x30, 0x01, 0x81, 0x10, 0x00, 0xE0]);
+        assert_eq!(assemble(b"start\tSE V0, 1\n\tJP #next\n\tLD V1, V1\nnext\tCLS\n"), vec![0x30, 0x01, 0x12, 0x04, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_jump_threading() {
+        let output = Assembler::new().optimize(true).assemble("game.asm", b"start\tJP #a\n\
+                                                                            \tCLS\n\
+                                                                            a\tJP #b\n\
+                                                                            \tCLS\n\
+                                                                            b\tJP #b\n").unwrap();
+
+        assert_eq!(output.bytes, vec![0x12, 0x08, 0x00, 0xE0, 0x12, 0x08, 0x00, 0xE0, 0x12, 0x08]);
+        assert_eq!(output.symbols.get("b"), Some(&0x208));
+    }
+
+    #[test]
+    fn test_jump_cycle() {
+        assert_eq!(assemble(b"a\tJP #b\n\tCLS\nb\tJP #a\n"), vec![0x12, 0x04, 0x00, 0xE0, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_tail_call() {
+        let output = Assembler::new().optimize(true).assemble("game.asm", b"start\tCALL #sub\n\
+                                                                            end\tJP #end\n\
+                                                                            draw\tCLS\n\
+                                                                            \tRET\n\
+                                                                            sub\tCALL #draw\n\
+                                                                            \tRET\n").unwrap();
+
+        assert_eq!(output.bytes, vec![0x22, 0x08, 0x12, 0x02, 0x00, 0xE0, 0x00, 0xEE, 0x12, 0x04]);
+        assert_eq!(output.symbols.get("sub"), Some(&0x208));
+    }
+
+    #[test]
+    fn test_tail_call_falls_through() {
+        assert_eq!(assemble(b"sub\tCALL #draw\n\tRET\ndraw\tCLS\n\tRET\n"), vec![0x00, 0xE0, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn test_labels_move() {
+        let output = Assembler::new().optimize(true).assemble("game.asm", b"start\tLD V0, V0\n\
+                                                                            loop\tLD I, #sprite\n\
+                                                                            \tJP #loop\n\
+                                                                            sprite\n\
+                                                                            \tdb $F0\n").unwrap();
+
+        assert_eq!(output.bytes, vec![0xA2, 0x04, 0x12, 0x00, 0xF0]);
+        assert_eq!(output.symbols.get("loop"), Some(&0x200));
+        assert_eq!(output.symbols.get("sprite"), Some(&0x204));
+    }
+
+    #[test]
+    fn test_numeric_addresses_relocated() {
+        assert_eq!(assemble(b"\tADD V0, 0\n\tLD I, $206\n\tJP $202\n\tdb $F0\n"), vec![0xA2, 0x04, 0x12, 0x00, 0xF0]);
+    }
+
+    #[test]
+    fn test_jump_table() {
+        let source = b"start\tJR #table\n\
+                       table\tJP #next\n\
+                       next\tJP #next\n";
+
+        assert_eq!(assemble(source), vec![0xB2, 0x02, 0x12, 0x04, 0x12, 0x04]);
+    }
+}
@@ -1,15 +1,49 @@
+use assembler::codes;
 use assembler::lexer::*;
 use nom::*;
 
+use std::error::Error;
+use std::fmt;
+
 /// an expression is a certain combination of tokens
 pub type Expression = Vec<Token>;
 
+/// an expression along with the position of its first token
+#[derive(Debug, PartialEq, Clone)]
+pub struct Statement {
+    pub position: Position,
+    pub expr: Expression
+}
+
 /// parser errors
 #[derive(Debug)]
 pub struct ParserError {
+    position: Position,
     message: String
 }
 
+impl ParserError {
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn code(&self) -> &'static str {
+        codes::MALFORMED_STATEMENT
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+impl Error for ParserError {}
+
 macro_rules! tag_token {
     ($i: expr, $tag: pat) => (
         {
@@ -75,6 +109,24 @@ named!(parse_directive<&[Token], Expression>,
     )
 );
 
+/// parse directives naming a file: include and incbin
+named!(parse_file_directive<&[Token], Expression>,
+    do_parse!(
+        directive: tag_token!(Token::Directive(_)) >>
+        path: tag_token!(Token::StringLiteral(_)) >>
+        (vec![directive, path])
+    )
+);
+
+/// parse section directives, naming the section
+named!(parse_section_directive<&[Token], Expression>,
+    do_parse!(
+        directive: tag_token!(Token::Directive(_)) >>
+        name: tag_token!(Token::LabelOperand(_)) >>
+        (vec![directive, name])
+    )
+);
+
 /// parse instructions
 named!(parse_instructions<&[Token], Expression>,
     do_parse!(
@@ -115,6 +167,8 @@ named!(parse_expressions<&[Token], Vec<Expression>>,
         exprs: many0!(
             alt_complete!(
                 parse_directive |
+                parse_file_directive |
+                parse_section_directive |
                 parse_label |
                 parse_instructions
             )
@@ -129,8 +183,39 @@ pub fn parse(tokens: Vec<Token>) -> Result<Vec<Expression>, ParserError> {
 
     match result {
         IResult::Done(_, exprs) => Ok(exprs),
-        _ => Err(ParserError{message: String::from("Error parsing tokens")})
+        _ => Err(ParserError{position: Position::default(), message: String::from("Error parsing tokens")})
+    }
+}
+
+/// parse expressions from tokens, keeping the position each expression starts at
+pub fn parse_statements(tokens: Vec<(Position, Token)>) -> Result<Vec<Statement>, ParserError> {
+    let plain: Vec<Token> = tokens.iter().map(|(_, token)| token.clone()).collect();
+
+    let (rest, exprs) = match parse_expressions(&plain[..]) {
+        IResult::Done(rest, exprs) => (rest.len(), exprs),
+        _ => return Err(ParserError{position: Position::default(), message: String::from("Error parsing tokens")})
+    };
+
+    // commas are the only tokens dropped by the parser, so expressions line up with the remaining tokens
+    let mut positions = tokens.iter()
+        .filter(|(_, token)| *token != Token::Comma)
+        .map(|&(position, _)| position);
+
+    if rest != 0 {
+        let position = tokens[tokens.len() - rest].0;
+        return Err(ParserError{position, message: String::from("Error parsing tokens")});
+    }
+
+    let mut statements = Vec::new();
+    for expr in exprs.into_iter() {
+        let position = positions.next().unwrap_or_default();
+        for _ in 1..expr.len() {
+            positions.next();
+        }
+        statements.push(Statement{position, expr});
     }
+
+    Ok(statements)
 }
 
 #[cfg(test)]
@@ -279,4 +364,49 @@ mod tests {
             vec![Token::Instruction(String::from("JP")), Token::Register(String::from("#end"))]
         ]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_statements() {
+        let input = vec![
+            (Position::new(1, 1), Token::Label(String::from("start"))),
+            (Position::new(1, 7), Token::Instruction(String::from("LD"))),
+            (Position::new(1, 10), Token::Register(String::from("V0"))),
+            (Position::new(1, 12), Token::Comma),
+            (Position::new(1, 14), Token::NumericLiteral(0xFF)),
+            (Position::new(2, 3), Token::Instruction(String::from("CLS")))
+        ];
+        let result = parse_statements(input).unwrap();
+
+        assert_eq!(result, vec![
+            Statement{position: Position::new(1, 1), expr: vec![Token::Label(String::from("start"))]},
+            Statement{position: Position::new(1, 7), expr: vec![
+                Token::Instruction(String::from("LD")), Token::Register(String::from("V0")), Token::NumericLiteral(0xFF)
+            ]},
+            Statement{position: Position::new(2, 3), expr: vec![Token::Instruction(String::from("CLS"))]}
+        ]);
+    }
+
+    #[test]
+    fn test_parse_statements_error_line() {
+        let input = vec![
+            (Position::new(1, 3), Token::Instruction(String::from("CLS"))),
+            (Position::new(2, 3), Token::Directive(String::from("org")))
+        ];
+        let result = parse_statements(input);
+
+        assert_eq!(result.unwrap_err().to_string(), "2:3: Error parsing tokens");
+    }
+
+    #[test]
+    fn test_parse_include() {
+        let input = vec![
+            Token::Directive(String::from("include")),
+            Token::StringLiteral(String::from("sprites.asm"))
+        ];
+        let result = parse(input).unwrap();
+
+        assert_eq!(result, vec![
+            vec![Token::Directive(String::from("include")), Token::StringLiteral(String::from("sprites.asm"))]
+        ]);
+    }
+}
@@ -0,0 +1,173 @@
+use assembler::layout;
+use assembler::lexer::Token;
+use assembler::parser::Statement;
+
+use std::collections::HashMap;
+
+/// Sections a program can put its content in, in the order they are placed
+pub const SECTIONS: [&str; 3] = ["code", "data", "font"];
+
+/// Name of the section a `section` directive switches to
+pub fn section_name(statement: &Statement) -> Option<&str> {
+    match (statement.expr.first(), statement.expr.get(1)) {
+        (Some(Token::Directive(ref directive)), Some(Token::LabelOperand(ref name))) if directive == "section" => Some(name),
+        _ => None
+    }
+}
+
+/// Where sections go, in order. Each starts at its address when it has one, or right after
+/// the section before it
+pub type Placement = Vec<(String, Option<u32>)>;
+
+/// Place sections without a fixed address one after the other in the order of `SECTIONS`,
+/// then those with one by address
+pub fn place<'a, I: Iterator<Item = &'a str>>(names: I, addresses: &HashMap<String, u32>) -> Placement {
+    let mut order: Vec<String> = Vec::new();
+    for name in names {
+        if !order.iter().any(|placed| placed == name) {
+            order.push(String::from(name));
+        }
+    }
+
+    order.sort_by_key(|name| (addresses.get(name).cloned(), SECTIONS.iter().position(|section| section == name)));
+    order.into_iter().map(|name| {
+        let address = addresses.get(&name).cloned();
+        (name, address)
+    }).collect()
+}
+
+/// Split statements into their sections, in the order the sections first appear.
+/// Statements before the first `section` directive are code
+pub fn split(statements: Vec<Statement>) -> Vec<(String, Vec<Statement>)> {
+    let mut sections: Vec<(String, Vec<Statement>)> = Vec::new();
+    let mut current = String::from("code");
+
+    for statement in statements.into_iter() {
+        if let Some(name) = section_name(&statement) {
+            current = String::from(name);
+        }
+        match sections.iter_mut().find(|(name, _)| *name == current) {
+            Some(section) => section.1.push(statement),
+            None => sections.push((current.clone(), vec![statement]))
+        }
+    }
+
+    sections
+}
+
+/// Put the content of each section together, as placed. Sections with an address start
+/// with an `org` to it
+pub fn arrange(sections: Vec<(String, Vec<Statement>)>, placement: &Placement) -> Vec<Statement> {
+    let mut sections = sections;
+    let mut arranged = Vec::new();
+
+    for &(ref name, address) in placement.iter() {
+        let statements = match sections.iter().position(|section| section.0 == *name) {
+            Some(i) => sections.remove(i).1,
+            None => continue
+        };
+
+        if let (Some(address), Some(first)) = (address, statements.first()) {
+            let expr = vec![Token::Directive(String::from("org")), Token::NumericLiteral(address)];
+            arranged.push(Statement{position: first.position, expr});
+        }
+        arranged.extend(statements);
+    }
+
+    arranged
+}
+
+/// Addresses each section of arranged statements occupies, as `(start, end, name)`
+/// with `end` the first address after it. Sections without bytes are left out
+pub fn ranges(statements: &[Statement], base: u32) -> Vec<(u32, u32, String)> {
+    let layout = layout::layout(statements, base);
+    let mut ranges: Vec<(u32, u32, String)> = Vec::new();
+    let mut current = "code";
+
+    for (statement, &address) in statements.iter().zip(layout.addresses.iter()) {
+        if let Some(name) = section_name(statement) {
+            current = name;
+        }
+        let size = layout::size(&statement.expr);
+        if size == 0 {
+            continue;
+        }
+        let end = address.saturating_add(size);
+
+        match ranges.iter_mut().find(|&&mut (_, _, ref name)| name == current) {
+            Some(range) => {
+                range.0 = range.0.min(address);
+                range.1 = range.1.max(end);
+            },
+            None => ranges.push((address, end, String::from(current)))
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::lexer::Position;
+
+    fn statement(expr: Vec<Token>) -> Statement {
+        Statement{position: Position::default(), expr}
+    }
+
+    fn section(name: &str) -> Statement {
+        statement(vec![Token::Directive(String::from("section")), Token::LabelOperand(String::from(name))])
+    }
+
+    fn label(name: &str) -> Statement {
+        statement(vec![Token::Label(String::from(name))])
+    }
+
+    fn names(statements: &[Statement]) -> Vec<String> {
+        statements.iter().map(|statement| match statement.expr[0] {
+            Token::Label(ref label) => label.clone(),
+            Token::Directive(ref directive) => format!("{} {:?}", directive, statement.expr[1]),
+            _ => String::new()
+        }).collect()
+    }
+
+    #[test]
+    fn test_split() {
+        let statements = vec![label("start"), section("font"), label("digits"), section("data"), label("sprite"), section("code"), label("loop")];
+        let sections = split(statements);
+
+        let order: Vec<&str> = sections.iter().map(|(name, _)| name.as_ref()).collect();
+        assert_eq!(order, vec!["code", "font", "data"]);
+        assert_eq!(names(&sections[0].1), vec!["start", "section LabelOperand(\"code\")", "loop"]);
+    }
+
+    #[test]
+    fn test_place() {
+        let mut addresses = HashMap::new();
+        addresses.insert(String::from("code"), 0x400);
+
+        assert_eq!(place(vec!["font", "code", "data"].into_iter(), &addresses), vec![
+            (String::from("data"), None),
+            (String::from("font"), None),
+            (String::from("code"), Some(0x400))
+        ]);
+    }
+
+    #[test]
+    fn test_arrange_at_address() {
+        let statements = vec![section("data"), label("sprite"), section("code"), label("start")];
+        let placement = vec![(String::from("code"), None), (String::from("data"), Some(0x400))];
+
+        assert_eq!(names(&arrange(split(statements), &placement)), vec![
+            "section LabelOperand(\"code\")", "start", "org NumericLiteral(1024)", "section LabelOperand(\"data\")", "sprite"
+        ]);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let db = statement(vec![Token::Directive(String::from("db")), Token::NumericLiteral(0xF0), Token::NumericLiteral(0x90)]);
+        let statements = vec![label("start"), db.clone(), section("font"), section("data"), db];
+
+        assert_eq!(ranges(&statements, 0x200), vec![(0x200, 0x202, String::from("code")), (0x202, 0x204, String::from("data"))]);
+    }
+}
@@ -1,11 +1,35 @@
+use assembler::codes;
 use assembler::lexer::Token;
 use assembler::parser::Expression;
+use assembler::section::SECTIONS;
+
+use std::error::Error;
+use std::fmt;
 
 #[derive(Debug)]
 pub struct SemanticsError {
+    code: &'static str,
     message: String
 }
 
+impl SemanticsError {
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for SemanticsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for SemanticsError {}
+
 /// Check an expression and ensure it is semantically correct
 pub fn check(expr: &Expression) -> Result<(), SemanticsError> {
     match expr[0] {
@@ -18,13 +42,13 @@ pub fn check(expr: &Expression) -> Result<(), SemanticsError> {
             match dir.as_ref() {
                 "org" => {
                     if expr.len() != 2 {
-                        return Err(SemanticsError{message: String::from("Invalid number of tokens for directive expression")})
+                        return Err(SemanticsError{code: codes::OPERAND_COUNT, message: String::from("Invalid number of tokens for directive expression")})
                     }
                     match expr[1] {
                         Token::NumericLiteral(_) => {
                             return Ok(())
                         },
-                        _ => return Err(SemanticsError{message: String::from("Invalid token in directive expression")})
+                        _ => return Err(SemanticsError{code: codes::INVALID_OPERAND, message: String::from("Invalid token in directive expression")})
                     }
                 },
                 "db" => {
@@ -32,12 +56,29 @@ pub fn check(expr: &Expression) -> Result<(), SemanticsError> {
                         match expr[i] {
                             Token::NumericLiteral(_) => {},
                             _ => {
-                                return Err(SemanticsError{message: String::from("Invalid token type in expression for db directive")})
+                                return Err(SemanticsError{code: codes::INVALID_OPERAND, message: String::from("Invalid token type in expression for db directive")})
                             }
                         }
                     }
                     return Ok(())
                 },
+                "section" => {
+                    match expr.get(1) {
+                        Some(Token::LabelOperand(ref name)) if expr.len() == 2 && SECTIONS.contains(&name.as_ref()) => Ok(()),
+                        Some(Token::LabelOperand(ref name)) if expr.len() == 2 => {
+                            Err(SemanticsError{code: codes::UNKNOWN_SECTION, message: format!("Unknown section {}, expected code, data or font", name)})
+                        },
+                        _ => Err(SemanticsError{code: codes::INVALID_OPERAND, message: String::from("section expects the name of a section")})
+                    }
+                },
+                "include" | "incbin" => {
+                    if expr.len() == 2 && expr[1].is_string_literal() {
+                        Ok(())
+                    }
+                    else {
+                        Err(SemanticsError{code: codes::OPERAND_COUNT, message: format!("{} expects a single file name", dir)})
+                    }
+                },
                 _ => {
                     panic!("Invalid directive: {}", dir);
                 }
@@ -47,269 +88,123 @@ pub fn check(expr: &Expression) -> Result<(), SemanticsError> {
             check_instruction_semantics(instr, expr)
         },
         _ => {
-            Err(SemanticsError{message: String::from("Invalid start of expression")})
+            Err(SemanticsError{code: codes::INVALID_STATEMENT, message: String::from("Invalid start of expression")})
         }
     }
 }
 
-fn check_instruction_semantics(instr: &String, expr: &Expression) -> Result<(), SemanticsError> {
-    match instr.as_ref() {
-        "CLS" => {
-            if expr.len() == 1 {
-                Ok(())
-            }
-            else {
-                Err(SemanticsError{message: String::from("CLS has no operands")})
-            }
-        },
-        "RET" => {
-            if expr.len() == 1 {
-                Ok(())
-            }
-            else {
-                Err(SemanticsError{message: String::from("RET has no operands")})
-            }
-        },
-        "JP" => {
-            if expr.len() == 2 {
-                if expr[1].is_numeric_literal() || expr[1].is_label_operand() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operand for instruction JP")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for JP expression")})
-            }
-        },
-        "JR" => {
-            if expr.len() == 2 {
-                if expr[1].is_numeric_literal() || expr[1].is_label_operand() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operand for instruction JR")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for JR expression")})
-            }
-        },
-        "CALL" => {
-            if expr.len() == 2 {
-                if expr[1].is_numeric_literal() || expr[1].is_label_operand() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operand for instruction CALL")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for CALL expression")})
-            }
-        },
-        "SE" => {
-            if expr.len() == 3 {
-                if (expr[1].is_register() && expr[2].is_numeric_literal()) ||
-                    (expr[1].is_register() && expr[2].is_register()) {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for SE instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for SE expression")})                
-            }
-        },
-        "SNE" => {
-            if expr.len() == 3 {
-                if (expr[1].is_register() && expr[2].is_numeric_literal()) ||
-                    (expr[1].is_register() && expr[2].is_register()) {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for SNE instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for SNE expression")})                
-            }
-        },
-        "LD" => {
-            if expr.len() == 3 {
-                if (expr[1].is_register() && expr[2].is_numeric_literal()) ||
-                    (expr[1].is_register() && expr[2].is_register()) ||
-                    (expr[1].is_register() && expr[2].is_label_operand()) {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for LD instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for LD expression")})                
-            }
-        },
-        "OR" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for OR instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for OR expression")})                
-            }
-        },
-        "AND" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for AND instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for AND expression")})                
-            }
-        },
-        "XOR" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for XOR instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for XOR expression")})                
-            }
-        },
-        "ADD" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_register() ||
-                   expr[1].is_register() && expr[2].is_numeric_literal() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for ADD instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for ADD expression")})                
-            }
-        },
-        "SUB" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for SUB instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for SUB expression")})                
-            }
-        },
-        "SUBN" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for SUBN instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for SUBN expression")})                
-            }
-        },
-        "SHL" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for SHL instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for SHL expression")})                
-            }
-        },
-        "SHR" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for SHR instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for SHR expression")})                
-            }
-        },
-        "RND" => {
-            if expr.len() == 3 {
-                if expr[1].is_register() && expr[2].is_numeric_literal() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for RND instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for RND expression")})                
-            }
-        },
-        "DRW" => {
-            if expr.len() == 4 {
-                if expr[1].is_register() && expr[2].is_register() && expr[3].is_numeric_literal() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for DRW instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for DRW expression")})                
-            }
-        },
-        "SKP" => {
-            if expr.len() == 2 {
-                if expr[1].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for SKP instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for SKP expression")})                
-            }
-        },
-        "SKNP" => {
-            if expr.len() == 2 {
-                if expr[1].is_register() {
-                    Ok(())
-                }
-                else {
-                    Err(SemanticsError{message: String::from("Invalid operands for SKNP instruction")})
-                }
-            }
-            else {
-                Err(SemanticsError{message: String::from("Invalid number of operands for SKNP expression")})                
-            }
-        },
-        _ => Ok(())
+/// Operands each instruction takes. `Vx` and `Vy` are V0 to VF, `byte`, `nibble` and
+/// `addr` are numbers, `addr` may also be a label and `#label` is a label or constant.
+/// Other names are the register itself. SYS is not checked
+pub const FORMS: [(&str, &[&str]); 20] = [
+    ("CLS", &[""]),
+    ("RET", &[""]),
+    ("JP", &["addr"]),
+    ("JR", &["addr"]),
+    ("CALL", &["addr"]),
+    ("SE", &["Vx, byte", "Vx, Vy"]),
+    ("SNE", &["Vx, byte", "Vx, Vy"]),
+    ("LD", &["Vx, byte", "Vx, #label", "Vx, Vy", "Vx, DT", "Vx, K", "Vx, [I]", "I, addr", "DT, Vx", "ST, Vx", "F, Vx", "B, Vx", "[I], Vx"]),
+    ("ADD", &["Vx, byte", "Vx, Vy", "I, Vx"]),
+    ("OR", &["Vx, Vy"]),
+    ("AND", &["Vx, Vy"]),
+    ("XOR", &["Vx, Vy"]),
+    ("SUB", &["Vx, Vy"]),
+    ("SUBN", &["Vx, Vy"]),
+    ("SHR", &["Vx, Vy"]),
+    ("SHL", &["Vx, Vy"]),
+    ("RND", &["Vx, byte"]),
+    ("DRW", &["Vx, Vy, nibble"]),
+    ("SKP", &["Vx"]),
+    ("SKNP", &["Vx"])
+];
+
+/// Operand forms of an instruction, written out with the instruction, e.g. `ADD I, Vx`
+pub fn forms(instr: &str) -> Vec<String> {
+    FORMS.iter()
+        .filter(|&&(name, _)| name == instr)
+        .flat_map(|&(_, forms)| forms.iter().map(move |form| format!("{} {}", instr, form).trim_end().to_string()))
+        .collect()
+}
+
+/// Registers named in operand forms
+const FIXED: [&str; 7] = ["I", "DT", "ST", "F", "B", "K", "[I]"];
+
+/// Whether an operand fits a place in an operand form
+fn fits(kind: &str, operand: &Token) -> bool {
+    match kind {
+        "Vx" | "Vy" => operand.is_general_purpose_register(),
+        "byte" | "nibble" => operand.is_numeric_literal(),
+        "addr" => operand.is_numeric_literal() || operand.is_label_operand(),
+        "#label" => operand.is_label_operand(),
+        register => *operand == Token::Register(String::from(register))
+    }
+}
+
+fn check_instruction_semantics(instr: &str, expr: &Expression) -> Result<(), SemanticsError> {
+    let kinds: Vec<Vec<&str>> = match FORMS.iter().find(|&&(name, _)| name == instr) {
+        Some(&(_, forms)) => forms.iter().map(|form| form.split(", ").filter(|kind| !kind.is_empty()).collect()).collect(),
+        None => return Ok(())
+    };
+    let operands = &expr[1..];
+
+    // score how well each form of the same length fits, the best is likely what was meant.
+    // Naming a register the form requires counts for more than any operand of the right kind
+    let fitting: Vec<(usize, usize, usize)> = kinds.iter().enumerate()
+        .filter(|&(_, form)| form.len() == operands.len())
+        .map(|(i, form)| {
+            let fit: Vec<&&str> = form.iter().zip(operands.iter()).filter(|&(kind, operand)| fits(kind, operand)).map(|(kind, _)| kind).collect();
+            let score = fit.iter().map(|kind| if FIXED.contains(kind) { 2 } else { 1 }).sum();
+            (i, fit.len(), score)
+        })
+        .collect();
+    if fitting.iter().any(|&(_, count, _)| count == operands.len()) {
+        return Ok(());
+    }
+
+    let written = to_source(expr);
+    let forms = forms(instr);
+    if forms == [instr] {
+        return Err(SemanticsError{code: codes::OPERAND_COUNT, message: format!("{} has no operands", instr)});
+    }
+    if fitting.is_empty() {
+        return Err(SemanticsError {
+            code: codes::OPERAND_COUNT,
+            message: format!("`{}` has the wrong number of operands; {} takes {}", written, instr, list(&forms))
+        });
+    }
+
+    let most = fitting.iter().map(|&(_, _, score)| score).max().unwrap_or(0);
+    let best: Vec<String> = fitting.iter()
+        .filter(|&&(_, _, score)| score == most && score > 0)
+        .map(|&(i, _, _)| forms[i].clone())
+        .collect();
+    let message = match best.len() {
+        0 => format!("`{}` is not valid; {} takes {}", written, instr, list(&forms)),
+        1 => format!("`{}` is not valid; `{}` is", written, best[0]),
+        _ => format!("`{}` is not valid; {} takes {}", written, instr, list(&best))
+    };
+
+    Err(SemanticsError{code: codes::INVALID_OPERAND, message})
+}
+
+/// Forms of an instruction as a list for a message, e.g. `SE Vx, byte` or `SE Vx, Vy`
+fn list(forms: &[String]) -> String {
+    let forms: Vec<String> = forms.iter().map(|form| format!("`{}`", form)).collect();
+    match forms.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        _ => forms.join("")
+    }
+}
+
+/// Write an expression back out as source, with operands separated by commas
+pub fn to_source(expr: &Expression) -> String {
+    let operands: Vec<String> = expr[1..].iter().map(|token| token.to_string()).collect();
+    if operands.is_empty() {
+        expr[0].to_string()
+    }
+    else {
+        format!("{} {}", expr[0], operands.join(", "))
     }
 }
 
@@ -586,4 +481,59 @@ mod tests {
         ];
         check(&expr).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_check_operand_forms() {
+        let add = vec![Token::Instruction(String::from("ADD")), Token::Register(String::from("I")), Token::NumericLiteral(5)];
+        let error = check(&add).unwrap_err();
+        assert_eq!(error.code(), codes::INVALID_OPERAND);
+        assert_eq!(error.message(), "`ADD I, 5` is not valid; `ADD I, Vx` is");
+
+        let se = vec![Token::Instruction(String::from("SE")), Token::Register(String::from("DT")), Token::Register(String::from("V0"))];
+        assert_eq!(check(&se).unwrap_err().message(), "`SE DT, V0` is not valid; `SE Vx, Vy` is");
+
+        let drw = vec![Token::Instruction(String::from("DRW")), Token::Register(String::from("V0")), Token::Register(String::from("V1"))];
+        let error = check(&drw).unwrap_err();
+        assert_eq!(error.code(), codes::OPERAND_COUNT);
+        assert_eq!(error.message(), "`DRW V0, V1` has the wrong number of operands; DRW takes `DRW Vx, Vy, nibble`");
+    }
+
+    #[test]
+    fn test_check_include() {
+        let expr = vec![
+            Token::Directive(String::from("include")),
+            Token::StringLiteral(String::from("sprites.asm"))
+        ];
+        check(&expr).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_include_invalid_operand() {
+        let expr = vec![
+            Token::Directive(String::from("include")),
+            Token::NumericLiteral(0)
+        ];
+        check(&expr).unwrap();
+    }
+
+    #[test]
+    fn test_check_section() {
+        let section = |name: &str| vec![
+            Token::Directive(String::from("section")),
+            Token::LabelOperand(String::from(name))
+        ];
+
+        check(&section("font")).unwrap();
+        assert_eq!(check(&section("bss")).unwrap_err().message(), "Unknown section bss, expected code, data or font");
+    }
+
+    #[test]
+    fn test_check_incbin() {
+        let expr = vec![
+            Token::Directive(String::from("incbin")),
+            Token::StringLiteral(String::from("title.bin"))
+        ];
+        check(&expr).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Gives the assembler access to source files, the main input as well as
+/// files named by `include` and `incbin` directives.
+/// Files are known by an identifier chosen by the provider, which is used in diagnostics
+pub trait SourceProvider {
+    /// Find the identifier of the file `name` refers to. `from` is the identifier of
+    /// the file containing the reference, or none for the main input
+    fn resolve(&self, name: &str, from: Option<&str>) -> Result<String>;
+
+    /// Read the contents of a file given its identifier
+    fn read(&self, id: &str) -> Result<Vec<u8>>;
+}
+
+/// Reads files from disk. Referenced files are found relative to the file referencing them,
+/// or failing that in the include directories
+#[derive(Default)]
+pub struct FileSystemProvider {
+    root: PathBuf,
+    include_dirs: Vec<PathBuf>
+}
+
+impl FileSystemProvider {
+    /// Provider finding the main input relative to the working directory
+    pub fn new() -> Self {
+        FileSystemProvider::default()
+    }
+
+    /// Provider finding the main input relative to the given directory
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        FileSystemProvider {
+            root: root.as_ref().to_path_buf(),
+            include_dirs: Vec::new()
+        }
+    }
+
+    /// Also look for referenced files in `dir`. Directories are searched in the order they are added
+    pub fn with_include_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+}
+
+impl SourceProvider for FileSystemProvider {
+    fn resolve(&self, name: &str, from: Option<&str>) -> Result<String> {
+        let dir = match from {
+            Some(from) => Path::new(from).parent().unwrap_or(Path::new("")).to_path_buf(),
+            None => self.root.clone()
+        };
+
+        let path = dir.join(name);
+        let found = match from {
+            Some(_) if !path.exists() => self.include_dirs.iter().map(|dir| dir.join(name)).find(|path| path.exists()),
+            _ => None
+        };
+
+        Ok(found.unwrap_or(path).to_string_lossy().into_owned())
+    }
+
+    fn read(&self, id: &str) -> Result<Vec<u8>> {
+        let mut file = File::open(id)?;
+
+        let mut buffer: Vec<u8> = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
+/// Serves files from memory. Names are used as identifiers as-is
+#[derive(Default)]
+pub struct MemoryProvider {
+    files: HashMap<String, Vec<u8>>
+}
+
+impl MemoryProvider {
+    pub fn new() -> Self {
+        MemoryProvider::default()
+    }
+
+    /// Add or replace a file
+    pub fn insert(&mut self, name: &str, contents: &[u8]) {
+        self.files.insert(String::from(name), contents.to_vec());
+    }
+
+    /// Builder style version of `insert`
+    pub fn with_file(mut self, name: &str, contents: &[u8]) -> Self {
+        self.insert(name, contents);
+        self
+    }
+}
+
+impl From<HashMap<String, Vec<u8>>> for MemoryProvider {
+    fn from(files: HashMap<String, Vec<u8>>) -> Self {
+        MemoryProvider {
+            files
+        }
+    }
+}
+
+impl SourceProvider for MemoryProvider {
+    fn resolve(&self, name: &str, _from: Option<&str>) -> Result<String> {
+        Ok(String::from(name))
+    }
+
+    fn read(&self, id: &str) -> Result<Vec<u8>> {
+        self.files.get(id).cloned().ok_or_else(|| Error::new(ErrorKind::NotFound, "file not found"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_system_resolve() {
+        let provider = FileSystemProvider::new();
+
+        assert_eq!(provider.resolve("game.asm", None).unwrap(), "game.asm");
+        assert_eq!(provider.resolve("sprites.asm", Some("games/pong/pong.asm")).unwrap(), "games/pong/sprites.asm");
+    }
+
+    #[test]
+    fn test_file_system_resolve_root() {
+        let provider = FileSystemProvider::with_root("games");
+
+        assert_eq!(provider.resolve("pong.asm", None).unwrap(), "games/pong.asm");
+    }
+
+    #[test]
+    fn test_file_system_include_dirs() {
+        let provider = FileSystemProvider::new().with_include_dir("missing").with_include_dir("src");
+
+        assert_eq!(provider.resolve("lib.rs", Some("game.asm")).unwrap(), "src/lib.rs");
+        assert_eq!(provider.resolve("sprites.asm", Some("game.asm")).unwrap(), "sprites.asm");
+        assert_eq!(provider.resolve("lib.rs", None).unwrap(), "lib.rs");
+    }
+
+    #[test]
+    fn test_memory_provider() {
+        let provider = MemoryProvider::new().with_file("main.asm", b"\tCLS\n");
+
+        assert_eq!(provider.resolve("main.asm", None).unwrap(), "main.asm");
+        assert_eq!(provider.read("main.asm").unwrap(), b"\tCLS\n".to_vec());
+        assert!(provider.read("other.asm").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+/// Number of single character insertions, deletions, substitutions and swaps of
+/// neighbouring characters that turn `a` into `b`
+pub fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // d[i][j] is the distance between the first i characters of a and the first j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..a.len() + 1 {
+        for j in 1..b.len() + 1 {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j - 1] + cost).min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+/// The candidate closest to `word`, if any is close enough to be what was meant.
+/// Case is ignored, so `ld` finds `LD`. Ties go to the earliest candidate
+pub fn closest<'a, I>(word: &str, candidates: I) -> Option<&'a str>
+    where I: IntoIterator<Item = &'a str>
+{
+    let word = word.to_uppercase();
+    // one character words would be close to every other one character word
+    let length = word.chars().count();
+    let limit = if length > 1 { (length / 3).max(1) } else { 0 };
+
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in candidates {
+        let d = distance(&word, &candidate.to_uppercase());
+        if d <= limit && best.map(|(best, _)| d < best).unwrap_or(true) {
+            best = Some((d, candidate));
+        }
+    }
+
+    best.map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("DRW", "DRAW"), 1);
+        assert_eq!(distance("strat", "start"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("", "CLS"), 3);
+        assert_eq!(distance("SKNP", "SKNP"), 0);
+    }
+
+    #[test]
+    fn test_closest() {
+        assert_eq!(closest("DRAW", vec!["CLS", "DRW", "RET"]), Some("DRW"));
+        assert_eq!(closest("ld", vec!["LD", "ADD"]), Some("LD"));
+        assert_eq!(closest("strat", vec!["end", "start"]), Some("start"));
+        assert_eq!(closest("PRINT", vec!["CLS", "DRW", "RET"]), None);
+        assert_eq!(closest("K", vec!["I", "F"]), None);
+    }
+}
@@ -0,0 +1,58 @@
+use quirks::Quirks;
+
+/// Chip8 variants the assembler can produce programs for
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Target {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip
+}
+
+impl Target {
+    /// Get the target from its command line name
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "chip8" => Some(Target::Chip8),
+            "schip" => Some(Target::SuperChip),
+            "xochip" => Some(Target::XoChip),
+            _ => None
+        }
+    }
+
+    /// Size of the address space programs can be placed in
+    pub fn memory_size(&self) -> u32 {
+        match *self {
+            Target::Chip8 | Target::SuperChip => 0x1000,
+            Target::XoChip => 0x10000
+        }
+    }
+
+    /// Quirks of the interpreters programs for the target are usually run on
+    pub fn quirks(&self) -> Quirks {
+        match *self {
+            Target::Chip8 => Quirks::vip(),
+            Target::SuperChip => Quirks::schip(),
+            Target::XoChip => Quirks::xochip()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Target::from_name("chip8"), Some(Target::Chip8));
+        assert_eq!(Target::from_name("schip"), Some(Target::SuperChip));
+        assert_eq!(Target::from_name("xochip"), Some(Target::XoChip));
+        assert_eq!(Target::from_name("megachip"), None);
+    }
+
+    #[test]
+    fn test_memory_size() {
+        assert_eq!(Target::Chip8.memory_size(), 4096);
+        assert_eq!(Target::XoChip.memory_size(), 65536);
+    }
+}
@@ -0,0 +1,354 @@
+use flow::{BasicBlock, ControlFlow};
+
+use std::collections::{HashMap, HashSet};
+
+/// Bits for the registers tracked: V0 to VF, then I, DT and ST
+const REG_I: u32 = 16;
+const REG_DT: u32 = 17;
+const REG_ST: u32 = 18;
+const ALL_REGISTERS: u32 = (1 << 19) - 1;
+const VF: u32 = 1 << 0xF;
+
+/// Something the dataflow pass found, at the address of the instruction responsible
+#[derive(Debug, PartialEq, Clone)]
+pub struct Finding {
+    pub address: u16,
+    pub message: String
+}
+
+/// Registers an instruction reads and writes
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Effects {
+    reads: u32,
+    writes: u32,
+    /// leaves a carry, borrow or shifted out bit in VF
+    sets_flag: bool
+}
+
+fn effects(opcode: u16) -> Effects {
+    let x = 1 << ((opcode >> 8) & 0xF);
+    let y = 1 << ((opcode >> 4) & 0xF);
+    let up_to_x = (1 << (((opcode >> 8) & 0xF) + 1)) - 1;
+    let i = 1 << REG_I;
+
+    let (reads, writes, sets_flag) = match opcode >> 12 {
+        0x3 | 0x4 => (x, 0, false),
+        0x5 | 0x9 => (x | y, 0, false),
+        0x6 | 0xC => (0, x, false),
+        0x7 => (x, x, false),
+        0x8 => match opcode & 0xF {
+            0x0 => (y, x, false),
+            0x1..=0x3 => (x | y, x, false),
+            0x4 | 0x5 | 0x7 => (x | y, x | VF, true),
+            // which register is shifted depends on the interpreter, Vx is read by all of them
+            0x6 | 0xE => (x, x | VF, true),
+            _ => (0, 0, false)
+        },
+        0xA => (0, i, false),
+        0xB => (1, 0, false),
+        0xD => (x | y | i, VF, false),
+        0xE => (x, 0, false),
+        0xF => match opcode & 0xFF {
+            0x07 => (1 << REG_DT, x, false),
+            0x0A => (0, x, false),
+            0x15 => (x, 1 << REG_DT, false),
+            0x18 => (x, 1 << REG_ST, false),
+            0x1E => (x | i, i, false),
+            0x29 => (x, i, false),
+            0x33 => (x | i, 0, false),
+            0x55 => (up_to_x | i, 0, false),
+            0x65 => (i, up_to_x, false),
+            _ => (0, 0, false)
+        },
+        _ => (0, 0, false)
+    };
+
+    Effects{reads, writes, sets_flag}
+}
+
+fn register_name(register: u32) -> String {
+    match register {
+        REG_I => String::from("I"),
+        REG_DT => String::from("DT"),
+        REG_ST => String::from("ST"),
+        _ => format!("V{:X}", register)
+    }
+}
+
+/// Reads of registers that may not have been set on every path from the entry point.
+/// A CALL counts as setting whatever the subroutine may set
+pub fn uninitialized(flow: &ControlFlow) -> Vec<Finding> {
+    let blocks: Vec<&BasicBlock> = flow.blocks.iter().filter(|block| block.reachable).collect();
+    let predecessors = predecessors(&blocks);
+    let callee_writes: HashMap<u16, u32> = flow.subroutines.iter().map(|&start| (start, subroutine_writes(flow, start))).collect();
+
+    // registers certainly set at the end of each block, and before each call to a subroutine
+    let mut block_out: HashMap<u16, u32> = blocks.iter().map(|block| (block.start, ALL_REGISTERS)).collect();
+    let mut call_in: HashMap<u16, u32> = HashMap::new();
+
+    let block_in = |start: u16, block_out: &HashMap<u16, u32>, call_in: &HashMap<u16, u32>| -> u32 {
+        if start == flow.entry {
+            return 0;
+        }
+        let mut defined = call_in.get(&start).cloned().unwrap_or(ALL_REGISTERS);
+        for predecessor in predecessors.get(&start).iter().flat_map(|p| p.iter()) {
+            defined &= block_out[predecessor];
+        }
+        defined
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut next_call_in: HashMap<u16, u32> = HashMap::new();
+
+        for block in blocks.iter() {
+            let mut defined = block_in(block.start, &block_out, &call_in);
+            for &(_, opcode) in block.instructions.iter() {
+                if opcode >> 12 == 0x2 {
+                    let callee = opcode & 0xFFF;
+                    *next_call_in.entry(callee).or_insert(ALL_REGISTERS) &= defined;
+                    defined |= callee_writes.get(&callee).cloned().unwrap_or(0);
+                }
+                defined |= effects(opcode).writes;
+            }
+
+            if block_out[&block.start] != defined {
+                block_out.insert(block.start, defined);
+                changed = true;
+            }
+        }
+
+        if next_call_in != call_in {
+            call_in = next_call_in;
+            changed = true;
+        }
+    }
+
+    let mut findings = Vec::new();
+    for block in blocks.iter() {
+        let mut defined = block_in(block.start, &block_out, &call_in);
+        for &(address, opcode) in block.instructions.iter() {
+            let effects = effects(opcode);
+            let undefined = effects.reads & !defined;
+            for register in (0..19).filter(|register| undefined & (1 << register) != 0) {
+                findings.push(Finding{address, message: format!("{} may be used before it is set", register_name(register))});
+            }
+
+            if opcode >> 12 == 0x2 {
+                defined |= callee_writes.get(&(opcode & 0xFFF)).cloned().unwrap_or(0);
+            }
+            // report each register once, later reads follow from the first
+            defined |= effects.writes | effects.reads;
+        }
+    }
+
+    findings
+}
+
+/// State of VF as far as carry and borrow flags go
+#[derive(Debug, PartialEq, Clone, Default)]
+struct FlagState {
+    /// arithmetic whose flag may be in VF
+    flags: HashSet<u16>,
+    /// arithmetic whose flag may have been overwritten, and the instruction that did it
+    clobbered: HashSet<(u16, u16)>
+}
+
+impl FlagState {
+    fn merge(&mut self, other: &FlagState) {
+        self.flags.extend(other.flags.iter().cloned());
+        self.clobbered.extend(other.clobbered.iter().cloned());
+    }
+
+    fn apply(&mut self, address: u16, opcode: u16) {
+        let effects = effects(opcode);
+        if effects.sets_flag {
+            self.flags = [address].iter().cloned().collect();
+            self.clobbered.clear();
+        }
+        else if effects.writes & VF != 0 {
+            self.clobbered = self.flags.iter().map(|&flag| (flag, address)).collect();
+            self.flags.clear();
+        }
+        else if opcode >> 12 == 0x2 {
+            // what the subroutine does with VF is its own business
+            self.flags.clear();
+            self.clobbered.clear();
+        }
+    }
+}
+
+/// Reads of VF where the flag left by an arithmetic instruction may have been
+/// overwritten by something else on the way
+pub fn vf_clobbered(flow: &ControlFlow) -> Vec<Finding> {
+    let blocks: Vec<&BasicBlock> = flow.blocks.iter().filter(|block| block.reachable).collect();
+    let predecessors = predecessors(&blocks);
+
+    let mut block_out: HashMap<u16, FlagState> = blocks.iter().map(|block| (block.start, FlagState::default())).collect();
+    let block_in = |start: u16, block_out: &HashMap<u16, FlagState>| -> FlagState {
+        let mut state = FlagState::default();
+        for predecessor in predecessors.get(&start).iter().flat_map(|p| p.iter()) {
+            state.merge(&block_out[predecessor]);
+        }
+        state
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in blocks.iter() {
+            let mut state = block_in(block.start, &block_out);
+            for &(address, opcode) in block.instructions.iter() {
+                state.apply(address, opcode);
+            }
+
+            if block_out[&block.start] != state {
+                block_out.insert(block.start, state);
+                changed = true;
+            }
+        }
+    }
+
+    let mut findings = Vec::new();
+    for block in blocks.iter() {
+        let mut state = block_in(block.start, &block_out);
+        for &(address, opcode) in block.instructions.iter() {
+            if effects(opcode).reads & VF != 0 {
+                let mut clobbered: Vec<&(u16, u16)> = state.clobbered.iter().collect();
+                clobbered.sort();
+                for &&(flag, clobber) in clobbered.iter() {
+                    let message = format!("VF is used as the flag set at {}, but it may have been overwritten at {}",
+                        flow.name(flag), flow.name(clobber));
+                    findings.push(Finding{address, message});
+                }
+            }
+            state.apply(address, opcode);
+        }
+    }
+
+    findings
+}
+
+/// Blocks control can come from, for each block
+fn predecessors(blocks: &[&BasicBlock]) -> HashMap<u16, Vec<u16>> {
+    let mut predecessors: HashMap<u16, Vec<u16>> = HashMap::new();
+    for block in blocks.iter() {
+        for edge in block.successors.iter() {
+            predecessors.entry(edge.target).or_default().push(block.start);
+        }
+    }
+
+    predecessors
+}
+
+/// Registers a subroutine may write, including through the subroutines it calls
+fn subroutine_writes(flow: &ControlFlow, start: u16) -> u32 {
+    let mut seen = vec![start];
+    let mut pending = vec![start];
+    let mut writes = 0;
+
+    while let Some(subroutine) = pending.pop() {
+        for block in flow.subroutine_blocks(subroutine) {
+            for &(_, opcode) in block.instructions.iter() {
+                writes |= effects(opcode).writes;
+
+                let callee = opcode & 0xFFF;
+                if opcode >> 12 == 0x2 && !seen.contains(&callee) {
+                    seen.push(callee);
+                    pending.push(callee);
+                }
+            }
+        }
+    }
+
+    writes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    fn flow(source: &[u8]) -> ControlFlow {
+        let output = Assembler::new().assemble("game.asm", source).unwrap();
+        ControlFlow::build(&output, 0x200)
+    }
+
+    fn messages(findings: Vec<Finding>) -> Vec<(u16, String)> {
+        findings.into_iter().map(|finding| (finding.address, finding.message)).collect()
+    }
+
+    #[test]
+    fn test_effects() {
+        assert_eq!(effects(0x8124), Effects{reads: 0b110, writes: 0b10 | VF, sets_flag: true});
+        assert_eq!(effects(0xF265), Effects{reads: 1 << REG_I, writes: 0b111, sets_flag: false});
+    }
+
+    #[test]
+    fn test_draw_before_ld_i() {
+        let findings = uninitialized(&flow(b"start\tLD V0, 0\n\tDRW V0, V0, 5\n\tLD I, $300\n"));
+
+        assert_eq!(messages(findings), vec![(0x202, String::from("I may be used before it is set"))]);
+    }
+
+    #[test]
+    fn test_set_on_one_path_only() {
+        let findings = uninitialized(&flow(b"start\tRND V0, 1\n\
+                                             \tSE V0, 0\n\
+                                             \tLD V1, 5\n\
+                                             \tADD V1, 1\n"));
+        assert_eq!(messages(findings), vec![(0x206, String::from("V1 may be used before it is set"))]);
+    }
+
+    #[test]
+    fn test_set_by_subroutine() {
+        let findings = uninitialized(&flow(b"start\tCALL #init\n\
+                                             \tDRW V0, V1, 5\n\
+                                             end\tJP #end\n\
+                                             init\tLD I, $300\n\
+                                             \tLD V0, 0\n\
+                                             \tLD V1, 0\n\
+                                             \tRET\n"));
+
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn test_subroutine_inputs() {
+        let findings = uninitialized(&flow(b"start\tLD V0, 1\n\
+                                             \tCALL #sub\n\
+                                             end\tJP #end\n\
+                                             sub\tADD V0, V1\n\
+                                             \tRET\n"));
+
+        assert_eq!(messages(findings), vec![(0x206, String::from("V1 may be used before it is set"))]);
+    }
+
+    #[test]
+    fn test_timers() {
+        let findings = uninitialized(&flow(b"start\tLD V0, DT\n"));
+
+        assert_eq!(messages(findings), vec![(0x200, String::from("DT may be used before it is set"))]);
+    }
+
+    #[test]
+    fn test_vf_clobbered() {
+        let findings = vf_clobbered(&flow(b"start\tLD V0, 1\n\
+                                            \tLD V1, 2\n\
+                                            \tLD I, $300\n\
+                                            \tADD V0, V1\n\
+                                            \tDRW V0, V1, 1\n\
+                                            \tSE VF, 1\n\
+                                            end\tJP #end\n"));
+
+        assert_eq!(messages(findings), vec![(0x20A, String::from("VF is used as the flag set at start+6, but it may have been overwritten at start+8"))]);
+    }
+
+    #[test]
+    fn test_vf_flag_used_directly() {
+        let findings = vf_clobbered(&flow(b"start\tADD V0, V1\n\tSE VF, 1\n\tDRW V0, V1, 1\nend\tJP #end\n"));
+
+        assert!(findings.is_empty());
+    }
+}
@@ -0,0 +1,536 @@
+use assembler::{AssemblyOutput, ByteKind, SourceMapEntry};
+use flow;
+use interpreter::{Chip8, InterpreterError, CYCLES_PER_FRAME, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, Write};
+
+/// Instructions `continue` runs before giving control back
+const MAX_INSTRUCTIONS: u32 = 1_000_000;
+
+const HELP: &str = "\
+step [n]      (s) execute one or n instructions
+next          (n) execute one instruction, running CALLs to completion
+continue      (c) run until a breakpoint or watch triggers
+break <at>    (b) stop at a label, line, file:line or $address
+delete <at>       remove a breakpoint
+watch <what>  (w) stop when V0-VF, I, DT, ST or the byte at $address changes
+unwatch <what>    remove a watch
+regs          (r) show registers and the stack
+display       (d) show the screen
+key <k>           toggle key 0-F
+info              list breakpoints and watches
+quit          (q) leave the debugger";
+
+/// Something that can be watched for changes
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum WatchTarget {
+    Register(usize),
+    I,
+    DelayTimer,
+    SoundTimer,
+    Memory(u16)
+}
+
+impl WatchTarget {
+    fn parse(name: &str) -> Option<WatchTarget> {
+        let upper = name.to_uppercase();
+        match upper.as_ref() {
+            "I" => Some(WatchTarget::I),
+            "DT" => Some(WatchTarget::DelayTimer),
+            "ST" => Some(WatchTarget::SoundTimer),
+            _ => {
+                if let Some(register) = upper.strip_prefix('V') {
+                    if register.len() == 1 {
+                        return usize::from_str_radix(register, 16).ok().map(WatchTarget::Register);
+                    }
+                }
+                parse_address(name).map(WatchTarget::Memory)
+            }
+        }
+    }
+
+    fn value(&self, chip8: &Chip8) -> u16 {
+        match *self {
+            WatchTarget::Register(x) => chip8.v[x] as u16,
+            WatchTarget::I => chip8.i,
+            WatchTarget::DelayTimer => chip8.dt as u16,
+            WatchTarget::SoundTimer => chip8.st as u16,
+            WatchTarget::Memory(address) => chip8.memory.get(address as usize).cloned().unwrap_or(0) as u16
+        }
+    }
+
+    fn name(&self) -> String {
+        match *self {
+            WatchTarget::Register(x) => format!("V{:X}", x),
+            WatchTarget::I => String::from("I"),
+            WatchTarget::DelayTimer => String::from("DT"),
+            WatchTarget::SoundTimer => String::from("ST"),
+            WatchTarget::Memory(address) => format!("[${:03X}]", address)
+        }
+    }
+}
+
+struct Watch {
+    target: WatchTarget,
+    value: u16
+}
+
+/// Why execution stopped
+enum Stop {
+    Breakpoint,
+    Watch(String),
+    Looping,
+    WaitingForKey,
+    Error(InterpreterError)
+}
+
+/// Runs an assembled program one command at a time
+pub struct Debugger {
+    chip8: Chip8,
+    /// labels ordered by address
+    labels: Vec<(String, u16)>,
+    source_map: Vec<SourceMapEntry>,
+    /// file line numbers refer to when none is given
+    file: String,
+    breakpoints: Vec<u16>,
+    watches: Vec<Watch>,
+    cycles: u32
+}
+
+impl Debugger {
+    /// Debugger for a program assembled from `file`, run on `chip8` with its image placed at `base`.
+    /// It starts where the control flow analysis says the program does
+    pub fn new(output: &AssemblyOutput, file: &str, base: u16, mut chip8: Chip8) -> Result<Self, InterpreterError> {
+        chip8.load(base, &output.bytes)?;
+        chip8.pc = flow::entry_point(output);
+
+        let mut labels: Vec<(String, u16)> = output.symbols.iter().map(|(label, &address)| (label.clone(), address as u16)).collect();
+        labels.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+
+        Ok(Debugger {
+            chip8,
+            labels,
+            source_map: output.source_map.clone(),
+            file: String::from(file),
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            cycles: 0
+        })
+    }
+
+    /// The machine being debugged
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    /// Read commands until `quit` or the end of input
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "{}", self.current_location())?;
+
+        for line in input.lines() {
+            let line = line?;
+            let command = line.trim();
+            if command == "quit" || command == "q" {
+                break;
+            }
+            if !command.is_empty() {
+                writeln!(output, "{}", self.execute(command))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Execute a single command and describe the result
+    pub fn execute(&mut self, command: &str) -> String {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let argument = words.next();
+
+        match (name, argument) {
+            ("step", count) | ("s", count) => {
+                match count.map(|count| count.parse::<u32>()) {
+                    None => self.step(1),
+                    Some(Ok(count)) if count > 0 => self.step(count),
+                    _ => String::from("Expected a number of instructions")
+                }
+            },
+            ("next", None) | ("n", None) => self.next(),
+            ("continue", None) | ("c", None) => self.resume(None),
+            ("break", Some(at)) | ("b", Some(at)) => {
+                match self.resolve(at) {
+                    Ok(address) => {
+                        if !self.breakpoints.contains(&address) {
+                            self.breakpoints.push(address);
+                        }
+                        format!("Breakpoint at {}", self.describe(address))
+                    },
+                    Err(message) => message
+                }
+            },
+            ("delete", Some(at)) => {
+                match self.resolve(at) {
+                    Ok(address) => {
+                        self.breakpoints.retain(|&breakpoint| breakpoint != address);
+                        format!("Deleted breakpoint at {}", self.describe(address))
+                    },
+                    Err(message) => message
+                }
+            },
+            ("watch", Some(what)) | ("w", Some(what)) => {
+                match WatchTarget::parse(what) {
+                    Some(target) => {
+                        let value = target.value(&self.chip8);
+                        self.watches.retain(|watch| watch.target != target);
+                        self.watches.push(Watch{target, value});
+                        format!("Watching {} = ${:02X}", target.name(), value)
+                    },
+                    None => format!("Cannot watch {}", what)
+                }
+            },
+            ("unwatch", Some(what)) => {
+                match WatchTarget::parse(what) {
+                    Some(target) => {
+                        self.watches.retain(|watch| watch.target != target);
+                        format!("Stopped watching {}", target.name())
+                    },
+                    None => format!("Cannot watch {}", what)
+                }
+            },
+            ("regs", None) | ("r", None) => self.registers(),
+            ("display", None) | ("d", None) => self.display(),
+            ("key", Some(key)) => {
+                match u8::from_str_radix(key, 16) {
+                    Ok(key) if key < 16 => {
+                        let pressed = !self.chip8.keys[key as usize];
+                        self.chip8.keys[key as usize] = pressed;
+                        format!("Key {:X} {}", key, if pressed { "down" } else { "up" })
+                    },
+                    _ => format!("Invalid key: {}", key)
+                }
+            },
+            ("info", None) => self.info(),
+            ("help", None) | ("h", None) => String::from(HELP),
+            _ => format!("Unknown command: {}. Type help for a list of commands", command)
+        }
+    }
+
+    /// Address as `label+offset`, or plain hex when no label comes before it
+    pub fn symbolize(&self, address: u16) -> String {
+        match self.labels.iter().rev().find(|&&(_, label_address)| label_address <= address) {
+            Some(&(ref label, label_address)) if label_address == address => label.clone(),
+            Some(&(ref label, label_address)) => format!("{}+{}", label, address - label_address),
+            None => format!("${:03X}", address)
+        }
+    }
+
+    fn step(&mut self, count: u32) -> String {
+        for _ in 0..count {
+            let mut changes = Vec::new();
+            if let Err(e) = self.execute_instruction(&mut changes) {
+                return format!("{}\n{}", e, self.current_location());
+            }
+            if !changes.is_empty() {
+                return format!("{}\n{}", changes.join("\n"), self.current_location());
+            }
+        }
+
+        self.current_location()
+    }
+
+    fn next(&mut self) -> String {
+        // run a subroutine until it returns to the instruction after the call
+        if self.chip8.opcode() & 0xF000 == 0x2000 {
+            let depth = self.chip8.stack.len();
+            let target = self.chip8.pc.wrapping_add(2);
+            self.resume(Some((target, depth)))
+        }
+        else {
+            self.step(1)
+        }
+    }
+
+    /// Run until something stops execution, or until the program reaches `until` at the given stack depth
+    fn resume(&mut self, until: Option<(u16, usize)>) -> String {
+        let mut stop = None;
+        for _ in 0..MAX_INSTRUCTIONS {
+            let previous = self.chip8.pc;
+            let mut changes = Vec::new();
+            if let Err(e) = self.execute_instruction(&mut changes) {
+                stop = Some(Stop::Error(e));
+                break;
+            }
+
+            let pc = self.chip8.pc;
+            if !changes.is_empty() {
+                stop = Some(Stop::Watch(changes.join("\n")));
+                break;
+            }
+            if let Some((target, depth)) = until {
+                if pc == target && self.chip8.stack.len() == depth {
+                    return self.current_location();
+                }
+            }
+            if self.breakpoints.contains(&pc) {
+                stop = Some(Stop::Breakpoint);
+                break;
+            }
+            // drawing waits for the next frame with the display wait quirk
+            if pc == previous && self.chip8.opcode() & 0xF000 != 0xD000 {
+                stop = if self.chip8.opcode() & 0xF0FF == 0xF00A { Some(Stop::WaitingForKey) } else { Some(Stop::Looping) };
+                break;
+            }
+        }
+
+        let reason = match stop {
+            Some(Stop::Breakpoint) => String::from("Breakpoint"),
+            Some(Stop::Watch(changes)) => changes,
+            Some(Stop::Looping) => String::from("Program is looping in place"),
+            Some(Stop::WaitingForKey) => String::from("Waiting for a key"),
+            Some(Stop::Error(e)) => e.to_string(),
+            None => format!("Stopped after {} instructions", MAX_INSTRUCTIONS)
+        };
+
+        format!("{}\n{}", reason, self.current_location())
+    }
+
+    /// Execute one instruction, keep the timers running and collect watch changes
+    fn execute_instruction(&mut self, changes: &mut Vec<String>) -> Result<(), InterpreterError> {
+        self.chip8.step()?;
+
+        self.cycles += 1;
+        if self.cycles == CYCLES_PER_FRAME {
+            self.cycles = 0;
+            self.chip8.tick_timers();
+        }
+
+        for watch in self.watches.iter_mut() {
+            let value = watch.target.value(&self.chip8);
+            if value != watch.value {
+                changes.push(format!("{} changed from ${:02X} to ${:02X}", watch.target.name(), watch.value, value));
+                watch.value = value;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find the address of a label, $address, line or file:line
+    fn resolve(&self, at: &str) -> Result<u16, String> {
+        if let Some(&(_, address)) = self.labels.iter().find(|(label, _)| label == at) {
+            return Ok(address);
+        }
+        if let Some(address) = parse_address(at) {
+            return Ok(address);
+        }
+
+        let (file, line) = match at.rfind(':') {
+            Some(i) => (&at[..i], &at[i + 1..]),
+            None => (self.file.as_ref(), at)
+        };
+        let line = line.parse::<usize>().map_err(|_| format!("Unknown label: {}", at))?;
+
+        self.source_map.iter()
+            .filter(|entry| entry.kind == ByteKind::Code && entry.location.line == line && entry.location.file == file)
+            .map(|entry| entry.address as u16)
+            .min()
+            .ok_or_else(|| format!("No code at {}:{}", file, line))
+    }
+
+    /// Address with its label and source location
+    fn describe(&self, address: u16) -> String {
+        let mut description = format!("${:03X} {}", address, self.symbolize(address));
+
+        let entry = self.source_map.iter().find(|entry| entry.address as u16 <= address && address < (entry.address + entry.size) as u16);
+        if let Some(entry) = entry {
+            write!(description, " ({})", entry.location).unwrap();
+        }
+
+        description
+    }
+
+    fn current_location(&self) -> String {
+        format!("{}: {:04X}", self.describe(self.chip8.pc), self.chip8.opcode())
+    }
+
+    fn registers(&self) -> String {
+        let chip8 = &self.chip8;
+        let mut out = String::new();
+
+        for (row, values) in chip8.v.chunks(8).enumerate() {
+            let registers: Vec<String> = values.iter().enumerate()
+                .map(|(i, value)| format!("V{:X}=${:02X}", row * 8 + i, value))
+                .collect();
+            writeln!(out, "{}", registers.join(" ")).unwrap();
+        }
+
+        writeln!(out, "I=${:03X} ({})  PC=${:03X} ({})  DT=${:02X} ST=${:02X}",
+            chip8.i, self.symbolize(chip8.i), chip8.pc, self.symbolize(chip8.pc), chip8.dt, chip8.st).unwrap();
+
+        if chip8.stack.is_empty() {
+            write!(out, "Stack: empty").unwrap();
+        }
+        else {
+            let frames: Vec<String> = chip8.stack.iter().rev()
+                .map(|&address| format!("${:03X} ({})", address, self.symbolize(address)))
+                .collect();
+            write!(out, "Stack: {}", frames.join(", ")).unwrap();
+        }
+
+        out
+    }
+
+    fn display(&self) -> String {
+        let rows: Vec<String> = (0..DISPLAY_HEIGHT)
+            .map(|y| (0..DISPLAY_WIDTH).map(|x| if self.chip8.pixel(x, y) { '#' } else { '.' }).collect())
+            .collect();
+
+        rows.join("\n")
+    }
+
+    fn info(&self) -> String {
+        let mut lines = Vec::new();
+        for &address in self.breakpoints.iter() {
+            lines.push(format!("Breakpoint at {}", self.describe(address)));
+        }
+        for watch in self.watches.iter() {
+            lines.push(format!("Watching {} = ${:02X}", watch.target.name(), watch.value));
+        }
+
+        if lines.is_empty() {
+            String::from("No breakpoints or watches")
+        }
+        else {
+            lines.join("\n")
+        }
+    }
+}
+
+/// Parse a `$` prefixed hex address
+fn parse_address(value: &str) -> Option<u16> {
+    value.strip_prefix('$').and_then(|hex| u16::from_str_radix(hex, 16).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::{Assembler, Target};
+
+    const PROGRAM: &[u8] = b"start\tLD V0, 1\n\
+                             \tCALL #sub\n\
+                             \tLD V1, 2\n\
+                             end\tJP #end\n\
+                             sub\tADD V0, 1\n\
+                             \tRET\n";
+
+    fn debugger(source: &[u8]) -> Debugger {
+        let output = Assembler::new().assemble("game.asm", source).unwrap();
+        Debugger::new(&output, "game.asm", 0x200, Chip8::new(4096)).unwrap()
+    }
+
+    #[test]
+    fn test_step() {
+        let mut debugger = debugger(PROGRAM);
+
+        assert_eq!(debugger.execute("step"), "$202 start+2 (game.asm:2:2): 2208");
+        assert_eq!(debugger.chip8().v[0], 1);
+        assert_eq!(debugger.execute("s 2"), "$20A sub+2 (game.asm:6:2): 00EE");
+    }
+
+    #[test]
+    fn test_next_runs_call() {
+        let mut debugger = debugger(PROGRAM);
+        debugger.execute("step");
+
+        assert_eq!(debugger.execute("next"), "$204 start+4 (game.asm:3:2): 6102");
+        assert_eq!(debugger.chip8().v[0], 2);
+    }
+
+    #[test]
+    fn test_starts_at_base() {
+        let output = Assembler::new().base_address(0x300).assemble("game.asm", b"\torg $300\nstart\tLD V0, 1\n").unwrap();
+        let mut debugger = Debugger::new(&output, "game.asm", 0x300, Chip8::new(4096)).unwrap();
+
+        assert_eq!(debugger.chip8().pc, 0x300);
+        assert_eq!(debugger.execute("step"), "$302 start+2: 0000");
+        assert_eq!(debugger.chip8().v[0], 1);
+    }
+
+    #[test]
+    fn test_next_at_end_of_memory() {
+        let output = Assembler::new().target(Target::XoChip).base_address(0)
+            .assemble("game.asm", b"\tCLS\nsub\tRET\n\torg $FFFE\n\tCALL #sub\n").unwrap();
+        let mut debugger = Debugger::new(&output, "game.asm", 0, Chip8::new(0x10000)).unwrap();
+        debugger.chip8.pc = 0xFFFE;
+
+        assert_eq!(debugger.execute("next"), "$000 $000 (game.asm:1:2): 00E0");
+    }
+
+    #[test]
+    fn test_continue_until_loop() {
+        let mut debugger = debugger(PROGRAM);
+
+        assert_eq!(debugger.execute("continue"), "Program is looping in place\n$206 end (game.asm:4:5): 1206");
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = debugger(PROGRAM);
+
+        assert_eq!(debugger.execute("break sub"), "Breakpoint at $208 sub (game.asm:5:5)");
+        assert_eq!(debugger.execute("b 3"), "Breakpoint at $204 start+4 (game.asm:3:2)");
+        assert_eq!(debugger.execute("c"), "Breakpoint\n$208 sub (game.asm:5:5): 7001");
+        assert_eq!(debugger.execute("c"), "Breakpoint\n$204 start+4 (game.asm:3:2): 6102");
+
+        assert_eq!(debugger.execute("delete sub"), "Deleted breakpoint at $208 sub (game.asm:5:5)");
+        assert_eq!(debugger.execute("b nowhere"), "Unknown label: nowhere");
+        assert_eq!(debugger.execute("b game.asm:40"), "No code at game.asm:40");
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut debugger = debugger(PROGRAM);
+
+        assert_eq!(debugger.execute("watch v1"), "Watching V1 = $00");
+        assert_eq!(debugger.execute("c"), "V1 changed from $00 to $02\n$206 end (game.asm:4:5): 1206");
+        assert_eq!(debugger.execute("w nothing"), "Cannot watch nothing");
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut debugger = debugger(PROGRAM);
+        debugger.execute("s 2");
+
+        let registers = debugger.execute("regs");
+        let lines: Vec<&str> = registers.lines().collect();
+
+        assert_eq!(lines[0], "V0=$01 V1=$00 V2=$00 V3=$00 V4=$00 V5=$00 V6=$00 V7=$00");
+        assert_eq!(lines[2], "I=$000 ($000)  PC=$208 (sub)  DT=$00 ST=$00");
+        assert_eq!(lines[3], "Stack: $204 (start+4)");
+    }
+
+    #[test]
+    fn test_display() {
+        let mut debugger = debugger(b"\tLD F, V0\n\tDRW V0, V0, 5\n");
+        debugger.execute("s 2");
+
+        let display = debugger.execute("display");
+        let rows: Vec<&str> = display.lines().collect();
+
+        assert_eq!(rows.len(), DISPLAY_HEIGHT);
+        assert!(rows[0].starts_with("####...."));
+        assert!(rows[1].starts_with("#..#...."));
+    }
+
+    #[test]
+    fn test_run_commands() {
+        let mut debugger = debugger(PROGRAM);
+        let mut output = Vec::new();
+        debugger.run(&b"step\nbogus\nquit\nstep\n"[..], &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "$200 start (game.asm:1:7): 6001\n\
+                                                        $202 start+2 (game.asm:2:2): 2208\n\
+                                                        Unknown command: bogus. Type help for a list of commands\n");
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// One line of a disassembly: an instruction, or bytes that do not decode to one
+#[derive(Debug, PartialEq, Clone)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "${:03X}  {:<4}  {}", self.address, bytes.join(""), self.text)
+    }
+}
+
+/// Decode a program image placed at `base`, two bytes at a time. Addresses that
+/// have a label in `symbols` are written as the label
+pub fn disassemble(image: &[u8], base: u16, symbols: &HashMap<String, u32>) -> Vec<Line> {
+    let names: HashMap<u16, &str> = symbols.iter().map(|(label, &address)| (address as u16, label.as_ref())).collect();
+    let address_name = |address: u16| match names.get(&address) {
+        Some(label) => format!("#{}", label),
+        None => format!("${:03X}", address)
+    };
+
+    image.chunks(2).enumerate().map(|(i, bytes)| {
+        let address = base + 2 * i as u16;
+        let text = match *bytes {
+            [msb, lsb] => decode((msb as u16) << 8 | lsb as u16, &address_name),
+            _ => None
+        };
+
+        Line {
+            address,
+            bytes: bytes.to_vec(),
+            text: text.unwrap_or_else(|| {
+                let data: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+                format!("db {}", data.join(" "))
+            })
+        }
+    }).collect()
+}
+
+/// The instruction an opcode encodes, in the syntax of the assembler
+pub fn mnemonic(opcode: u16) -> Option<String> {
+    decode(opcode, &|address| format!("${:03X}", address))
+}
+
+fn decode(opcode: u16, address: &dyn Fn(u16) -> String) -> Option<String> {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let kk = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    let text = match (opcode >> 12, n) {
+        (0x0, _) if opcode == 0x00E0 => String::from("CLS"),
+        (0x0, _) if opcode == 0x00EE => String::from("RET"),
+        (0x0, _) => format!("SYS {}", address(nnn)),
+        (0x1, _) => format!("JP {}", address(nnn)),
+        (0x2, _) => format!("CALL {}", address(nnn)),
+        (0x3, _) => format!("SE V{:X}, ${:02X}", x, kk),
+        (0x4, _) => format!("SNE V{:X}, ${:02X}", x, kk),
+        (0x5, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x6, _) => format!("LD V{:X}, ${:02X}", x, kk),
+        (0x7, _) => format!("ADD V{:X}, ${:02X}", x, kk),
+        (0x8, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _) => format!("LD I, {}", address(nnn)),
+        (0xB, _) => format!("JR {}", address(nnn)),
+        (0xC, _) => format!("RND V{:X}, ${:02X}", x, kk),
+        (0xD, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _) if kk == 0x9E => format!("SKP V{:X}", x),
+        (0xE, _) if kk == 0xA1 => format!("SKNP V{:X}", x),
+        (0xF, _) => match kk {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => return None
+        },
+        _ => return None
+    };
+
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mnemonic() {
+        assert_eq!(mnemonic(0x00E0), Some(String::from("CLS")));
+        assert_eq!(mnemonic(0x8124), Some(String::from("ADD V1, V2")));
+        assert_eq!(mnemonic(0xD125), Some(String::from("DRW V1, V2, 5")));
+        assert_eq!(mnemonic(0xF233), Some(String::from("LD B, V2")));
+        assert_eq!(mnemonic(0x5121), None);
+        assert_eq!(mnemonic(0xE1FF), None);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let mut symbols = HashMap::new();
+        symbols.insert(String::from("end"), 0x202);
+
+        let lines: Vec<String> = disassemble(&[0x60, 0x03, 0x12, 0x02, 0xFF, 0xFF, 0xF0], 0x200, &symbols)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+
+        assert_eq!(lines, vec![
+            "$200  6003  LD V0, $03",
+            "$202  1202  JP #end",
+            "$204  FFFF  db $FF $FF",
+            "$206  F0    db $F0"
+        ]);
+    }
+}
@@ -0,0 +1,364 @@
+use assembler::{AssemblyOutput, ByteKind};
+use interpreter::ENTRY_POINT;
+
+use std::collections::HashSet;
+
+/// How control gets from one block to another
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EdgeKind {
+    /// the next instruction in memory
+    Fallthrough,
+    /// JP
+    Jump,
+    /// the instruction after the next one, when SE, SNE, SKP or SKNP skip
+    Skip,
+    /// JR, to the address plus V0: any jump of the table at the address
+    Computed
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Skip => "skip",
+            EdgeKind::Computed => "computed"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind
+}
+
+/// Instructions that always run one after the other
+#[derive(Debug, PartialEq, Clone)]
+pub struct BasicBlock {
+    pub start: u16,
+    /// address after the last instruction
+    pub end: u16,
+    /// address and opcode of each instruction
+    pub instructions: Vec<(u16, u16)>,
+    pub successors: Vec<Edge>,
+    /// control can get here from the entry point
+    pub reachable: bool
+}
+
+/// A CALL from one subroutine to another
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Call {
+    /// address of the CALL instruction
+    pub address: u16,
+    /// start of the subroutine making the call
+    pub caller: u16,
+    pub callee: u16
+}
+
+/// Basic blocks and call graph of an assembled program
+pub struct ControlFlow {
+    pub entry: u16,
+    pub blocks: Vec<BasicBlock>,
+    /// entry point and every CALL target, in address order
+    pub subroutines: Vec<u16>,
+    pub calls: Vec<Call>,
+    /// labels ordered by address
+    labels: Vec<(String, u16)>
+}
+
+impl ControlFlow {
+    /// Build the graphs of a program whose image starts at `base`
+    pub fn build(output: &AssemblyOutput, base: u16) -> Self {
+        let code = code_addresses(output);
+
+        let opcode = |address: u16| {
+            let i = (address - base) as usize;
+            ((output.bytes[i] as u16) << 8) | output.bytes.get(i + 1).cloned().unwrap_or(0) as u16
+        };
+        let instructions: Vec<(u16, u16)> = code.iter().map(|&address| (address, opcode(address))).collect();
+
+        let mut labels: Vec<(String, u16)> = output.symbols.iter().map(|(label, &address)| (label.clone(), address as u16)).collect();
+        labels.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+
+        let entry = entry_point(output);
+        let blocks = build_blocks(&instructions, entry);
+
+        let mut flow = ControlFlow {
+            entry,
+            blocks,
+            subroutines: Vec::new(),
+            calls: Vec::new(),
+            labels
+        };
+        flow.find_calls();
+        flow.mark_reachable();
+
+        flow
+    }
+
+    /// The block starting at `address`
+    pub fn block_at(&self, address: u16) -> Option<&BasicBlock> {
+        self.blocks.iter().find(|block| block.start == address)
+    }
+
+    /// The block containing `address`
+    pub fn block_containing(&self, address: u16) -> Option<&BasicBlock> {
+        self.blocks.iter().find(|block| block.start <= address && address < block.end)
+    }
+
+    /// Address as `label+offset`, or plain hex when no label comes before it
+    pub fn name(&self, address: u16) -> String {
+        match self.labels.iter().rev().find(|&&(_, label_address)| label_address <= address) {
+            Some((label, label_address)) if *label_address == address => label.clone(),
+            Some((label, label_address)) => format!("{}+{}", label, address - label_address),
+            None => format!("${:03X}", address)
+        }
+    }
+
+    /// Blocks control flows through within a subroutine, without following calls
+    pub fn subroutine_blocks(&self, start: u16) -> Vec<&BasicBlock> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![start];
+        let mut blocks = Vec::new();
+
+        while let Some(address) = pending.pop() {
+            if !seen.insert(address) {
+                continue;
+            }
+            if let Some(block) = self.block_at(address) {
+                pending.extend(block.successors.iter().map(|edge| edge.target));
+                blocks.push(block);
+            }
+        }
+
+        blocks.sort_by_key(|block| block.start);
+        blocks
+    }
+
+    fn find_calls(&mut self) {
+        let mut subroutines = vec![self.entry];
+        let mut i = 0;
+        while i < subroutines.len() {
+            let caller = subroutines[i];
+            let mut calls: Vec<Call> = self.subroutine_blocks(caller).iter()
+                .flat_map(|block| block.instructions.iter())
+                .filter(|&&(_, opcode)| opcode >> 12 == 0x2)
+                .map(|&(address, opcode)| Call{address, caller, callee: opcode & 0xFFF})
+                .collect();
+
+            for call in calls.iter() {
+                if !subroutines.contains(&call.callee) {
+                    subroutines.push(call.callee);
+                }
+            }
+            self.calls.append(&mut calls);
+            i += 1;
+        }
+
+        subroutines.sort();
+        self.subroutines = subroutines;
+    }
+
+    fn mark_reachable(&mut self) {
+        let reachable: HashSet<u16> = self.subroutines.iter()
+            .flat_map(|&start| self.subroutine_blocks(start).iter().map(|block| block.start).collect::<Vec<u16>>())
+            .collect();
+
+        for block in self.blocks.iter_mut() {
+            block.reachable = reachable.contains(&block.start);
+        }
+    }
+}
+
+/// Where a program starts running: the usual entry point when there is code there,
+/// otherwise its first instruction
+pub fn entry_point(output: &AssemblyOutput) -> u16 {
+    let code = code_addresses(output);
+    if code.contains(&ENTRY_POINT) || code.is_empty() { ENTRY_POINT } else { code[0] }
+}
+
+/// Address of every instruction, in order
+fn code_addresses(output: &AssemblyOutput) -> Vec<u16> {
+    let mut code: Vec<u16> = output.source_map.iter()
+        .filter(|entry| entry.kind == ByteKind::Code)
+        .flat_map(|entry| (entry.address..entry.address + entry.size).step_by(2).map(|address| address as u16))
+        .collect();
+    code.sort();
+    code.dedup();
+
+    code
+}
+
+/// Split the instructions into basic blocks and link them up. Timing analysis splits
+/// routines the same way, so the blocks it reports are these
+pub fn build_blocks(instructions: &[(u16, u16)], entry: u16) -> Vec<BasicBlock> {
+    // blocks start at the entry point, at jump targets and after anything that changes the flow
+    // addresses wrap at the end of XO-CHIP's 64K, as the interpreter's program counter does
+    let mut leaders = vec![entry];
+    for &(address, opcode) in instructions.iter() {
+        match opcode >> 12 {
+            0x1 | 0x2 | 0xB => {
+                leaders.push(opcode & 0xFFF);
+                if opcode >> 12 != 0x2 {
+                    leaders.push(address.wrapping_add(2));
+                }
+            },
+            0x3 | 0x4 | 0x5 | 0x9 | 0xE => {
+                leaders.push(address.wrapping_add(2));
+                leaders.push(address.wrapping_add(4));
+            },
+            0x0 if opcode == 0x00EE => leaders.push(address.wrapping_add(2)),
+            _ => {}
+        }
+    }
+    let leaders: HashSet<u16> = leaders.into_iter().collect();
+
+    let mut blocks: Vec<BasicBlock> = Vec::new();
+    for &(address, opcode) in instructions.iter() {
+        let new_block = match blocks.last() {
+            Some(block) => block.end != address || leaders.contains(&address),
+            None => true
+        };
+        if new_block {
+            blocks.push(BasicBlock{start: address, end: address, instructions: Vec::new(), successors: Vec::new(), reachable: false});
+        }
+
+        let block = blocks.last_mut().unwrap();
+        block.end = address.wrapping_add(2);
+        block.instructions.push((address, opcode));
+    }
+
+    for block in blocks.iter_mut() {
+        let (address, opcode) = *block.instructions.last().unwrap();
+        let next = address.wrapping_add(2);
+
+        block.successors = match opcode >> 12 {
+            0x0 if opcode == 0x00EE => vec![],
+            0x1 => vec![Edge{target: opcode & 0xFFF, kind: EdgeKind::Jump}],
+            0xB => jump_table(instructions, opcode & 0xFFF),
+            0x3 | 0x4 | 0x5 | 0x9 | 0xE => vec![
+                Edge{target: next, kind: EdgeKind::Fallthrough},
+                Edge{target: next.wrapping_add(2), kind: EdgeKind::Skip}
+            ],
+            _ => vec![Edge{target: next, kind: EdgeKind::Fallthrough}]
+        };
+    }
+
+    blocks
+}
+
+/// Edges of a JR to `table`. The table is the run of JP instructions one after the
+/// other from there, and V0 can pick any of them
+fn jump_table(instructions: &[(u16, u16)], table: u16) -> Vec<Edge> {
+    let mut edges = vec![Edge{target: table, kind: EdgeKind::Computed}];
+
+    let start = instructions.iter().position(|&(address, _)| address == table).unwrap_or(instructions.len());
+    let mut next = table;
+    for &(address, opcode) in instructions[start..].iter() {
+        if address != next || opcode >> 12 != 0x1 {
+            break;
+        }
+        if address != table {
+            edges.push(Edge{target: address, kind: EdgeKind::Computed});
+        }
+        next = address.wrapping_add(2);
+    }
+
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::{Assembler, Target};
+
+    fn flow(source: &[u8]) -> ControlFlow {
+        let output = Assembler::new().assemble("game.asm", source).unwrap();
+        ControlFlow::build(&output, 0x200)
+    }
+
+    #[test]
+    fn test_blocks() {
+        let flow = flow(b"start\tLD V0, 0\n\
+                          loop\tADD V0, 1\n\
+                          \tSE V0, 10\n\
+                          \tJP #loop\n\
+                          end\tJP #end\n");
+
+        let starts: Vec<u16> = flow.blocks.iter().map(|block| block.start).collect();
+        assert_eq!(starts, vec![0x200, 0x202, 0x206, 0x208]);
+
+        assert_eq!(flow.blocks[0].successors, vec![Edge{target: 0x202, kind: EdgeKind::Fallthrough}]);
+        assert_eq!(flow.blocks[1].successors, vec![
+            Edge{target: 0x206, kind: EdgeKind::Fallthrough},
+            Edge{target: 0x208, kind: EdgeKind::Skip}
+        ]);
+        assert_eq!(flow.blocks[2].successors, vec![Edge{target: 0x202, kind: EdgeKind::Jump}]);
+        assert!(flow.blocks.iter().all(|block| block.reachable));
+    }
+
+    #[test]
+    fn test_calls() {
+        let flow = flow(b"start\tCALL #draw\n\
+                          \tCALL #draw\n\
+                          end\tJP #end\n\
+                          draw\tCALL #sprite\n\
+                          \tRET\n\
+                          sprite\tRET\n");
+
+        assert_eq!(flow.subroutines, vec![0x200, 0x206, 0x20A]);
+        assert_eq!(flow.calls, vec![
+            Call{address: 0x200, caller: 0x200, callee: 0x206},
+            Call{address: 0x202, caller: 0x200, callee: 0x206},
+            Call{address: 0x206, caller: 0x206, callee: 0x20A}
+        ]);
+        assert_eq!(flow.name(0x208), "draw+2");
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let flow = flow(b"start\tJP #start\n\
+                          \tCLS\n\
+                          unused\tRET\n");
+
+        let reachable: Vec<(u16, bool)> = flow.blocks.iter().map(|block| (block.start, block.reachable)).collect();
+        assert_eq!(reachable, vec![(0x200, true), (0x202, false)]);
+    }
+
+    #[test]
+    fn test_jump_table() {
+        let flow = flow(b"start\tJR #table\n\
+                          table\tJP #left\n\
+                          \tJP #right\n\
+                          left\tCLS\n\
+                          \tJP #start\n\
+                          right\tJP #start\n");
+
+        assert_eq!(flow.blocks[0].successors, vec![
+            Edge{target: 0x202, kind: EdgeKind::Computed},
+            Edge{target: 0x204, kind: EdgeKind::Computed}
+        ]);
+        assert!(flow.blocks.iter().all(|block| block.reachable));
+    }
+
+    #[test]
+    fn test_code_at_end_of_memory() {
+        let output = Assembler::new().target(Target::XoChip).base_address(0).assemble("game.asm", b"\torg $FFFE\n\tCLS\n").unwrap();
+        let flow = ControlFlow::build(&output, 0);
+
+        assert_eq!(flow.blocks.len(), 1);
+        assert_eq!(flow.blocks[0].end, 0);
+        assert_eq!(flow.blocks[0].successors, vec![Edge{target: 0, kind: EdgeKind::Fallthrough}]);
+    }
+
+    #[test]
+    fn test_data_ends_block() {
+        let flow = flow(b"start\tCLS\nsprite\n\tdb $F0 $90\n");
+
+        assert_eq!(flow.blocks.len(), 1);
+        assert_eq!(flow.blocks[0].successors, vec![Edge{target: 0x202, kind: EdgeKind::Fallthrough}]);
+        assert!(flow.block_at(0x202).is_none());
+        assert_eq!(flow.block_containing(0x200).unwrap().start, 0x200);
+    }
+}
@@ -0,0 +1,269 @@
+use assembler::diagnostic::{Diagnostic, Fix, Location, Severity};
+use assembler::lexer::{self, LosslessTokens, Position, Token, Trivia, TriviaToken, DIRECTIVES, INSTRUCTIONS, REGISTERS};
+use assembler::parser;
+
+/// Column instructions and directives start at
+const STATEMENT_COLUMN: usize = 12;
+
+/// Column operands start at, past the longest mnemonic or directive
+const OPERAND_COLUMN: usize = 20;
+
+/// Column comments after code start at
+const COMMENT_COLUMN: usize = 40;
+
+/// Lay out a source file the same way throughout: labels on the left, then
+/// mnemonics, operands and comments each starting at the same column. Instructions
+/// and registers are upper case and directives lower case. The source is read with
+/// the lexer, which keeps comments and blank lines as trivia, and checked with the
+/// parser, so code they reject is an error naming `file`
+pub fn format(file: &str, source: &str) -> Result<String, Box<Diagnostic>> {
+    let source = normalize_case(source);
+    let error = |position: Position, code: &'static str, message: &str, fixes: Vec<Fix>| {
+        // the location covers the rest of the code on the line
+        let line = source.lines().nth(position.line - 1).unwrap_or("");
+        let rest = line.get(position.column - 1..).unwrap_or("");
+        let length = rest.split(';').next().unwrap_or("").trim_end().len();
+        let location = Location {
+            file: String::from(file),
+            line: position.line,
+            column: position.column,
+            end_column: position.column + length.max(1)
+        };
+        Box::new(Diagnostic::new(Severity::Error, code, message, Some(location)).with_fixes(fixes))
+    };
+
+    let lossless = lexer::tokenize_with_trivia(source.as_bytes())
+        .map_err(|e| error(e.position(), e.code(), e.message(), e.fixes().to_vec()))?;
+    let tokens = lossless.tokens.iter().map(|token| (token.position, token.token.clone())).collect();
+    parser::parse_statements(tokens).map_err(|e| {
+        let first = lossless.tokens.first().map(|token| token.position).unwrap_or(Position::new(1, 1));
+        let position = if e.position().line == 0 { first } else { e.position() };
+        error(position, e.code(), e.message(), vec![])
+    })?;
+
+    let mut out = String::new();
+    for line in lines(&lossless).iter() {
+        out.push_str(&line.format());
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// The tokens and comment of a line of source
+#[derive(Default)]
+struct Line<'a> {
+    tokens: Vec<&'a TriviaToken>,
+    comment: Option<&'a str>,
+    /// the line starts with whitespace
+    indented: bool
+}
+
+impl<'a> Line<'a> {
+    /// The line laid out in columns. Tokens keep their spelling, so `$0a` stays `$0a`
+    fn format(&self) -> String {
+        let mut label = String::new();
+        let mut mnemonic = String::new();
+        let mut operands = String::new();
+        for token in self.tokens.iter() {
+            match token.token {
+                Token::Label(_) => label = token.text.clone(),
+                Token::Instruction(_) | Token::Directive(_) => mnemonic = token.text.clone(),
+                Token::Comma => operands.push(','),
+                _ => {
+                    if !operands.is_empty() {
+                        operands.push(' ');
+                    }
+                    operands.push_str(&token.text);
+                }
+            }
+        }
+
+        let mut formatted = label;
+        if !mnemonic.is_empty() {
+            pad(&mut formatted, STATEMENT_COLUMN);
+            formatted.push_str(&mnemonic);
+        }
+        if !operands.is_empty() {
+            pad(&mut formatted, OPERAND_COLUMN);
+            formatted.push_str(&operands);
+        }
+        if let Some(comment) = self.comment {
+            // comments on their own line keep to the left or line up with statements
+            if !formatted.is_empty() {
+                pad(&mut formatted, COMMENT_COLUMN);
+            }
+            else if self.indented {
+                pad(&mut formatted, STATEMENT_COLUMN);
+            }
+            formatted.push_str(comment.trim_end());
+        }
+
+        formatted
+    }
+}
+
+/// Split the tokens into lines at the line breaks in their trivia. A line break at
+/// the end of the source does not start another line
+fn lines(lossless: &LosslessTokens) -> Vec<Line<'_>> {
+    let mut lines = vec![Line::default()];
+
+    for token in lossless.tokens.iter() {
+        for trivia in token.leading.iter() {
+            add_trivia(&mut lines, trivia);
+        }
+        lines.last_mut().unwrap().tokens.push(token);
+        for trivia in token.trailing.iter() {
+            add_trivia(&mut lines, trivia);
+        }
+    }
+    for trivia in lossless.end.iter() {
+        add_trivia(&mut lines, trivia);
+    }
+
+    let last = lines.last().unwrap();
+    if last.tokens.is_empty() && last.comment.is_none() {
+        lines.pop();
+    }
+
+    lines
+}
+
+fn add_trivia<'a>(lines: &mut Vec<Line<'a>>, trivia: &'a Trivia) {
+    let line = lines.last_mut().unwrap();
+    match *trivia {
+        Trivia::LineBreak(_) => lines.push(Line::default()),
+        Trivia::Comment(ref text) => line.comment = Some(text),
+        Trivia::Whitespace(_) => {
+            if line.tokens.is_empty() && line.comment.is_none() {
+                line.indented = true;
+            }
+        }
+    }
+}
+
+/// Instructions and registers in upper case and directives in lower case, so the
+/// lexer reads them however they were written. Labels, label operands, numbers,
+/// strings and comments are left alone
+fn normalize_case(source: &str) -> String {
+    let mut out = String::new();
+    let mut chars = source.chars().peekable();
+    // whether the statement is an instruction, once its first word has been read
+    let mut statement = None;
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                statement = None;
+                line_start = true;
+                out.push(c);
+                continue;
+            },
+            // comments and strings run to their end, or to the end of the line
+            ';' | '"' => {
+                out.push(c);
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    out.push(next);
+                    chars.next();
+                    if c == '"' && next == '"' {
+                        break;
+                    }
+                }
+            },
+            c if c.is_whitespace() || c == ',' => out.push(c),
+            c => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || next == ',' || next == ';' || next == '"' {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+
+                // a word at the start of a line is a label
+                if !line_start && statement.is_none() {
+                    if INSTRUCTIONS.contains(&&word.to_uppercase()[..]) {
+                        word = word.to_uppercase();
+                        statement = Some(true);
+                    }
+                    else {
+                        if DIRECTIVES.contains(&&word.to_lowercase()[..]) {
+                            word = word.to_lowercase();
+                        }
+                        statement = Some(false);
+                    }
+                }
+                else if statement == Some(true) && REGISTERS.contains(&&word.to_uppercase()[..]) {
+                    word = word.to_uppercase();
+                }
+                out.push_str(&word);
+            }
+        }
+        line_start = false;
+    }
+
+    out
+}
+
+/// Pad a line with spaces up to `column`, or with a single space if it is already past it
+fn pad(line: &mut String, column: usize) {
+    if line.len() >= column && !line.is_empty() {
+        line.push(' ');
+    }
+    while line.len() < column {
+        line.push(' ');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let source = "start\tld   v0 ,$FF ; lives\n\
+                      \n\
+                      ; sprites\n  \t; ship\nship\n\tDB $18   $3C\n\
+                      averyverylonglabel\tJP #start\n\
+                      \tinclude \"a  b;c.asm\"\n\
+                      \tsknp v1 ;key\n";
+
+        assert_eq!(format("game.asm", source).unwrap(),
+            "start       LD      V0, $FF             ; lives\n\
+             \n\
+             ; sprites\n            ; ship\nship\n            db      $18 $3C\n\
+             averyverylonglabel JP #start\n            include \"a  b;c.asm\"\n\
+             \x20           SKNP    V1                  ;key\n");
+    }
+
+    #[test]
+    fn test_format_is_stable() {
+        let source = "loop        SE      V0, $0a             ; wait\n            JP      #loop\n";
+
+        assert_eq!(format("game.asm", source).unwrap(), source);
+    }
+
+    #[test]
+    fn test_format_error() {
+        let error = format("game.asm", "start\tCLS\n\tDRAW V0, V1, 5 ; ship\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "game.asm:2:2: error[E001]: Unknown instruction DRAW, did you mean DRW?");
+        assert_eq!(error.location.unwrap().end_column, 16);
+        assert_eq!(error.fixes[0].replacement, "DRW V0, V1, 5");
+    }
+
+    #[test]
+    fn test_normalize_case() {
+        assert_eq!(normalize_case("loop\tld [i], v0"), "loop\tLD [I], V0");
+        assert_eq!(normalize_case("\tORG $200"), "\torg $200");
+        assert_eq!(normalize_case("\tsection f"), "\tsection f");
+        assert_eq!(normalize_case("\tjp #ret"), "\tJP #ret");
+        assert_eq!(normalize_case("\tld v0, 1 ; ld v1\n\tinclude \"Ld v2.asm\"\nx\tse v0,v1"),
+                   "\tLD V0, 1 ; ld v1\n\tinclude \"Ld v2.asm\"\nx\tSE V0,V1");
+    }
+}
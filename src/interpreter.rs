@@ -0,0 +1,625 @@
+use quirks::Quirks;
+
+use std::error::Error;
+use std::fmt;
+
+/// Width of the display in pixels
+pub const DISPLAY_WIDTH: usize = 64;
+/// Height of the display in pixels
+pub const DISPLAY_HEIGHT: usize = 32;
+/// Address execution starts at
+pub const ENTRY_POINT: u16 = 0x200;
+/// Depth of the call stack
+pub const STACK_SIZE: usize = 16;
+/// Instructions executed for every 60Hz timer tick
+pub const CYCLES_PER_FRAME: u32 = 10;
+
+/// Address of the built in hex font, 5 bytes per digit
+const FONT_ADDRESS: usize = 0x000;
+
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+/// The program did something the machine cannot do
+#[derive(Debug, PartialEq)]
+pub struct InterpreterError {
+    address: u16,
+    message: String
+}
+
+impl InterpreterError {
+    /// Address of the instruction that failed
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:03X}: {}", self.address, self.message)
+    }
+}
+
+impl Error for InterpreterError {}
+
+/// Chip8 machine state and instruction execution
+pub struct Chip8 {
+    pub memory: Vec<u8>,
+    /// general purpose registers V0 to VF
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub dt: u8,
+    pub st: u8,
+    /// pixels, row by row
+    pub display: Vec<bool>,
+    pub keys: [bool; 16],
+    pub quirks: Quirks,
+    /// a sprite has been drawn since the last timer tick
+    frame_drawn: bool,
+    rng: u32
+}
+
+impl Chip8 {
+    /// Machine with `memory_size` bytes of memory holding the font, ready to run from the entry point
+    pub fn new(memory_size: usize) -> Self {
+        Chip8::with_quirks(memory_size, Quirks::default())
+    }
+
+    /// Machine behaving like the interpreter described by `quirks`
+    pub fn with_quirks(memory_size: usize, quirks: Quirks) -> Self {
+        let mut memory = vec![0; memory_size];
+        memory[FONT_ADDRESS..FONT_ADDRESS + FONT.len()].copy_from_slice(&FONT);
+
+        Chip8 {
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: ENTRY_POINT,
+            stack: Vec::new(),
+            dt: 0,
+            st: 0,
+            display: vec![false; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            keys: [false; 16],
+            quirks,
+            frame_drawn: false,
+            rng: 0x2A6D_365B
+        }
+    }
+
+    /// Copy a program image into memory at `address`
+    pub fn load(&mut self, address: u16, image: &[u8]) -> Result<(), InterpreterError> {
+        let start = address as usize;
+        if start + image.len() > self.memory.len() {
+            return Err(self.error(address, "Program does not fit in memory"));
+        }
+
+        self.memory[start..start + image.len()].copy_from_slice(image);
+        Ok(())
+    }
+
+    /// The opcode at the program counter
+    pub fn opcode(&self) -> u16 {
+        self.read_opcode(self.pc)
+    }
+
+    /// The opcode at `address`, zero past the end of memory
+    pub fn read_opcode(&self, address: u16) -> u16 {
+        let msb = self.memory.get(address as usize).cloned().unwrap_or(0) as u16;
+        let lsb = self.memory.get(address as usize + 1).cloned().unwrap_or(0) as u16;
+
+        (msb << 8) | lsb
+    }
+
+    /// Whether a pixel is lit
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.display[y * DISPLAY_WIDTH + x]
+    }
+
+    /// Count down the delay and sound timers, called at 60Hz
+    pub fn tick_timers(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+        self.frame_drawn = false;
+    }
+
+    /// Execute the instruction at the program counter
+    pub fn step(&mut self) -> Result<(), InterpreterError> {
+        let address = self.pc;
+        if address as usize + 1 >= self.memory.len() {
+            return Err(self.error(address, "Program counter is outside of memory"));
+        }
+
+        let opcode = self.opcode();
+        let x = ((opcode >> 8) & 0xF) as usize;
+        let y = ((opcode >> 4) & 0xF) as usize;
+        let n = (opcode & 0xF) as u8;
+        let kk = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        // with 64K of memory the last instruction is followed by the first
+        self.pc = self.pc.wrapping_add(2);
+
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => {
+                    for pixel in self.display.iter_mut() {
+                        *pixel = false;
+                    }
+                },
+                0x00EE => {
+                    self.pc = self.stack.pop().ok_or_else(|| self.error(address, "Return with an empty stack"))?;
+                },
+                _ => return Err(self.unknown(address, opcode))
+            },
+            0x1 => self.pc = nnn,
+            0x2 => {
+                if self.stack.len() == STACK_SIZE {
+                    return Err(self.error(address, "Stack overflow"));
+                }
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            },
+            0x3 => self.skip_if(self.v[x] == kk),
+            0x4 => self.skip_if(self.v[x] != kk),
+            0x5 if n == 0 => self.skip_if(self.v[x] == self.v[y]),
+            0x6 => self.v[x] = kk,
+            0x7 => self.v[x] = self.v[x].wrapping_add(kk),
+            0x8 => self.execute_arithmetic(address, opcode, x, y, n)?,
+            0x9 if n == 0 => self.skip_if(self.v[x] != self.v[y]),
+            0xA => self.i = nnn,
+            0xB => {
+                let offset = if self.quirks.jump_vx { self.v[x] } else { self.v[0] };
+                self.pc = nnn + offset as u16;
+            },
+            0xC => self.v[x] = self.random() & kk,
+            0xD => {
+                if self.quirks.display_wait && self.frame_drawn {
+                    // wait for the next frame by running this instruction again
+                    self.pc = address;
+                }
+                else {
+                    self.draw(x, y, n);
+                    self.frame_drawn = true;
+                }
+            },
+            0xE => match kk {
+                0x9E => self.skip_if(self.key_pressed(self.v[x])),
+                0xA1 => self.skip_if(!self.key_pressed(self.v[x])),
+                _ => return Err(self.unknown(address, opcode))
+            },
+            0xF => self.execute_misc(address, opcode, x, kk)?,
+            _ => return Err(self.unknown(address, opcode))
+        }
+
+        Ok(())
+    }
+
+    fn execute_arithmetic(&mut self, address: u16, opcode: u16, x: usize, y: usize, n: u8) -> Result<(), InterpreterError> {
+        match n {
+            0x0 => self.v[x] = self.v[y],
+            0x1 => {
+                self.v[x] |= self.v[y];
+                self.reset_vf();
+            },
+            0x2 => {
+                self.v[x] &= self.v[y];
+                self.reset_vf();
+            },
+            0x3 => {
+                self.v[x] ^= self.v[y];
+                self.reset_vf();
+            },
+            0x4 => {
+                let (result, carry) = self.v[x].overflowing_add(self.v[y]);
+                self.v[x] = result;
+                self.v[0xF] = carry as u8;
+            },
+            0x5 => {
+                let (result, borrow) = self.v[x].overflowing_sub(self.v[y]);
+                self.v[x] = result;
+                self.v[0xF] = !borrow as u8;
+            },
+            0x6 => {
+                let source = self.v[self.shift_source(x, y)];
+                self.v[x] = source >> 1;
+                self.v[0xF] = source & 0x01;
+            },
+            0x7 => {
+                let (result, borrow) = self.v[y].overflowing_sub(self.v[x]);
+                self.v[x] = result;
+                self.v[0xF] = !borrow as u8;
+            },
+            0xE => {
+                let source = self.v[self.shift_source(x, y)];
+                self.v[x] = source << 1;
+                self.v[0xF] = source >> 7;
+            },
+            _ => return Err(self.unknown(address, opcode))
+        }
+
+        Ok(())
+    }
+
+    fn execute_misc(&mut self, address: u16, opcode: u16, x: usize, kk: u8) -> Result<(), InterpreterError> {
+        match kk {
+            0x07 => self.v[x] = self.dt,
+            0x0A => {
+                // wait by running this instruction again until a key is down
+                match self.keys.iter().position(|&key| key) {
+                    Some(key) => self.v[x] = key as u8,
+                    None => self.pc = address
+                }
+            },
+            0x15 => self.dt = self.v[x],
+            0x18 => self.st = self.v[x],
+            0x1E => self.i = self.i.wrapping_add(self.v[x] as u16),
+            0x29 => self.i = (FONT_ADDRESS + (self.v[x] & 0xF) as usize * 5) as u16,
+            0x33 => {
+                let value = self.v[x];
+                self.write_memory(address, self.i, &[value / 100, (value / 10) % 10, value % 10])?;
+            },
+            0x55 => {
+                let registers = self.v[..x + 1].to_vec();
+                self.write_memory(address, self.i, &registers)?;
+                self.advance_i(x);
+            },
+            0x65 => {
+                let start = self.i as usize;
+                if start + x + 1 > self.memory.len() {
+                    return Err(self.error(address, "Read past the end of memory"));
+                }
+                self.v[..x + 1].copy_from_slice(&self.memory[start..start + x + 1]);
+                self.advance_i(x);
+            },
+            _ => return Err(self.unknown(address, opcode))
+        }
+
+        Ok(())
+    }
+
+    /// Draw an n byte sprite from I at (Vx, Vy). The position wraps, the sprite is
+    /// clipped at the edges unless the wrap quirk is on
+    fn draw(&mut self, x: usize, y: usize, n: u8) {
+        let left = self.v[x] as usize % DISPLAY_WIDTH;
+        let top = self.v[y] as usize % DISPLAY_HEIGHT;
+        let wrap = self.quirks.wrap;
+
+        self.v[0xF] = 0;
+        for row in 0..n as usize {
+            let py = if wrap { (top + row) % DISPLAY_HEIGHT } else { top + row };
+            if py >= DISPLAY_HEIGHT {
+                break;
+            }
+
+            let sprite = self.memory.get(self.i as usize + row).cloned().unwrap_or(0);
+            for col in 0..8 {
+                let px = if wrap { (left + col) % DISPLAY_WIDTH } else { left + col };
+                if px >= DISPLAY_WIDTH {
+                    break;
+                }
+
+                if sprite & (0x80 >> col) != 0 {
+                    let pixel = &mut self.display[py * DISPLAY_WIDTH + px];
+                    if *pixel {
+                        self.v[0xF] = 1;
+                    }
+                    *pixel = !*pixel;
+                }
+            }
+        }
+    }
+
+    fn write_memory(&mut self, address: u16, start: u16, bytes: &[u8]) -> Result<(), InterpreterError> {
+        let start = start as usize;
+        if start + bytes.len() > self.memory.len() {
+            return Err(self.error(address, "Write past the end of memory"));
+        }
+
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Register shifted by SHR and SHL
+    fn shift_source(&self, x: usize, y: usize) -> usize {
+        if self.quirks.shift_vy { y } else { x }
+    }
+
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+    }
+
+    /// Move I past the registers stored or loaded, if the quirk says so
+    fn advance_i(&mut self, x: usize) {
+        if self.quirks.load_store_increment {
+            self.i = self.i.wrapping_add(x as u16 + 1);
+        }
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    fn key_pressed(&self, key: u8) -> bool {
+        self.keys[(key & 0xF) as usize]
+    }
+
+    /// xorshift, good enough for games
+    fn random(&mut self) -> u8 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+
+        (self.rng >> 24) as u8
+    }
+
+    fn unknown(&self, address: u16, opcode: u16) -> InterpreterError {
+        self.error(address, &format!("Unknown opcode ${:04X}", opcode))
+    }
+
+    fn error(&self, address: u16, message: &str) -> InterpreterError {
+        InterpreterError {
+            address,
+            message: String::from(message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &[u8], steps: usize) -> Chip8 {
+        let mut chip8 = Chip8::new(4096);
+        chip8.load(ENTRY_POINT, program).unwrap();
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+
+        chip8
+    }
+
+    #[test]
+    fn test_load_and_jump() {
+        let chip8 = run(&[0x12, 0x04, 0x00, 0x00, 0x13, 0x00], 2);
+
+        assert_eq!(chip8.pc, 0x300);
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        let chip8 = run(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE], 1);
+        assert_eq!(chip8.pc, 0x204);
+        assert_eq!(chip8.stack, vec![0x202]);
+
+        let chip8 = run(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE], 2);
+        assert_eq!(chip8.pc, 0x202);
+        assert!(chip8.stack.is_empty());
+    }
+
+    #[test]
+    fn test_return_empty_stack() {
+        let mut chip8 = Chip8::new(4096);
+        chip8.load(ENTRY_POINT, &[0x00, 0xEE]).unwrap();
+
+        assert_eq!(chip8.step().unwrap_err().to_string(), "$200: Return with an empty stack");
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut chip8 = Chip8::new(4096);
+        chip8.load(ENTRY_POINT, &[0x22, 0x00]).unwrap();
+        for _ in 0..STACK_SIZE {
+            chip8.step().unwrap();
+        }
+
+        assert_eq!(chip8.step().unwrap_err().message(), "Stack overflow");
+    }
+
+    #[test]
+    fn test_skip() {
+        // LD V0, 5; SE V0, 5; LD V1, 1; LD V2, 2
+        let chip8 = run(&[0x60, 0x05, 0x30, 0x05, 0x61, 0x01, 0x62, 0x02], 3);
+
+        assert_eq!(chip8.v[1], 0);
+        assert_eq!(chip8.v[2], 2);
+    }
+
+    #[test]
+    fn test_pc_wraps_at_end_of_memory() {
+        let mut chip8 = Chip8::new(0x10000);
+        // SE V0, 0; CLS at the last two addresses
+        chip8.load(0xFFFC, &[0x30, 0x00, 0x00, 0xE0]).unwrap();
+        chip8.pc = 0xFFFC;
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc, 0);
+
+        chip8.pc = 0xFFFE;
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc, 0);
+    }
+
+    #[test]
+    fn test_add_carry() {
+        // LD V0, $FF; LD V1, 2; ADD V0, V1
+        let chip8 = run(&[0x60, 0xFF, 0x61, 0x02, 0x80, 0x14], 3);
+
+        assert_eq!(chip8.v[0], 1);
+        assert_eq!(chip8.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_sub_borrow() {
+        // LD V0, 1; LD V1, 2; SUB V0, V1
+        let chip8 = run(&[0x60, 0x01, 0x61, 0x02, 0x80, 0x15], 3);
+
+        assert_eq!(chip8.v[0], 0xFF);
+        assert_eq!(chip8.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_bcd_and_store() {
+        // LD V0, 234; LD I, $300; LD B, V0; LD V2, [I]
+        let chip8 = run(&[0x60, 0xEA, 0xA3, 0x00, 0xF0, 0x33, 0xF2, 0x65], 4);
+
+        assert_eq!(&chip8.memory[0x300..0x303], &[2, 3, 4]);
+        assert_eq!(&chip8.v[..3], &[2, 3, 4]);
+    }
+
+    #[test]
+    fn test_draw_and_collision() {
+        // LD V0, 1; LD F, V0; DRW V0, V0, 5; DRW V0, V0, 5
+        let chip8 = run(&[0x60, 0x01, 0xF0, 0x29, 0xD0, 0x05], 3);
+        assert!(chip8.pixel(3, 1));
+        assert!(!chip8.pixel(1, 1));
+        assert_eq!(chip8.v[0xF], 0);
+
+        let chip8 = run(&[0x60, 0x01, 0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05], 4);
+        assert!(!chip8.pixel(3, 1));
+        assert_eq!(chip8.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_draw_clips() {
+        // LD V0, 62; LD I, $300; DRW V0, V0, 1
+        let mut chip8 = Chip8::new(4096);
+        chip8.load(ENTRY_POINT, &[0x60, 0x3E, 0xA3, 0x00, 0xD0, 0x01]).unwrap();
+        chip8.memory[0x300] = 0xFF;
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+
+        assert!(chip8.pixel(63, 30));
+        assert!(!chip8.pixel(0, 30));
+    }
+
+    #[test]
+    fn test_wait_for_key() {
+        let mut chip8 = run(&[0xF3, 0x0A], 1);
+        assert_eq!(chip8.pc, 0x200);
+
+        chip8.keys[7] = true;
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc, 0x202);
+        assert_eq!(chip8.v[3], 7);
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut chip8 = run(&[0x60, 0x02, 0xF0, 0x15], 2);
+        chip8.tick_timers();
+        chip8.tick_timers();
+        chip8.tick_timers();
+
+        assert_eq!(chip8.dt, 0);
+    }
+
+    fn run_with_quirks(quirks: Quirks, program: &[u8], steps: usize) -> Chip8 {
+        let mut chip8 = Chip8::with_quirks(4096, quirks);
+        chip8.load(ENTRY_POINT, program).unwrap();
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+
+        chip8
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        // LD V0, 1; LD V1, 4; SHR V0, V1
+        let program = [0x60, 0x01, 0x61, 0x04, 0x80, 0x16];
+
+        let chip8 = run_with_quirks(Quirks::default(), &program, 3);
+        assert_eq!(chip8.v[0], 0);
+        assert_eq!(chip8.v[0xF], 1);
+
+        let chip8 = run_with_quirks(Quirks::vip(), &program, 3);
+        assert_eq!(chip8.v[0], 2);
+        assert_eq!(chip8.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_load_store_quirk() {
+        // LD I, $300; LD [I], V2
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+
+        assert_eq!(run_with_quirks(Quirks::default(), &program, 2).i, 0x300);
+        assert_eq!(run_with_quirks(Quirks::vip(), &program, 2).i, 0x303);
+    }
+
+    #[test]
+    fn test_jump_quirk() {
+        // LD V0, 1; LD V3, 2; JP V0, $300
+        let program = [0x60, 0x01, 0x63, 0x02, 0xB3, 0x00];
+
+        assert_eq!(run_with_quirks(Quirks::default(), &program, 3).pc, 0x301);
+        assert_eq!(run_with_quirks(Quirks::schip(), &program, 3).pc, 0x302);
+    }
+
+    #[test]
+    fn test_vf_reset_quirk() {
+        // LD VF, 1; OR V0, V1
+        let program = [0x6F, 0x01, 0x80, 0x11];
+
+        assert_eq!(run_with_quirks(Quirks::default(), &program, 2).v[0xF], 1);
+        assert_eq!(run_with_quirks(Quirks::vip(), &program, 2).v[0xF], 0);
+    }
+
+    #[test]
+    fn test_wrap_quirk() {
+        // LD V0, 62; LD I, $300; DRW V0, V0, 1
+        let mut chip8 = Chip8::with_quirks(4096, Quirks::xochip());
+        chip8.load(ENTRY_POINT, &[0x60, 0x3E, 0xA3, 0x00, 0xD0, 0x01]).unwrap();
+        chip8.memory[0x300] = 0xFF;
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+
+        assert!(chip8.pixel(63, 30));
+        assert!(chip8.pixel(0, 30));
+    }
+
+    #[test]
+    fn test_display_wait_quirk() {
+        // DRW V0, V0, 1; DRW V0, V0, 1
+        let mut chip8 = run_with_quirks(Quirks::vip(), &[0xD0, 0x01, 0xD0, 0x01], 2);
+        assert_eq!(chip8.pc, 0x202);
+
+        chip8.tick_timers();
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let mut chip8 = Chip8::new(4096);
+        chip8.load(ENTRY_POINT, &[0xF0, 0xFF]).unwrap();
+
+        assert_eq!(chip8.step().unwrap_err().to_string(), "$200: Unknown opcode $F0FF");
+    }
+}
@@ -0,0 +1,356 @@
+use output::json_string;
+
+use std::error::Error;
+use std::fmt;
+
+/// A JSON value. Object members keep the order they were written in
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+    /// An object from its members
+    pub fn object(members: Vec<(&str, Value)>) -> Value {
+        Value::Object(members.into_iter().map(|(name, value)| (String::from(name), value)).collect())
+    }
+
+    pub fn string(value: &str) -> Value {
+        Value::String(String::from(value))
+    }
+
+    /// Member `name` of an object
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref members) => members.iter().find(|(member, _)| member == name).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    /// Follow a path of member names, e.g. `["textDocument", "uri"]`
+    pub fn path(&self, names: &[&str]) -> Option<&Value> {
+        names.iter().try_fold(self, |value, name| value.get(name))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref value) => Some(value),
+            _ => None
+        }
+    }
+
+    /// The value as a whole number that is not negative
+    pub fn as_usize(&self) -> Option<usize> {
+        match *self {
+            Value::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as usize),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(value) => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self {
+            Value::Array(ref values) => Some(values),
+            _ => None
+        }
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl fmt::Display for Value {
+    /// Write the value as compact JSON
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", n as i64),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(ref value) => write!(f, "{}", json_string(value)),
+            Value::Array(ref values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Value::Object(ref members) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", json_string(name), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Text that is not valid JSON, with the character offset it went wrong at
+#[derive(Debug, PartialEq)]
+pub struct JsonError {
+    offset: usize,
+    message: String
+}
+
+impl JsonError {
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.offset, self.message)
+    }
+}
+
+impl Error for JsonError {}
+
+/// Parse a JSON document
+pub fn parse(text: &str) -> Result<Value, JsonError> {
+    let mut reader = Reader{chars: text.chars().collect(), offset: 0};
+    let value = reader.value()?;
+    reader.skip_whitespace();
+    if reader.offset < reader.chars.len() {
+        return Err(reader.error("Unexpected text after the value"));
+    }
+
+    Ok(value)
+}
+
+struct Reader {
+    chars: Vec<char>,
+    offset: usize
+}
+
+impl Reader {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError{offset: self.offset, message: String::from(message)}
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.offset).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.offset += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map(char::is_whitespace).unwrap_or(false) {
+            self.offset += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => {
+                self.offset -= 1;
+                Err(self.error(&format!("Expected {}", expected)))
+            }
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, JsonError> {
+        let end = self.offset + word.chars().count();
+        if end <= self.chars.len() && self.chars[self.offset..end].iter().cloned().eq(word.chars()) {
+            self.offset = end;
+            Ok(value)
+        }
+        else {
+            Err(self.error("Unexpected character"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.keyword("null", Value::Null),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('"') => self.string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of text"))
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let start = self.offset;
+        while self.peek().map(|c| c.is_ascii_digit() || "+-.eE".contains(c)).unwrap_or(false) {
+            self.offset += 1;
+        }
+
+        let text: String = self.chars[start..self.offset].iter().collect();
+        text.parse::<f64>().map(Value::Number).map_err(|_| JsonError{offset: start, message: format!("Invalid number {}", text)})
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut out = String::new();
+
+        loop {
+            match self.next() {
+                Some('"') => return Ok(out),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => self.escaped_char()?,
+                        Some(c) if c == '"' || c == '\\' || c == '/' => c,
+                        _ => return Err(self.error("Invalid escape"))
+                    };
+                    out.push(c);
+                },
+                Some(c) => out.push(c),
+                None => return Err(self.error("Unterminated string"))
+            }
+        }
+    }
+
+    /// The character of a `\u` escape, which takes two escapes outside the basic plane
+    fn escaped_char(&mut self) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        if (0xD800..0xDC00).contains(&high) && self.chars.get(self.offset..self.offset + 2) == Some(&['\\', 'u'][..]) {
+            self.offset += 2;
+            let low = self.hex4()?;
+            let c = 0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+            return Ok(::std::char::from_u32(c).unwrap_or('\u{FFFD}'));
+        }
+
+        Ok(::std::char::from_u32(high).unwrap_or('\u{FFFD}'))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let end = self.offset + 4;
+        let digits: String = self.chars.get(self.offset..end).map(|digits| digits.iter().collect()).unwrap_or_default();
+        let value = u32::from_str_radix(&digits, 16).map_err(|_| self.error("Invalid \\u escape"))?;
+        self.offset = end;
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Value, JsonError> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.offset += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {},
+                Some(']') => return Ok(Value::Array(values)),
+                _ => {
+                    self.offset -= 1;
+                    return Err(self.error("Expected , or ]"));
+                }
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, JsonError> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.offset += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {},
+                Some('}') => return Ok(Value::Object(members)),
+                _ => {
+                    self.offset -= 1;
+                    return Err(self.error("Expected , or }"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = parse(r#" {"id": 1, "params": {"uri": "file:///a b.asm", "list": [true, null, -2.5e1]}, "s": "q\"é😀"} "#).unwrap();
+
+        assert_eq!(value.get("id").and_then(Value::as_usize), Some(1));
+        assert_eq!(value.path(&["params", "uri"]).and_then(Value::as_str), Some("file:///a b.asm"));
+        assert_eq!(value.path(&["params", "list"]).and_then(Value::as_array),
+            Some(&[Value::Bool(true), Value::Null, Value::Number(-25.0)][..]));
+        assert_eq!(value.get("s").and_then(Value::as_str), Some("q\"é😀"));
+        assert_eq!(value.get("missing"), None);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(parse("{\"a\" 1}").unwrap_err().to_string(), "5: Expected :");
+        assert_eq!(parse("[1, 2").unwrap_err().message(), "Expected , or ]");
+        assert_eq!(parse("\"abc").unwrap_err().message(), "Unterminated string");
+        assert_eq!(parse("1 2").unwrap_err().message(), "Unexpected text after the value");
+    }
+
+    #[test]
+    fn test_display() {
+        let value = Value::object(vec![
+            ("id", Value::from(3)),
+            ("result", Value::Array(vec![Value::string("a\n\"b\""), Value::Null, Value::from(false), Value::Number(0.5)]))
+        ]);
+
+        assert_eq!(value.to_string(), r#"{"id":3,"result":["a\n\"b\"",null,false,0.5]}"#);
+        assert_eq!(parse(&value.to_string()).unwrap(), value);
+    }
+}
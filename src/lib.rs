@@ -9,6 +9,19 @@ use std::io::prelude::*;
 use std::error::Error;
 
 pub mod assembler;
+pub mod dataflow;
+pub mod debugger;
+pub mod disassembler;
+pub mod flow;
+pub mod formatter;
+pub mod interpreter;
+pub mod json;
+pub mod lint;
+pub mod lsp;
+pub mod manifest;
+pub mod output;
+pub mod quirks;
+pub mod timing;
 
 /// Command line arguments
 pub mod options {
@@ -18,23 +31,169 @@ pub mod options {
     silica
 
     Usage:
-      silica [--output=<f>] <input>
+      silica asm [options] [--define=<def>...] [--quirk=<q>...] [--place=<p>...] <input>
+      silica disasm [options] <input>
+      silica run [options] [--define=<def>...] [--quirk=<q>...] [--place=<p>...] <input>
+      silica check [options] [--define=<def>...] [--quirk=<q>...] [--place=<p>...] [--lint=<l>...] <input>
+      silica fmt [options] [--check] <file>...
+      silica debug [options] [--define=<def>...] [--quirk=<q>...] [--place=<p>...] <input>
+      silica link [options] [--place=<p>...] <object>...
+      silica build [options] [<profile>...]
+      silica lsp [options] [--define=<def>...] [--quirk=<q>...]
+      silica [options] [--define=<def>...] [--quirk=<q>...] [--place=<p>...] <input>
+      silica --explain=<code>
       silica (-h | --help)
 
+    Commands:
+      asm       Assemble a program into a ROM, the default when no command is given
+      disasm    List the instructions of a ROM
+      run       Assemble a program and run it until it stops, then show the screen
+      check     Assemble a program and look for likely mistakes
+      fmt       Lay out source files the same way throughout, in place
+      debug     Assemble a program and step through it
+      link      Link object files into a ROM
+      build     Build the profiles of a project manifest
+      lsp       Serve the Language Server Protocol on stdin and stdout, for editors
+
+    Global options:
+      -v --verbose            Print more about what is being done
+      -q --quiet              Print errors only
+      --color=<when>          Colour diagnostics: auto, always or never [default: auto]
+      --message-format=<fmt>  Print diagnostics as human readable text or json, one per line [default: human]
+
     Options:
-      -o --output=<f>  Output file name
-      -h --help        Show help.
+      -o --output=<f>         Output file name, output.c8, .h or .rs by format when not given
+      -c --compile            Write a relocatable object file to link later instead of a ROM
+      --format=<fmt>          Output format: binary, c-header or rust [default: binary]
+      --base=<addr>           Address the output image starts at [default: $200]
+      --fill=<byte>           Value of bytes not written by the program [default: 0]
+      --full-image            Output the image up to the end of memory
+      --allow-overlap         Warn instead of failing when statements write the same address
+      -O --optimize           Remove and shorten redundant instructions before generating code
+      --cfg=<f>               Write the basic blocks of the program as a graph
+      --call-graph=<f>        Write which subroutines call which as a graph
+      --graph-format=<fmt>    Graph format: dot or json [default: dot]
+      --timing                Print estimated COSMAC VIP cycles for each labelled routine
+      --source-map=<f>        Write a JSON map from addresses to source locations
+      --symbols=<f>           Write the address of each label
+      --listing=<f>           Write each statement with its address and bytes
+      --watch                 Assemble again whenever the input or a file it includes changes
+      --target=<t>            Chip8 variant: chip8, schip or xochip [default: chip8]
+      --quirks=<profile>      Interpreter quirks: vip, schip, xochip or custom. Defaults to the target's
+      --quirk=<q>             Turn a quirk on, or off with a no- prefix: shift-vy, load-store,
+                              jump-vx, vf-reset, wrap or display-wait
+      --lint=<l>              Turn a check lint on, or off with a no- prefix: unreachable,
+                              skip-long-load, call-depth, jump-into-data, drw-zero-height,
+                              fall-through-into-data, uninitialized or vf-clobber
+      -D --define=<def>       Define a constant as NAME=VALUE
+      --place=<p>             Place a section at a fixed address as SECTION=ADDRESS, e.g. font=$300
+      --memory-map=<f>        Place sections in the memory regions declared in a file and report their usage
+      --warnings-as-errors    Fail if any warnings are produced
+      --manifest=<f>          Project manifest to build [default: silica.toml]
+      --check                 List the files fmt would change without changing them, failing if there are any
+      --explain=<code>        Explain an error or warning code, e.g. E009
+      -h --help               Show help.
     ";
 
     #[derive(Debug, Deserialize)]
     pub struct ProgramOptions {
+        pub cmd_asm: bool,
+        pub cmd_disasm: bool,
+        pub cmd_run: bool,
+        pub cmd_check: bool,
+        pub cmd_fmt: bool,
+        pub cmd_debug: bool,
+        pub cmd_link: bool,
+        pub cmd_build: bool,
+        pub cmd_lsp: bool,
         pub arg_input: String,
-        pub flag_output: Option<String>
+        pub arg_file: Vec<String>,
+        pub arg_object: Vec<String>,
+        pub arg_profile: Vec<String>,
+        pub flag_output: Option<String>,
+        pub flag_compile: bool,
+        pub flag_format: String,
+        pub flag_base: String,
+        pub flag_fill: String,
+        pub flag_full_image: bool,
+        pub flag_allow_overlap: bool,
+        pub flag_optimize: bool,
+        pub flag_cfg: Option<String>,
+        pub flag_call_graph: Option<String>,
+        pub flag_graph_format: String,
+        pub flag_timing: bool,
+        pub flag_source_map: Option<String>,
+        pub flag_symbols: Option<String>,
+        pub flag_listing: Option<String>,
+        pub flag_watch: bool,
+        pub flag_target: String,
+        pub flag_quirks: Option<String>,
+        pub flag_quirk: Vec<String>,
+        pub flag_define: Vec<String>,
+        pub flag_place: Vec<String>,
+        pub flag_memory_map: Option<String>,
+        pub flag_manifest: String,
+        pub flag_explain: Option<String>,
+        pub flag_check: bool,
+        pub flag_lint: Vec<String>,
+        pub flag_warnings_as_errors: bool,
+        pub flag_verbose: bool,
+        pub flag_quiet: bool,
+        pub flag_color: String,
+        pub flag_message_format: String
     }
 
     pub fn get_program_options() -> ProgramOptions {
         Docopt::new(USAGE).and_then(|d| d.deserialize()).unwrap_or_else(|e| e.exit())
     }
+
+    /// Parse a number given on the command line. Accepts `$` or `0x` prefixed hex and decimal
+    pub fn parse_number(value: &str) -> Option<u32> {
+        if let Some(hex) = value.strip_prefix('$') {
+            u32::from_str_radix(hex, 16).ok()
+        }
+        else if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            u32::from_str_radix(hex, 16).ok()
+        }
+        else {
+            value.parse::<u32>().ok()
+        }
+    }
+
+    /// Parse a constant definition of the form `NAME=VALUE`
+    pub fn parse_define(value: &str) -> Option<(String, u32)> {
+        let mut parts = value.splitn(2, '=');
+        let name = parts.next()?;
+        let value = parse_number(parts.next()?)?;
+
+        if name.is_empty() {
+            return None;
+        }
+
+        Some((String::from(name), value))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_define() {
+            assert_eq!(parse_define("LIVES=3"), Some((String::from("LIVES"), 3)));
+            assert_eq!(parse_define("ENTRY=$2A0"), Some((String::from("ENTRY"), 0x2A0)));
+            assert_eq!(parse_define("LIVES"), None);
+            assert_eq!(parse_define("=3"), None);
+        }
+
+        #[test]
+        fn test_parse_number() {
+            assert_eq!(parse_number("$200"), Some(0x200));
+            assert_eq!(parse_number("0xFF"), Some(0xFF));
+            assert_eq!(parse_number("512"), Some(512));
+            assert_eq!(parse_number("$"), None);
+            assert_eq!(parse_number("two"), None);
+        }
+    }
 }
 
 /// Load bytes from file into memory
@@ -47,7 +206,7 @@ pub fn load_file(rom_file: &String) -> Result<Vec<u8>, Box<Error>> {
     Ok(buffer)
 }
 
-pub fn write_to_file(data: Vec<u8>) {
-    let mut file = File::create("output.c8").unwrap();
+pub fn write_to_file(file_name: &str, data: Vec<u8>) {
+    let mut file = File::create(file_name).unwrap();
     file.write_all(&data[..]).unwrap();
 }
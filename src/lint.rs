@@ -0,0 +1,423 @@
+use assembler::{AssemblyOutput, ByteKind, Severity, Target};
+use assembler::codes;
+use assembler::diagnostic::{Diagnostic, Location};
+use dataflow;
+use flow::{ControlFlow, EdgeKind};
+use interpreter::STACK_SIZE;
+
+use std::collections::HashMap;
+
+/// Problems `silica check` looks for
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Lint {
+    /// code that cannot be reached from the entry point
+    Unreachable,
+    /// a skip in front of XO-CHIP's 4-byte LD I, long, which only skips its first half
+    SkipLongLoad,
+    /// calls nested deeper than the stack, or recursive calls
+    CallDepth,
+    /// JP, CALL or JR to data, or to an address with nothing in it
+    JumpIntoData,
+    /// DRW with a height of 0, which draws nothing on plain Chip8
+    DrawZeroHeight,
+    /// code running on into db data
+    FallThroughIntoData,
+    /// registers, I or the timers read before anything was put in them
+    Uninitialized,
+    /// VF read as a carry or borrow flag after something else overwrote it
+    VfClobber
+}
+
+pub const LINTS: [Lint; 8] = [
+    Lint::Unreachable,
+    Lint::SkipLongLoad,
+    Lint::CallDepth,
+    Lint::JumpIntoData,
+    Lint::DrawZeroHeight,
+    Lint::FallThroughIntoData,
+    Lint::Uninitialized,
+    Lint::VfClobber
+];
+
+impl Lint {
+    /// Name used on the command line and in messages
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Lint::Unreachable => "unreachable",
+            Lint::SkipLongLoad => "skip-long-load",
+            Lint::CallDepth => "call-depth",
+            Lint::JumpIntoData => "jump-into-data",
+            Lint::DrawZeroHeight => "drw-zero-height",
+            Lint::FallThroughIntoData => "fall-through-into-data",
+            Lint::Uninitialized => "uninitialized",
+            Lint::VfClobber => "vf-clobber"
+        }
+    }
+
+    /// Stable code of the warnings this lint gives
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Lint::Unreachable => codes::UNREACHABLE,
+            Lint::SkipLongLoad => codes::SKIP_LONG_LOAD,
+            Lint::CallDepth => codes::CALL_DEPTH,
+            Lint::JumpIntoData => codes::JUMP_INTO_DATA,
+            Lint::DrawZeroHeight => codes::DRAW_ZERO_HEIGHT,
+            Lint::FallThroughIntoData => codes::FALL_THROUGH_INTO_DATA,
+            Lint::Uninitialized => codes::UNINITIALIZED,
+            Lint::VfClobber => codes::VF_CLOBBER
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        LINTS.iter().find(|lint| lint.name() == name).cloned()
+    }
+}
+
+/// Which lints are run. All of them unless turned off
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Lints {
+    disabled: Vec<Lint>
+}
+
+impl Lints {
+    pub fn new() -> Self {
+        Lints::default()
+    }
+
+    /// Turn a lint on by name, or off when the name is prefixed with `no-`.
+    /// Returns false for unknown names
+    pub fn set(&mut self, name: &str) -> bool {
+        let (name, enabled) = match name.strip_prefix("no-") {
+            Some(name) => (name, false),
+            None => (name, true)
+        };
+
+        match Lint::from_name(name) {
+            Some(lint) => {
+                self.disabled.retain(|&disabled| disabled != lint);
+                if !enabled {
+                    self.disabled.push(lint);
+                }
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn is_enabled(&self, lint: Lint) -> bool {
+        !self.disabled.contains(&lint)
+    }
+}
+
+/// Run the enabled lints over a program assembled for `target` whose image starts at `base`
+pub fn check(output: &AssemblyOutput, base: u16, target: Target, lints: &Lints) -> Vec<Diagnostic> {
+    let flow = ControlFlow::build(output, base);
+    let mut checker = Checker {
+        output,
+        base,
+        flow: &flow,
+        lints,
+        diagnostics: Vec::new()
+    };
+
+    checker.check_unreachable();
+    checker.check_instructions(target);
+    checker.check_call_depth();
+    checker.check_dataflow();
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| d.location.as_ref().map(|location| (location.file.clone(), location.line, location.column)));
+    diagnostics
+}
+
+struct Checker<'a> {
+    output: &'a AssemblyOutput,
+    base: u16,
+    flow: &'a ControlFlow,
+    lints: &'a Lints,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl<'a> Checker<'a> {
+    fn check_unreachable(&mut self) {
+        let mut previous_end = None;
+        for block in self.flow.blocks.iter() {
+            // report runs of unreachable blocks once
+            if !block.reachable && previous_end != Some(block.start) {
+                self.report(Lint::Unreachable, block.start, String::from("Unreachable code"));
+            }
+            previous_end = if block.reachable { None } else { Some(block.end) };
+        }
+    }
+
+    fn check_instructions(&mut self, target: Target) {
+        for block in self.flow.blocks.iter() {
+            for &(address, opcode) in block.instructions.iter() {
+                if let Some(skip) = skip_name(opcode) {
+                    if self.read_opcode(address.wrapping_add(2)) == Some(0xF000) {
+                        let message = format!("{} skips only the first half of the 4-byte LD I, long that follows", skip);
+                        self.report(Lint::SkipLongLoad, address, message);
+                    }
+                }
+
+                let jump = match opcode >> 12 {
+                    0x1 => Some("JP"),
+                    0x2 => Some("CALL"),
+                    0xB => Some("JR"),
+                    _ => None
+                };
+                if let Some(jump) = jump {
+                    let destination = opcode & 0xFFF;
+                    match self.kind(destination) {
+                        Some(ByteKind::Code) => {},
+                        Some(ByteKind::Data) => {
+                            let message = format!("{} to {}, which is data", jump, self.flow.name(destination));
+                            self.report(Lint::JumpIntoData, address, message);
+                        },
+                        None => {
+                            let message = format!("{} to ${:03X}, where there is no code", jump, destination);
+                            self.report(Lint::JumpIntoData, address, message);
+                        }
+                    }
+                }
+
+                if opcode >> 12 == 0xD && opcode & 0xF == 0 && target == Target::Chip8 {
+                    self.report(Lint::DrawZeroHeight, address, String::from("DRW with a height of 0 draws nothing on Chip8"));
+                }
+            }
+
+            let last = block.instructions.last().unwrap().0;
+            for edge in block.successors.iter() {
+                let falls_through = edge.kind == EdgeKind::Fallthrough || edge.kind == EdgeKind::Skip;
+                if falls_through && self.kind(edge.target) == Some(ByteKind::Data) {
+                    let message = format!("Code falls through into data at {}", self.flow.name(edge.target));
+                    self.report(Lint::FallThroughIntoData, last, message);
+                }
+            }
+        }
+    }
+
+    fn check_dataflow(&mut self) {
+        if self.lints.is_enabled(Lint::Uninitialized) {
+            for finding in dataflow::uninitialized(self.flow) {
+                self.report(Lint::Uninitialized, finding.address, finding.message);
+            }
+        }
+        if self.lints.is_enabled(Lint::VfClobber) {
+            for finding in dataflow::vf_clobbered(self.flow) {
+                self.report(Lint::VfClobber, finding.address, finding.message);
+            }
+        }
+    }
+
+    fn check_call_depth(&mut self) {
+        let mut path = vec![self.flow.entry];
+        let mut reported = Vec::new();
+        let mut deepest = HashMap::new();
+        self.visit_calls(&mut path, &mut reported, &mut deepest);
+    }
+
+    /// Follow calls depth first from the subroutine at the end of `path`. `deepest` holds
+    /// the longest path each subroutine has been visited with: visiting it again with a
+    /// path that is not longer finds nothing new, which keeps diamond-shaped call graphs linear
+    fn visit_calls(&mut self, path: &mut Vec<u16>, reported: &mut Vec<u16>, deepest: &mut HashMap<u16, usize>) {
+        let caller = *path.last().unwrap();
+        let calls: Vec<(u16, u16)> = self.flow.calls.iter()
+            .filter(|call| call.caller == caller)
+            .map(|call| (call.address, call.callee))
+            .collect();
+
+        for (address, callee) in calls.into_iter() {
+            if reported.contains(&address) {
+                continue;
+            }
+
+            if path.contains(&callee) {
+                reported.push(address);
+                let message = format!("Recursive call to {} can overflow the {}-entry stack", self.flow.name(callee), STACK_SIZE);
+                self.report(Lint::CallDepth, address, message);
+            }
+            else if path.len() > STACK_SIZE {
+                reported.push(address);
+                let message = format!("Call to {} is nested {} calls deep, more than the {}-entry stack holds",
+                    self.flow.name(callee), path.len(), STACK_SIZE);
+                self.report(Lint::CallDepth, address, message);
+            }
+            else if deepest.get(&callee).map(|&depth| depth < path.len() + 1).unwrap_or(true) {
+                deepest.insert(callee, path.len() + 1);
+                path.push(callee);
+                self.visit_calls(path, reported, deepest);
+                path.pop();
+            }
+        }
+    }
+
+    /// What the program placed at an address, if anything
+    fn kind(&self, address: u16) -> Option<ByteKind> {
+        let address = address as u32;
+        self.output.source_map.iter()
+            .find(|entry| entry.address <= address && address < entry.address + entry.size)
+            .map(|entry| entry.kind)
+    }
+
+    fn read_opcode(&self, address: u16) -> Option<u16> {
+        let i = address.checked_sub(self.base)? as usize;
+        let msb = *self.output.bytes.get(i)? as u16;
+        let lsb = *self.output.bytes.get(i + 1)? as u16;
+
+        Some((msb << 8) | lsb)
+    }
+
+    fn location(&self, address: u16) -> Option<Location> {
+        let address = address as u32;
+        self.output.source_map.iter()
+            .find(|entry| entry.address <= address && address < entry.address + entry.size)
+            .map(|entry| entry.location.clone())
+    }
+
+    fn report(&mut self, lint: Lint, address: u16, message: String) {
+        if !self.lints.is_enabled(lint) {
+            return;
+        }
+
+        let message = format!("{} [{}]", message, lint.name());
+        let location = self.location(address);
+        self.diagnostics.push(Diagnostic::new(Severity::Warning, lint.code(), &message, location));
+    }
+}
+
+/// Mnemonic of an instruction that can skip the next one
+fn skip_name(opcode: u16) -> Option<&'static str> {
+    match opcode >> 12 {
+        0x3 | 0x5 => Some("SE"),
+        0x4 | 0x9 => Some("SNE"),
+        0xE if opcode & 0xFF == 0x9E => Some("SKP"),
+        0xE if opcode & 0xFF == 0xA1 => Some("SKNP"),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    fn lint_with(source: &[u8], target: Target, lints: &Lints) -> Vec<String> {
+        let output = Assembler::new().target(target).assemble("game.asm", source).unwrap();
+        check(&output, 0x200, target, lints).iter().map(|d| d.to_string()).collect()
+    }
+
+    fn lint(source: &[u8]) -> Vec<String> {
+        lint_with(source, Target::Chip8, &Lints::new())
+    }
+
+    #[test]
+    fn test_lint_names() {
+        for lint in LINTS.iter() {
+            assert_eq!(Lint::from_name(lint.name()), Some(*lint));
+        }
+        assert_eq!(Lint::from_name("style"), None);
+    }
+
+    #[test]
+    fn test_clean_program() {
+        assert!(lint(b"start\tCALL #sub\nend\tJP #end\nsub\tRET\n").is_empty());
+    }
+
+    #[test]
+    fn test_unreachable() {
+        assert_eq!(lint(b"start\tJP #start\n\tCLS\n\tRET\n"), vec!["game.asm:2:2: warning[W010]: Unreachable code [unreachable]"]);
+    }
+
+    #[test]
+    fn test_jump_table_reachable() {
+        assert!(lint(b"start\tLD V0, 2\n\tJR #table\ntable\tJP #left\n\tJP #right\nleft\tCLS\n\tJP #start\nright\tJP #start\n").is_empty());
+    }
+
+    #[test]
+    fn test_skip_long_load() {
+        let warnings = lint(b"start\tSE V0, 1\n\tdb $F0 $00 $03 $00\nend\tJP #end\n");
+
+        assert!(warnings.contains(&String::from("game.asm:1:7: warning[W011]: SE skips only the first half of the 4-byte LD I, long that follows [skip-long-load]")));
+    }
+
+    #[test]
+    fn test_recursion() {
+        assert_eq!(lint(b"start\tCALL #start\n"), vec!["game.asm:1:7: warning[W012]: Recursive call to start can overflow the 16-entry stack [call-depth]"]);
+    }
+
+    #[test]
+    fn test_call_depth() {
+        let mut source = String::from("start\tCALL #s1\nend\tJP #end\n");
+        for i in 1..18 {
+            source.push_str(&format!("s{}\tCALL #s{}\n\tRET\n", i, i + 1));
+        }
+        source.push_str("s18\tRET\n");
+
+        assert_eq!(lint(source.as_bytes()), vec!["game.asm:33:5: warning[W012]: Call to s17 is nested 17 calls deep, more than the 16-entry stack holds [call-depth]"]);
+    }
+
+    #[test]
+    fn test_call_depth_diamonds() {
+        // each subroutine calls the next twice, giving 2^40 paths through the calls
+        let mut source = String::from("s0\tCALL #s1\n\tCALL #s1\nend\tJP #end\n");
+        for i in 1..40 {
+            source.push_str(&format!("s{}\tCALL #s{}\n\tCALL #s{}\n\tRET\n", i, i + 1, i + 1));
+        }
+        source.push_str("s40\tRET\n");
+
+        assert_eq!(lint(source.as_bytes()), vec![
+            "game.asm:49:5: warning[W012]: Call to s17 is nested 17 calls deep, more than the 16-entry stack holds [call-depth]",
+            "game.asm:50:2: warning[W012]: Call to s17 is nested 17 calls deep, more than the 16-entry stack holds [call-depth]"
+        ]);
+    }
+
+    #[test]
+    fn test_skip_at_end_of_memory() {
+        // the instruction after the last one is the first one in memory
+        let output = Assembler::new().target(Target::XoChip).base_address(0)
+            .assemble("game.asm", b"\tdb $F0 $00 $03 $00\n\torg $FFFE\n\tSE V0, 1\n").unwrap();
+        let warnings: Vec<String> = check(&output, 0, Target::XoChip, &Lints::new()).iter().map(|d| d.to_string()).collect();
+
+        assert!(warnings.contains(&String::from("game.asm:3:2: warning[W011]: SE skips only the first half of the 4-byte LD I, long that follows [skip-long-load]")));
+    }
+
+    #[test]
+    fn test_jump_into_data() {
+        assert_eq!(lint(b"start\tJP #sprite\nsprite\n\tdb $F0\n"), vec!["game.asm:1:7: warning[W013]: JP to sprite, which is data [jump-into-data]"]);
+        assert_eq!(lint(b"start\tCALL $300\nend\tJP #end\n"), vec!["game.asm:1:7: warning[W013]: CALL to $300, where there is no code [jump-into-data]"]);
+    }
+
+    #[test]
+    fn test_drw_zero_height() {
+        let source = b"start\tLD I, $300\n\tLD V0, 0\n\tLD V1, 0\ndraw\tDRW V0, V1, 0\nend\tJP #end\n";
+
+        assert_eq!(lint(source), vec!["game.asm:4:6: warning[W014]: DRW with a height of 0 draws nothing on Chip8 [drw-zero-height]"]);
+        assert!(lint_with(source, Target::SuperChip, &Lints::new()).is_empty());
+    }
+
+    #[test]
+    fn test_dataflow() {
+        assert_eq!(lint(b"start\tLD V0, 0\n\tDRW V0, V0, 5\nend\tJP #end\n"),
+            vec!["game.asm:2:2: warning[W016]: I may be used before it is set [uninitialized]"]);
+        assert_eq!(lint(b"start\tLD V0, 1\n\tADD V0, V0\n\tLD VF, 0\n\tSE VF, 1\nend\tJP #end\n"),
+            vec!["game.asm:4:2: warning[W017]: VF is used as the flag set at start+2, but it may have been overwritten at start+4 [vf-clobber]"]);
+    }
+
+    #[test]
+    fn test_fall_through_into_data() {
+        assert_eq!(lint(b"start\tCLS\nsprite\n\tdb $F0\n"), vec!["game.asm:1:7: warning[W015]: Code falls through into data at sprite [fall-through-into-data]"]);
+    }
+
+    #[test]
+    fn test_disable_lint() {
+        let mut lints = Lints::new();
+        assert!(lints.set("no-unreachable"));
+        assert!(!lints.set("no-style"));
+
+        assert!(lint_with(b"start\tJP #start\n\tCLS\n", Target::Chip8, &lints).is_empty());
+
+        assert!(lints.set("unreachable"));
+        assert_eq!(lint_with(b"start\tJP #start\n\tCLS\n", Target::Chip8, &lints).len(), 1);
+    }
+}
@@ -0,0 +1,749 @@
+use assembler::{Assembler, AssemblyOutput, ByteKind, FileSystemProvider, Severity, Target};
+use assembler::diagnostic::Diagnostic;
+use assembler::lexer::{self, Position, Token, DIRECTIVES, INSTRUCTIONS, REGISTERS};
+use json::{self, Value};
+use quirks::Quirks;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+/// JSON-RPC error codes
+const PARSE_ERROR: usize = 32700;
+const INVALID_REQUEST: usize = 32600;
+const METHOD_NOT_FOUND: usize = 32601;
+
+/// LSP completion item and symbol kinds
+const COMPLETION_KEYWORD: usize = 14;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_REFERENCE: usize = 18;
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_CONSTANT: usize = 14;
+
+/// An open document and the program assembled from it, if it assembles
+struct Document {
+    text: String,
+    output: Option<AssemblyOutput>
+}
+
+/// Language server for Chip8 sources. Documents are assembled each time they
+/// change, which gives their diagnostics and the addresses shown on hover
+pub struct Server {
+    target: Target,
+    quirks: Option<Quirks>,
+    base: u32,
+    defines: Vec<(String, u32)>,
+    documents: HashMap<String, Document>,
+    shut_down: bool
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+impl Server {
+    /// Server assembling documents for plain Chip8
+    pub fn new() -> Self {
+        Server {
+            target: Target::default(),
+            quirks: None,
+            base: 0x200,
+            defines: Vec::new(),
+            documents: HashMap::new(),
+            shut_down: false
+        }
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    pub fn base_address(mut self, base: u32) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// Define a constant for every document, as `-D` does
+    pub fn define(mut self, name: &str, value: u32) -> Self {
+        self.defines.push((String::from(name), value));
+        self
+    }
+
+    /// Read messages until `exit` or the end of input, writing responses and notifications
+    pub fn run<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<()> {
+        while let Some(content) = read_message(&mut input)? {
+            let replies = match json::parse(&content) {
+                Ok(ref message) if message.get("method").and_then(Value::as_str) == Some("exit") => break,
+                Ok(message) => self.handle(&message),
+                Err(e) => vec![error(Value::Null, PARSE_ERROR, &format!("Invalid JSON at {}", e))]
+            };
+
+            for reply in replies.iter() {
+                let content = reply.to_string();
+                write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+            }
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a request or notification, giving the messages to send back
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let uri = params.path(&["textDocument", "uri"]).and_then(Value::as_str).unwrap_or("").to_string();
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method, &uri, &params)
+        };
+        if self.shut_down {
+            return vec![error(id, INVALID_REQUEST, "The server has been shut down")];
+        }
+
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shut_down = true;
+                Value::Null
+            },
+            "textDocument/definition" => self.definition(&uri, &params),
+            "textDocument/references" => self.references(&uri, &params),
+            "textDocument/hover" => self.hover(&uri, &params),
+            "textDocument/completion" => self.completion(&uri, &params),
+            "textDocument/documentSymbol" => self.document_symbols(&uri),
+            _ => return vec![error(id, METHOD_NOT_FOUND, &format!("Unknown method {}", method))]
+        };
+
+        vec![Value::object(vec![("jsonrpc", Value::string("2.0")), ("id", id), ("result", result)])]
+    }
+
+    fn notification(&mut self, method: &str, uri: &str, params: &Value) -> Vec<Value> {
+        let text = match method {
+            "textDocument/didOpen" => params.path(&["textDocument", "text"]).and_then(Value::as_str),
+            // changes are always whole documents, as asked for in the capabilities
+            "textDocument/didChange" => params.get("contentChanges")
+                .and_then(Value::as_array)
+                .and_then(|changes| changes.last())
+                .and_then(|change| change.get("text"))
+                .and_then(Value::as_str),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, vec![])];
+            },
+            _ => None
+        };
+
+        match text {
+            Some(text) => {
+                let diagnostics = self.update(uri, text);
+                vec![publish_diagnostics(uri, diagnostics)]
+            },
+            None => vec![]
+        }
+    }
+
+    /// Assemble a document after it changed, giving its diagnostics
+    fn update(&mut self, uri: &str, text: &str) -> Vec<Value> {
+        let mut assembler = Assembler::new()
+            .target(self.target)
+            .source_provider(FileSystemProvider::new())
+            .base_address(self.base);
+        if let Some(quirks) = self.quirks {
+            assembler = assembler.quirks(quirks);
+        }
+        for (name, value) in self.defines.iter() {
+            assembler = assembler.define(name, *value);
+        }
+
+        let file = uri_to_path(uri);
+        let (output, diagnostics) = match assembler.assemble(&file, text.as_bytes()) {
+            Ok(mut output) => {
+                let diagnostics = output.diagnostics.split_off(0);
+                (Some(output), diagnostics)
+            },
+            Err(e) => (None, e.diagnostics)
+        };
+
+        self.documents.insert(String::from(uri), Document{text: String::from(text), output});
+        diagnostics.iter().map(|diagnostic| to_lsp_diagnostic(uri, &file, text, diagnostic)).collect()
+    }
+
+    /// Where the label under the cursor is defined
+    fn definition(&self, uri: &str, params: &Value) -> Value {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Value::Null
+        };
+        let name = match word_at(&document.text, params) {
+            Some((word, _)) => String::from(word.trim_start_matches('#')),
+            None => return Value::Null
+        };
+
+        tokens(&document.text).into_iter()
+            .find(|(_, token)| *token == Token::Label(name.clone()))
+            .map(|(position, _)| location(uri, &document.text, position, name.len()))
+            .unwrap_or(Value::Null)
+    }
+
+    /// Every use of the label or constant under the cursor, and its definition if asked for
+    fn references(&self, uri: &str, params: &Value) -> Value {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Value::Array(vec![])
+        };
+        let name = match word_at(&document.text, params) {
+            Some((word, _)) => String::from(word.trim_start_matches('#')),
+            None => return Value::Array(vec![])
+        };
+        let declaration = params.path(&["context", "includeDeclaration"]).and_then(Value::as_bool).unwrap_or(false);
+
+        let mut locations = Vec::new();
+        let mut previous = None;
+        for (position, token) in tokens(&document.text) {
+            match token {
+                Token::Label(ref label) if *label == name && declaration => {
+                    locations.push(location(uri, &document.text, position, name.len()))
+                },
+                // section names are read as label operands but are not labels
+                Token::LabelOperand(ref label) if *label == name && previous != Some(Token::Directive(String::from("section"))) => {
+                    let after_hash = Position::new(position.line, position.column + 1);
+                    locations.push(location(uri, &document.text, after_hash, name.len()));
+                },
+                _ => {}
+            }
+            previous = Some(token);
+        }
+
+        Value::Array(locations)
+    }
+
+    /// The address and encoding of the line under the cursor, and the value of the
+    /// label or constant under it
+    fn hover(&self, uri: &str, params: &Value) -> Value {
+        let output = match self.documents.get(uri).and_then(|document| document.output.as_ref()) {
+            Some(output) => output,
+            None => return Value::Null
+        };
+        let file = uri_to_path(uri);
+        let line = params.path(&["position", "line"]).and_then(Value::as_usize).unwrap_or(0) + 1;
+
+        let mut parts = Vec::new();
+        for entry in output.source_map.iter().filter(|entry| entry.location.file == file && entry.location.line == line) {
+            let start = (entry.address - self.base) as usize;
+            let bytes = output.bytes.get(start..start + entry.size as usize).unwrap_or(&[]);
+            let encoded: Vec<String> = match entry.kind {
+                ByteKind::Code => bytes.chunks(2).map(|pair| pair.iter().map(|b| format!("{:02X}", b)).collect()).collect(),
+                ByteKind::Data => bytes.iter().map(|b| format!("{:02X}", b)).collect()
+            };
+            parts.push(format!("`${:04X}`: `{}`", entry.address, encoded.join(" ")));
+        }
+
+        let document = &self.documents[uri];
+        if let Some((word, _)) = word_at(&document.text, params) {
+            let name = word.trim_start_matches('#');
+            if let Some(value) = output.symbols.get(name) {
+                parts.push(format!("`{}` = `${:04X}`", name, value));
+            }
+        }
+
+        if parts.is_empty() {
+            return Value::Null;
+        }
+        let contents = Value::object(vec![("kind", Value::string("markdown")), ("value", Value::String(parts.join("\n\n")))]);
+        Value::object(vec![("contents", contents)])
+    }
+
+    /// Mnemonics where a statement starts, registers and labels after it
+    fn completion(&self, uri: &str, params: &Value) -> Value {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Value::Array(vec![])
+        };
+        let line = params.path(&["position", "line"]).and_then(Value::as_usize).unwrap_or(0);
+        let character = params.path(&["position", "character"]).and_then(Value::as_usize).unwrap_or(0);
+        let text = document.text.lines().nth(line).unwrap_or("");
+        let before = &text[..utf16_to_byte(text, character)];
+
+        if before.contains(';') {
+            return Value::Array(vec![]);
+        }
+        // words finished before the cursor. The last word is the one being typed
+        let words: Vec<&str> = before.split(|c: char| c.is_whitespace() || c == ',').collect();
+        let finished = words[..words.len() - 1].iter().filter(|word| !word.is_empty()).count();
+        let label = before.starts_with(|c: char| !c.is_whitespace());
+        if label && finished == 0 {
+            return Value::Array(vec![]);
+        }
+
+        let item = |label: &str, kind: usize| Value::object(vec![("label", Value::string(label)), ("kind", Value::from(kind))]);
+        let items = if finished == label as usize {
+            INSTRUCTIONS.iter().chain(DIRECTIVES.iter()).map(|name| item(name, COMPLETION_KEYWORD)).collect()
+        }
+        else {
+            let labels = tokens(&document.text).into_iter().filter_map(|(_, token)| match token {
+                Token::Label(label) => Some(format!("#{}", label)),
+                _ => None
+            });
+            REGISTERS.iter().map(|name| item(name, COMPLETION_VARIABLE))
+                .chain(labels.map(|label| item(&label, COMPLETION_REFERENCE)))
+                .collect()
+        };
+
+        Value::Array(items)
+    }
+
+    /// The labels of a document, with their addresses if it assembles
+    fn document_symbols(&self, uri: &str) -> Value {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Value::Array(vec![])
+        };
+
+        let symbols = tokens(&document.text).into_iter().filter_map(|(position, token)| {
+            let name = match token {
+                Token::Label(name) => name,
+                _ => return None
+            };
+            let address = document.output.as_ref().and_then(|output| output.symbols.get(&name).cloned());
+            let data = document.output.as_ref()
+                .map(|output| output.source_map.iter().any(|entry| Some(entry.address) == address && entry.kind == ByteKind::Data))
+                .unwrap_or(false);
+
+            let range = range(&document.text, position, name.len());
+            let mut members = vec![
+                ("name", Value::String(name)),
+                ("kind", Value::from(if data { SYMBOL_CONSTANT } else { SYMBOL_FUNCTION })),
+                ("range", range.clone()),
+                ("selectionRange", range)
+            ];
+            if let Some(address) = address {
+                members.insert(1, ("detail", Value::String(format!("${:04X}", address))));
+            }
+            Some(Value::object(members))
+        });
+
+        Value::Array(symbols.collect())
+    }
+}
+
+/// What the server can do, in answer to `initialize`
+fn capabilities() -> Value {
+    let capabilities = Value::object(vec![
+        // whole documents are sent on every change
+        ("textDocumentSync", Value::from(1)),
+        ("definitionProvider", Value::from(true)),
+        ("referencesProvider", Value::from(true)),
+        ("hoverProvider", Value::from(true)),
+        ("completionProvider", Value::object(vec![("triggerCharacters", Value::Array(vec![Value::string("#")]))])),
+        ("documentSymbolProvider", Value::from(true))
+    ]);
+
+    Value::object(vec![
+        ("capabilities", capabilities),
+        ("serverInfo", Value::object(vec![("name", Value::string("silica"))]))
+    ])
+}
+
+fn error(id: Value, code: usize, message: &str) -> Value {
+    let error = Value::object(vec![("code", Value::Number(-(code as f64))), ("message", Value::string(message))]);
+    Value::object(vec![("jsonrpc", Value::string("2.0")), ("id", id), ("error", error)])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    let params = Value::object(vec![("uri", Value::string(uri)), ("diagnostics", Value::Array(diagnostics))]);
+    Value::object(vec![
+        ("jsonrpc", Value::string("2.0")),
+        ("method", Value::string("textDocument/publishDiagnostics")),
+        ("params", params)
+    ])
+}
+
+/// A diagnostic of the document `file` with contents `text`. Problems elsewhere, such as
+/// in an included file, are shown at the top of the document along with where they are
+fn to_lsp_diagnostic(uri: &str, file: &str, text: &str, diagnostic: &Diagnostic) -> Value {
+    let (range, message) = match diagnostic.location {
+        Some(ref location) if location.file == file => {
+            let start = Position::new(location.line, location.column);
+            (range(text, start, location.end_column.saturating_sub(location.column)), diagnostic.message.clone())
+        },
+        Some(ref location) => (range(text, Position::new(1, 1), 0), format!("{}: {}", location, diagnostic.message)),
+        None => (range(text, Position::new(1, 1), 0), diagnostic.message.clone())
+    };
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2
+    };
+
+    let mut members = vec![
+        ("range", range),
+        ("severity", Value::from(severity)),
+        ("code", Value::string(diagnostic.code)),
+        ("source", Value::string("silica")),
+        ("message", Value::String(message))
+    ];
+    if !diagnostic.related.is_empty() {
+        let related = diagnostic.related.iter().map(|related| {
+            let (related_uri, related_text) = if related.location.file == file {
+                (String::from(uri), String::from(text))
+            }
+            else {
+                (path_to_uri(&related.location.file), fs::read_to_string(&related.location.file).unwrap_or_default())
+            };
+            let start = Position::new(related.location.line, related.location.column);
+            let length = related.location.end_column.saturating_sub(related.location.column);
+            Value::object(vec![
+                ("location", location(&related_uri, &related_text, start, length)),
+                ("message", Value::string(&related.message))
+            ])
+        });
+        members.push(("relatedInformation", Value::Array(related.collect())));
+    }
+
+    Value::object(members)
+}
+
+/// LSP range of `length` bytes from a position in `text`. LSP counts from 0
+fn range(text: &str, start: Position, length: usize) -> Value {
+    let line = text.lines().nth(start.line.saturating_sub(1)).unwrap_or("");
+    let point = |column: usize| Value::object(vec![
+        ("line", Value::from(start.line.saturating_sub(1))),
+        ("character", Value::from(byte_to_utf16(line, column.saturating_sub(1))))
+    ]);
+    Value::object(vec![("start", point(start.column)), ("end", point(start.column + length))])
+}
+
+fn location(uri: &str, text: &str, start: Position, length: usize) -> Value {
+    Value::object(vec![("uri", Value::string(uri)), ("range", range(text, start, length))])
+}
+
+/// LSP positions count UTF-16 code units along a line, the lexer counts bytes.
+/// The UTF-16 offset of byte `byte` of `line`, counting bytes past its end as one unit each
+fn byte_to_utf16(line: &str, byte: usize) -> usize {
+    let inside: usize = line.char_indices().take_while(|&(i, _)| i < byte).map(|(_, c)| c.len_utf16()).sum();
+    inside + byte.saturating_sub(line.len())
+}
+
+/// The byte of `line` at UTF-16 offset `character`, the end of the line when past it
+fn utf16_to_byte(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Tokens of a document with their positions. Lines the lexer rejects are left
+/// out, so a document being edited still has the tokens of its other lines
+fn tokens(text: &str) -> Vec<(Position, Token)> {
+    let mut ret = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        if let Ok(tokens) = lexer::tokenize_with_positions(line.as_bytes()) {
+            ret.extend(tokens.into_iter().map(|(position, token)| (Position::new(index + 1, position.column), token)));
+        }
+    }
+
+    ret
+}
+
+/// The word at `params.position`, a label, `#label`, mnemonic, register or number,
+/// with the character it starts at
+fn word_at<'a>(text: &'a str, params: &Value) -> Option<(&'a str, usize)> {
+    let line = params.path(&["position", "line"]).and_then(Value::as_usize)?;
+    let character = params.path(&["position", "character"]).and_then(Value::as_usize)?;
+    let text = text.lines().nth(line)?;
+
+    // words are ASCII, so any byte of a wider character ends them
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'#' || b == b'$';
+    let bytes = text.as_bytes();
+    let cursor = utf16_to_byte(text, character);
+    let mut start = cursor;
+    while start > 0 && is_word(bytes[start - 1]) {
+        start -= 1;
+    }
+    let mut end = cursor;
+    while end < bytes.len() && is_word(bytes[end]) {
+        end += 1;
+    }
+    if start == end {
+        return None;
+    }
+
+    Some((&text[start..end], byte_to_utf16(text, start)))
+}
+
+/// The path of a `file://` URI, which is what the assembler resolves includes against
+fn uri_to_path(uri: &str) -> String {
+    let path = match uri.strip_prefix("file://") {
+        Some(path) => path,
+        None => return String::from(uri)
+    };
+
+    // percent decoding, done on bytes as an escape may be part of a character
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 3)
+            .and_then(|hex| ::std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            },
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The `file://` URI of a path, for files the assembler found through includes
+fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        }
+        else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    uri
+}
+
+/// Read a message framed by a `Content-Length` header. None at the end of input
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut content = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut content)?;
+    Ok(Some(String::from_utf8_lossy(&content).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::codes;
+    use assembler::diagnostic::{Location, Related};
+
+    const URI: &str = "file:///tmp/game%20one.asm";
+
+    fn request(id: usize, method: &str, params: &str) -> Value {
+        json::parse(&format!(r#"{{"jsonrpc": "2.0", "id": {}, "method": "{}", "params": {}}}"#, id, method, params)).unwrap()
+    }
+
+    fn position(line: usize, character: usize) -> String {
+        format!(r#"{{"textDocument": {{"uri": "{}"}}, "position": {{"line": {}, "character": {}}}, "context": {{"includeDeclaration": true}}}}"#,
+            URI, line, character)
+    }
+
+    fn open(server: &mut Server, text: &str) -> Vec<Value> {
+        let params = Value::object(vec![(
+            "textDocument",
+            Value::object(vec![("uri", Value::string(URI)), ("text", Value::string(text))])
+        )]);
+        server.handle(&Value::object(vec![("method", Value::string("textDocument/didOpen")), ("params", params)]))
+    }
+
+    fn result(server: &mut Server, method: &str, params: &str) -> Value {
+        server.handle(&request(1, method, params))[0].get("result").cloned().unwrap()
+    }
+
+    const SOURCE: &str = "start\tLD V0, 5\n\tCALL #draw\n\tJP #start\ndraw\tLD I, #ship\n\tRET\nship\n\tdb $18 $3C\n";
+
+    #[test]
+    fn test_initialize() {
+        let mut server = Server::new();
+        let reply = server.handle(&request(1, "initialize", "{}"));
+
+        assert_eq!(reply[0].path(&["result", "capabilities", "hoverProvider"]), Some(&Value::Bool(true)));
+        assert_eq!(server.handle(&request(2, "shutdown", "null"))[0].get("result"), Some(&Value::Null));
+        assert_eq!(server.handle(&request(3, "textDocument/hover", "{}"))[0].path(&["error", "code"]), Some(&Value::Number(-32600.0)));
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        let reply = Server::new().handle(&request(4, "workspace/symbol", "{}"));
+
+        assert_eq!(reply[0].get("id"), Some(&Value::from(4)));
+        assert_eq!(reply[0].path(&["error", "message"]).and_then(Value::as_str), Some("Unknown method workspace/symbol"));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut server = Server::new();
+        let reply = open(&mut server, "start\tCLS\n\tJP #strat\n");
+
+        assert_eq!(reply[0].get("method").and_then(Value::as_str), Some("textDocument/publishDiagnostics"));
+        assert_eq!(reply[0].path(&["params", "diagnostics"]).unwrap().to_string(),
+            r#"[{"range":{"start":{"line":1,"character":1},"end":{"line":1,"character":10}},"severity":1,"code":"E009","source":"silica","message":"Undefined label: strat, did you mean start?"}]"#);
+
+        let reply = open(&mut server, SOURCE);
+        assert_eq!(reply[0].path(&["params", "diagnostics"]), Some(&Value::Array(vec![])));
+    }
+
+    #[test]
+    fn test_definition_and_references() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+
+        let definition = result(&mut server, "textDocument/definition", &position(1, 8));
+        assert_eq!(definition.to_string(),
+            r#"{"uri":"file:///tmp/game%20one.asm","range":{"start":{"line":3,"character":0},"end":{"line":3,"character":4}}}"#);
+
+        let references = result(&mut server, "textDocument/references", &position(0, 2));
+        let lines: Vec<usize> = references.as_array().unwrap().iter()
+            .map(|location| location.path(&["range", "start", "line"]).and_then(Value::as_usize).unwrap())
+            .collect();
+        assert_eq!(lines, vec![0, 2]);
+        assert_eq!(references.as_array().unwrap()[1].path(&["range", "start", "character"]), Some(&Value::from(5)));
+    }
+
+    #[test]
+    fn test_hover() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+
+        let hover = result(&mut server, "textDocument/hover", &position(3, 13));
+        assert_eq!(hover.path(&["contents", "value"]).and_then(Value::as_str), Some("`$0206`: `A20A`\n\n`ship` = `$020A`"));
+
+        let hover = result(&mut server, "textDocument/hover", &position(6, 6));
+        assert_eq!(hover.path(&["contents", "value"]).and_then(Value::as_str), Some("`$020A`: `18 3C`"));
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+
+        let labels = |items: Value| -> Vec<String> {
+            items.as_array().unwrap().iter().map(|item| String::from(item.get("label").and_then(Value::as_str).unwrap())).collect()
+        };
+
+        let mnemonics = labels(result(&mut server, "textDocument/completion", &position(4, 2)));
+        assert!(mnemonics.contains(&String::from("RET")) && mnemonics.contains(&String::from("db")));
+        assert!(!mnemonics.contains(&String::from("V0")));
+
+        let operands = labels(result(&mut server, "textDocument/completion", &position(0, 10)));
+        assert!(operands.contains(&String::from("V0")) && operands.contains(&String::from("#draw")));
+        assert!(!operands.contains(&String::from("LD")));
+
+        assert_eq!(result(&mut server, "textDocument/completion", &position(0, 3)), Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_document_symbols() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+
+        let symbols = result(&mut server, "textDocument/documentSymbol", &format!(r#"{{"textDocument": {{"uri": "{}"}}}}"#, URI));
+        let symbols: Vec<(String, String, usize)> = symbols.as_array().unwrap().iter().map(|symbol| (
+            String::from(symbol.get("name").and_then(Value::as_str).unwrap()),
+            String::from(symbol.get("detail").and_then(Value::as_str).unwrap()),
+            symbol.get("kind").and_then(Value::as_usize).unwrap()
+        )).collect();
+
+        assert_eq!(symbols, vec![
+            (String::from("start"), String::from("$0200"), SYMBOL_FUNCTION),
+            (String::from("draw"), String::from("$0206"), SYMBOL_FUNCTION),
+            (String::from("ship"), String::from("$020A"), SYMBOL_CONSTANT)
+        ]);
+    }
+
+    #[test]
+    fn test_run() {
+        let initialize = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#;
+        let input = format!("Content-Length: {}\r\n\r\n{}Content-Length: 17\r\n\r\n{{\"method\":\"exit\"}}", initialize.len(), initialize);
+        let mut output = Vec::new();
+
+        Server::new().run(input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Content-Length: "));
+        assert_eq!(output.matches("Content-Length").count(), 1);
+        assert!(output.contains(r#""id":1,"result":{"capabilities""#));
+    }
+
+    #[test]
+    fn test_utf16_offsets() {
+        // 😀 is four bytes and two UTF-16 units, é two bytes and one unit
+        let line = "\tCLS ; 😀 é #start";
+
+        assert_eq!(byte_to_utf16(line, 7), 7);
+        assert_eq!(byte_to_utf16(line, 12), 10);
+        assert_eq!(byte_to_utf16(line, 15), 12);
+        assert_eq!(byte_to_utf16(line, line.len() + 2), 20);
+        assert_eq!(utf16_to_byte(line, 10), 12);
+        assert_eq!(utf16_to_byte(line, 12), 15);
+        assert_eq!(utf16_to_byte(line, 30), line.len());
+    }
+
+    #[test]
+    fn test_non_ascii_line() {
+        let mut server = Server::new();
+        let reply = open(&mut server, "start\tCLS ; 😀 #start x\n\tinclude \"é.asm\" ; là\n");
+
+        // the include statement is 17 bytes and 16 UTF-16 units long
+        assert_eq!(reply[0].path(&["params", "diagnostics"]).and_then(Value::as_array).unwrap()[0].get("range").unwrap().to_string(),
+            r#"{"start":{"line":1,"character":1},"end":{"line":1,"character":16}}"#);
+
+        // just past #start in the comment, counting 😀 as two units
+        let definition = result(&mut server, "textDocument/definition", &position(0, 21));
+        assert_eq!(definition.path(&["range", "end"]).unwrap().to_string(), r#"{"line":0,"character":5}"#);
+    }
+
+    #[test]
+    fn test_uri_to_path() {
+        assert_eq!(uri_to_path("file:///home/me/my%20game.asm"), "/home/me/my game.asm");
+        assert_eq!(uri_to_path("untitled:1"), "untitled:1");
+        assert_eq!(path_to_uri("/home/me/my game.asm"), "file:///home/me/my%20game.asm");
+    }
+
+    #[test]
+    fn test_related_information() {
+        let location = Location{file: String::from("/a/game.asm"), line: 3, column: 1, end_column: 4};
+        let earlier = Location{file: String::from("/a/lib.asm"), line: 1, column: 1, end_column: 4};
+        let diagnostic = Diagnostic::new(Severity::Error, codes::OVERLAPPING_WRITE, "Write to $200 overlaps earlier code", Some(location))
+            .with_related(vec![Related::new("Earlier code writing $200", earlier)]);
+        let value = to_lsp_diagnostic("file:///a/game.asm", "/a/game.asm", "", &diagnostic);
+
+        assert_eq!(value.get("relatedInformation").unwrap().to_string(),
+            r#"[{"location":{"uri":"file:///a/lib.asm","range":{"start":{"line":0,"character":0},"end":{"line":0,"character":3}}},"message":"Earlier code writing $200"}]"#);
+    }
+}
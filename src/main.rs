@@ -1,20 +1,594 @@
 extern crate silica;
 
+use silica::assembler::{Assembler, AssemblyOutput, FileSystemProvider, Linker, MemoryMap, ObjectFile, Severity, Target};
+use silica::assembler::codes;
+use silica::assembler::diagnostic::Diagnostic;
+use silica::assembler::section::SECTIONS;
+use silica::debugger::Debugger;
+use silica::disassembler;
+use silica::flow::ControlFlow;
+use silica::formatter;
+use silica::interpreter::Chip8;
+use silica::lint::{self, Lints};
+use silica::lsp::Server;
+use silica::manifest::{Build, Manifest};
+use silica::quirks::Quirks;
+use silica::timing;
+use silica::options::{parse_define, parse_number, ProgramOptions};
+use silica::output::{self, Format};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
 use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often `--watch` looks for changed files
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How much is printed besides diagnostics
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose
+}
+
+/// Prints diagnostics and messages the way the global options ask for
+struct Ui {
+    verbosity: Verbosity,
+    color: bool,
+    json: bool
+}
+
+impl Ui {
+    fn new(options: &ProgramOptions) -> Self {
+        let json = match options.flag_message_format.as_ref() {
+            "human" => false,
+            "json" => true,
+            _ => {
+                println!("Unknown message format: {}", options.flag_message_format);
+                process::exit(1);
+            }
+        };
+        let color = match options.flag_color.as_ref() {
+            "auto" => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+            "always" => true,
+            "never" => false,
+            _ => {
+                println!("Unknown color setting: {}", options.flag_color);
+                process::exit(1);
+            }
+        };
+        let verbosity = if options.flag_quiet {
+            Verbosity::Quiet
+        }
+        else if options.flag_verbose {
+            Verbosity::Verbose
+        }
+        else {
+            Verbosity::Normal
+        };
+
+        Ui{verbosity, color, json}
+    }
+
+    fn diagnostic(&self, diagnostic: &Diagnostic) {
+        if self.verbosity == Verbosity::Quiet && !diagnostic.is_error() {
+            return;
+        }
+
+        if self.json {
+            println!("{}", output::to_diagnostic_json(diagnostic));
+        }
+        else if self.color {
+            println!("{}", output::to_colored(diagnostic));
+        }
+        else {
+            println!("{}", diagnostic);
+        }
+    }
+
+    fn diagnostics(&self, diagnostics: &[Diagnostic]) {
+        for diagnostic in diagnostics.iter() {
+            self.diagnostic(diagnostic);
+        }
+    }
+
+    /// Report of what was done. Goes to stderr when stdout is for JSON diagnostics
+    fn info(&self, message: &str) {
+        if self.verbosity >= Verbosity::Normal {
+            self.print(message);
+        }
+    }
+
+    fn verbose(&self, message: &str) {
+        if self.verbosity >= Verbosity::Verbose {
+            self.print(message);
+        }
+    }
+
+    fn print(&self, message: &str) {
+        if self.json {
+            eprintln!("{}", message);
+        }
+        else {
+            println!("{}", message);
+        }
+    }
+
+    /// Report an error that stops silica
+    fn fail(&self, code: &'static str, message: &str) -> ! {
+        self.diagnostic(&Diagnostic::new(Severity::Error, code, message, None));
+        process::exit(1);
+    }
+}
+
+/// Settings shared by the commands that assemble, checked
+struct Settings {
+    format: Format,
+    target: Target,
+    graph_json: bool,
+    quirks: Quirks,
+    lints: Lints,
+    base: u32,
+    fill: u32,
+    places: Vec<(String, u32)>,
+    memory_map: Option<MemoryMap>
+}
+
+impl Settings {
+    fn new(ui: &Ui, options: &ProgramOptions) -> Self {
+        let format = Format::from_name(&options.flag_format).unwrap_or_else(
+            || ui.fail(codes::INVALID_CONFIGURATION, &format!("Unknown output format: {}", options.flag_format))
+        );
+        let target = Target::from_name(&options.flag_target).unwrap_or_else(
+            || ui.fail(codes::INVALID_CONFIGURATION, &format!("Unknown target: {}", options.flag_target))
+        );
+        let graph_json = match options.flag_graph_format.as_ref() {
+            "dot" => false,
+            "json" => true,
+            _ => ui.fail(codes::INVALID_CONFIGURATION, &format!("Unknown graph format: {}", options.flag_graph_format))
+        };
+        let mut quirks = match options.flag_quirks {
+            Some(ref profile) => Quirks::from_name(profile).unwrap_or_else(
+                || ui.fail(codes::INVALID_CONFIGURATION, &format!("Unknown quirks profile: {}", profile))
+            ),
+            None => target.quirks()
+        };
+        for quirk in options.flag_quirk.iter() {
+            if !quirks.set(quirk) {
+                ui.fail(codes::INVALID_CONFIGURATION, &format!("Unknown quirk: {}", quirk));
+            }
+        }
+        let mut lints = Lints::new();
+        for name in options.flag_lint.iter() {
+            if !lints.set(name) {
+                ui.fail(codes::INVALID_CONFIGURATION, &format!("Unknown lint: {}", name));
+            }
+        }
+        let base = parse_number(&options.flag_base).filter(|&base| base < target.memory_size()).unwrap_or_else(
+            || ui.fail(codes::INVALID_CONFIGURATION, &format!("Invalid base address: {}", options.flag_base))
+        );
+        let fill = parse_number(&options.flag_fill).filter(|&fill| fill <= 0xFF).unwrap_or_else(
+            || ui.fail(codes::INVALID_CONFIGURATION, &format!("Invalid fill byte: {}", options.flag_fill))
+        );
+
+        let mut places = Vec::new();
+        for place in options.flag_place.iter() {
+            let (section, address) = parse_define(place).filter(|(section, _)| SECTIONS.contains(&section.as_ref())).unwrap_or_else(
+                || ui.fail(codes::INVALID_CONFIGURATION, &format!("Invalid section placement: {}", place))
+            );
+            places.push((section, address));
+        }
+        let memory_map = options.flag_memory_map.as_ref().map(|file| {
+            if !places.is_empty() {
+                ui.fail(codes::INVALID_CONFIGURATION, "Sections are placed by either --place or --memory-map, not both");
+            }
+            load_memory_map(ui, file, target)
+        });
+
+        Settings{format, target, graph_json, quirks, lints, base, fill, places, memory_map}
+    }
+}
 
 fn main() {
     let options = silica::options::get_program_options();
-    let input_data = silica::load_file(&options.arg_input).unwrap_or_else(
+    let ui = Ui::new(&options);
+
+    if let Some(ref code) = options.flag_explain {
+        match codes::explain(code) {
+            Some(explanation) => print!("{}", explanation),
+            None => ui.fail(codes::INVALID_CONFIGURATION, &format!("Unknown code: {}", code))
+        }
+    }
+    else if options.cmd_build {
+        build(&ui, &options.flag_manifest, &options.arg_profile);
+    }
+    else if options.cmd_fmt {
+        fmt(&ui, &options.arg_file, options.flag_check);
+    }
+    else if options.cmd_disasm {
+        disasm(&ui, &options, &Settings::new(&ui, &options));
+    }
+    else if options.cmd_link {
+        link(&ui, &options, &Settings::new(&ui, &options));
+    }
+    else if options.cmd_lsp {
+        lsp(&ui, &options, &Settings::new(&ui, &options));
+    }
+    else {
+        // asm is the default command
+        assemble(&ui, &options, &Settings::new(&ui, &options));
+    }
+}
+
+/// Assemble the input, then do what the command asks with the program
+fn assemble(ui: &Ui, options: &ProgramOptions, settings: &Settings) {
+    let mut assembler = Assembler::new()
+        .target(settings.target)
+        .source_provider(FileSystemProvider::new())
+        .base_address(settings.base)
+        .fill(settings.fill as u8)
+        .full_image(options.flag_full_image)
+        .overlap(if options.flag_allow_overlap { Severity::Warning } else { Severity::Error })
+        .quirks(settings.quirks)
+        .optimize(options.flag_optimize)
+        .warnings_as_errors(options.flag_warnings_as_errors);
+
+    for define in options.flag_define.iter() {
+        let (name, value) = parse_define(define).unwrap_or_else(
+            || ui.fail(codes::INVALID_CONFIGURATION, &format!("Invalid definition: {}", define))
+        );
+        assembler = assembler.define(&name, value);
+    }
+    for (section, address) in settings.places.iter() {
+        assembler = assembler.section_address(section, *address);
+    }
+    if let Some(ref map) = settings.memory_map {
+        assembler = assembler.memory_map(map.clone());
+    }
+
+    if options.flag_watch {
+        if options.flag_compile || options.cmd_check || options.cmd_debug || options.cmd_run {
+            ui.fail(codes::INVALID_CONFIGURATION, "--watch only works when assembling a ROM");
+        }
+        watch(ui, options, settings, &assembler);
+    }
+
+    if options.flag_compile {
+        // objects are named after the source unless told otherwise
+        let output_file = options.flag_output.clone().unwrap_or_else(
+            || Path::new(&options.arg_input).with_extension("o").to_string_lossy().into_owned()
+        );
+
+        match assembler.assemble_object_file(&options.arg_input) {
+            Ok(output) => {
+                ui.diagnostics(&output.diagnostics);
+                silica::write_to_file(&output_file, output.object.to_bytes());
+                ui.verbose(&format!("Wrote {} sections to {}", output.object.sections.len(), output_file));
+            },
+            Err(e) => {
+                ui.diagnostics(&e.diagnostics);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let output = assembler.assemble_file(&options.arg_input).unwrap_or_else(
         |e| {
-            println!("Could not load input file: {:?}", e);
+            ui.diagnostics(&e.diagnostics);
             process::exit(1);
         }
     );
+    ui.diagnostics(&output.diagnostics);
+    let base = settings.base;
 
-    match silica::assembler::assemble(input_data) {
-        Ok(data) => {
-            silica::write_to_file(data)
+    if options.cmd_check {
+        let warnings = lint::check(&output, base as u16, settings.target, &settings.lints);
+        ui.diagnostics(&warnings);
+        if options.flag_warnings_as_errors && !warnings.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if options.cmd_debug || options.cmd_run {
+        let chip8 = Chip8::with_quirks(settings.target.memory_size() as usize, settings.quirks);
+        let mut debugger = Debugger::new(&output, &options.arg_input, base as u16, chip8).unwrap_or_else(
+            |e| ui.fail(codes::OUTSIDE_MEMORY, &e.to_string())
+        );
+        if options.cmd_run {
+            ui.info(&debugger.execute("continue"));
+            println!("{}", debugger.execute("display"));
+        }
+        else {
+            let stdin = io::stdin();
+            debugger.run(stdin.lock(), io::stdout()).unwrap();
+        }
+        return;
+    }
+
+    write_outputs(ui, options, settings, &assembler, &output);
+}
+
+/// Assemble the input again whenever it or a file it reads changes, until interrupted.
+/// Files that did not change are not lexed and parsed again
+fn watch(ui: &Ui, options: &ProgramOptions, settings: &Settings, assembler: &Assembler) -> ! {
+    loop {
+        let started = Instant::now();
+        match assembler.assemble_file(&options.arg_input) {
+            Ok(output) => {
+                ui.diagnostics(&output.diagnostics);
+                write_outputs(ui, options, settings, assembler, &output);
+                ui.info(&format!("Assembled {} in {} ms", options.arg_input, started.elapsed().as_millis()));
+            },
+            Err(e) => ui.diagnostics(&e.diagnostics)
+        }
+
+        let mut files = assembler.dependencies();
+        if files.is_empty() {
+            files.push(options.arg_input.clone());
+        }
+        ui.verbose(&format!("Lexed and parsed {} of {} files, watching them for changes", assembler.files_parsed(), files.len()));
+
+        let changed = wait_for_change(&files);
+        ui.info(&format!("{} changed", changed));
+    }
+}
+
+/// Wait until one of the files is modified, created or removed, giving its name
+fn wait_for_change(files: &[String]) -> String {
+    let modified = |file: &String| fs::metadata(file).and_then(|metadata| metadata.modified()).ok();
+    let times: Vec<Option<SystemTime>> = files.iter().map(modified).collect();
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if let Some((file, _)) = files.iter().zip(times.iter()).find(|&(file, time)| modified(file) != *time) {
+            // editors may save a file in more than one write
+            thread::sleep(POLL_INTERVAL);
+            return file.clone();
+        }
+    }
+}
+
+/// Write the program and whatever else the options ask for. The listing takes the
+/// source text from the assembler that produced `output`
+fn write_outputs(ui: &Ui, options: &ProgramOptions, settings: &Settings, assembler: &Assembler, output: &AssemblyOutput) {
+    let base = settings.base;
+
+    for region in output.regions.iter() {
+        ui.info(&region.to_string());
+    }
+
+    // name the generated array after the output file
+    let output_file = options.flag_output.clone().unwrap_or(format!("output.{}", settings.format.extension()));
+    let name = Path::new(&output_file).file_stem().and_then(|s| s.to_str()).unwrap_or("rom");
+    silica::write_to_file(&output_file, output::render(settings.format, name, &output.bytes, &output.symbols));
+    ui.verbose(&format!("Wrote {} bytes to {}", output.bytes.len(), output_file));
+
+    if let Some(ref source_map_file) = options.flag_source_map {
+        silica::write_to_file(source_map_file, output::to_source_map_json(&output.source_map).into_bytes());
+    }
+
+    if let Some(ref symbols_file) = options.flag_symbols {
+        silica::write_to_file(symbols_file, output::to_symbols(&output.symbols).into_bytes());
+    }
+
+    if let Some(ref listing_file) = options.flag_listing {
+        let mut sources = HashMap::new();
+        for entry in output.source_map.iter() {
+            let file = &entry.location.file;
+            sources.entry(file.clone()).or_insert_with(
+                || assembler.source(file).map(|source| String::from_utf8_lossy(&source).into_owned()).unwrap_or_default()
+            );
+        }
+        silica::write_to_file(listing_file, output::to_listing(&output.source_map, &output.bytes, base, &sources).into_bytes());
+    }
+
+    if options.flag_cfg.is_some() || options.flag_call_graph.is_some() {
+        let flow = ControlFlow::build(output, base as u16);
+        if let Some(ref cfg_file) = options.flag_cfg {
+            let graph = if settings.graph_json { output::to_cfg_json(&flow) } else { output::to_cfg_dot(&flow) };
+            silica::write_to_file(cfg_file, graph.into_bytes());
+        }
+        if let Some(ref call_graph_file) = options.flag_call_graph {
+            let graph = if settings.graph_json { output::to_call_graph_json(&flow) } else { output::to_call_graph_dot(&flow) };
+            silica::write_to_file(call_graph_file, graph.into_bytes());
+        }
+    }
+
+    if options.flag_timing {
+        print!("{}", timing::report(&timing::analyze(output, base as u16)));
+    }
+}
+
+/// List the instructions of a ROM, to the output file if there is one
+fn disasm(ui: &Ui, options: &ProgramOptions, settings: &Settings) {
+    let image = silica::load_file(&options.arg_input).unwrap_or_else(
+        |e| ui.fail(codes::UNREADABLE_FILE, &format!("Could not read {}: {}", options.arg_input, e))
+    );
+
+    let lines: Vec<String> = disassembler::disassemble(&image, settings.base as u16, &HashMap::new())
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    let listing = lines.join("\n") + "\n";
+
+    match options.flag_output {
+        Some(ref output_file) => silica::write_to_file(output_file, listing.into_bytes()),
+        None => print!("{}", listing)
+    }
+}
+
+/// Reformat source files in place, or with `check` only list the files that are not
+/// formatted and fail if there are any
+fn fmt(ui: &Ui, files: &[String], check: bool) {
+    let mut unformatted = 0;
+
+    for file in files.iter() {
+        let source = silica::load_file(file).unwrap_or_else(
+            |e| ui.fail(codes::UNREADABLE_FILE, &format!("Could not read {}: {}", file, e))
+        );
+        let source = String::from_utf8_lossy(&source);
+
+        let formatted = formatter::format(file, &source).unwrap_or_else(|diagnostic| {
+            ui.diagnostic(&diagnostic);
+            process::exit(1);
+        });
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            ui.print(&format!("{} is not formatted", file));
+            unformatted += 1;
+        }
+        else {
+            silica::write_to_file(file, formatted.into_bytes());
+            ui.verbose(&format!("Formatted {}", file));
+        }
+    }
+
+    if unformatted > 0 {
+        process::exit(1);
+    }
+}
+
+/// Serve editors over stdin and stdout until they exit
+fn lsp(ui: &Ui, options: &ProgramOptions, settings: &Settings) {
+    let mut server = Server::new()
+        .target(settings.target)
+        .quirks(settings.quirks)
+        .base_address(settings.base);
+    for define in options.flag_define.iter() {
+        let (name, value) = parse_define(define).unwrap_or_else(
+            || ui.fail(codes::INVALID_CONFIGURATION, &format!("Invalid definition: {}", define))
+        );
+        server = server.define(&name, value);
+    }
+
+    let stdin = io::stdin();
+    server.run(stdin.lock(), io::stdout()).unwrap();
+}
+
+fn link(ui: &Ui, options: &ProgramOptions, settings: &Settings) {
+    let mut linker = Linker::new()
+        .target(settings.target)
+        .base_address(settings.base)
+        .fill(settings.fill as u8);
+    for (section, address) in settings.places.iter() {
+        linker = linker.section_address(section, *address);
+    }
+    if let Some(ref map) = settings.memory_map {
+        linker = linker.memory_map(map.clone());
+    }
+
+    for file in options.arg_object.iter() {
+        let object = silica::load_file(file)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| ObjectFile::from_bytes(&bytes).map_err(|e| e.to_string()))
+            .unwrap_or_else(
+                |e| ui.fail(codes::UNREADABLE_FILE, &format!("Could not read {}: {}", file, e))
+            );
+        linker = linker.object(file, object);
+    }
+
+    match linker.link() {
+        Ok(output) => {
+            for region in output.regions.iter() {
+                ui.info(&region.to_string());
+            }
+            let output_file = options.flag_output.clone().unwrap_or(format!("output.{}", settings.format.extension()));
+            let name = Path::new(&output_file).file_stem().and_then(|s| s.to_str()).unwrap_or("rom");
+            silica::write_to_file(&output_file, output::render(settings.format, name, &output.bytes, &output.symbols));
+            ui.verbose(&format!("Wrote {} bytes to {}", output.bytes.len(), output_file));
         },
-        Err(e) => println!("{:?}", e)
+        Err(e) => {
+            ui.diagnostics(&e.diagnostics);
+            process::exit(1);
+        }
+    }
+}
+
+fn load_memory_map(ui: &Ui, file: &str, target: Target) -> MemoryMap {
+    silica::load_file(&String::from(file))
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| MemoryMap::parse(&String::from_utf8_lossy(&bytes), target).map_err(|e| e.to_string()))
+        .unwrap_or_else(
+            |e| ui.fail(codes::INVALID_CONFIGURATION, &format!("Could not read memory map {}: {}", file, e))
+        )
+}
+
+/// Build the given profiles of a manifest, or every profile when none are given
+fn build(ui: &Ui, manifest_file: &str, profiles: &[String]) {
+    let manifest = silica::load_file(&String::from(manifest_file))
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| Manifest::parse(&String::from_utf8_lossy(&bytes)).map_err(|e| e.to_string()))
+        .unwrap_or_else(
+            |e| ui.fail(codes::INVALID_CONFIGURATION, &format!("Could not read {}: {}", manifest_file, e))
+        );
+
+    let names: Vec<Option<&str>> = if !profiles.is_empty() {
+        profiles.iter().map(|name| Some(name.as_ref())).collect()
+    }
+    else if !manifest.profiles.is_empty() {
+        manifest.profiles.iter().map(|(name, _)| Some(name.as_ref())).collect()
+    }
+    else {
+        vec![None]
+    };
+
+    // paths in the manifest are relative to it
+    let dir = Path::new(manifest_file).parent().unwrap_or_else(|| Path::new(""));
+    let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+
+    let builds = manifest.builds(&names).unwrap_or_else(
+        |e| ui.fail(codes::INVALID_CONFIGURATION, &e.to_string())
+    );
+    for build in builds.into_iter() {
+        let Build{profile, entry, include, target, quirks, outputs, base, fill, optimize, memory_map, defines} = build;
+
+        let mut provider = FileSystemProvider::new();
+        for dir in include.iter() {
+            provider = provider.with_include_dir(path(dir));
+        }
+        let mut assembler = Assembler::new()
+            .target(target)
+            .source_provider(provider)
+            .base_address(base)
+            .fill(fill)
+            .quirks(quirks)
+            .optimize(optimize);
+        for (name, value) in defines.iter() {
+            assembler = assembler.define(name, *value);
+        }
+        if let Some(ref file) = memory_map {
+            assembler = assembler.memory_map(load_memory_map(ui, &path(file), target));
+        }
+
+        let output = assembler.assemble_file(&path(&entry)).unwrap_or_else(
+            |e| {
+                ui.diagnostics(&e.diagnostics);
+                process::exit(1);
+            }
+        );
+        ui.diagnostics(&output.diagnostics);
+        for region in output.regions.iter() {
+            ui.info(&region.to_string());
+        }
+
+        let mut written = Vec::new();
+        for (format, file) in outputs.iter() {
+            let file = path(file);
+            let name = Path::new(&file).file_stem().and_then(|s| s.to_str()).unwrap_or("rom");
+            silica::write_to_file(&file, output::render(*format, name, &output.bytes, &output.symbols));
+            written.push(file.clone());
+        }
+        ui.info(&format!("Built {}: {}", profile.unwrap_or_else(|| String::from("project")), written.join(", ")));
     }
 }
@@ -0,0 +1,483 @@
+use assembler::Target;
+use options::parse_number;
+use output::Format;
+use quirks::Quirks;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// Problem in a manifest. The line is 0 for problems with a build as a whole
+#[derive(Debug, PartialEq)]
+pub struct ManifestError {
+    line: usize,
+    message: String
+}
+
+impl ManifestError {
+    fn new(line: usize, message: String) -> Self {
+        ManifestError{line, message}
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+        else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl Error for ManifestError {}
+
+/// Settings of a build as written in the manifest. Anything a profile leaves out
+/// comes from the settings at the top of the manifest
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Settings {
+    pub entry: Option<String>,
+    pub include: Vec<String>,
+    pub target: Option<String>,
+    pub quirks: Option<String>,
+    pub quirk: Vec<String>,
+    pub formats: Vec<String>,
+    pub output: Option<String>,
+    pub base: Option<u32>,
+    pub fill: Option<u32>,
+    pub optimize: Option<bool>,
+    pub memory_map: Option<String>,
+    pub defines: Vec<(String, u32)>
+}
+
+impl Settings {
+    /// These settings with those of `profile` on top. Lists are added to and defines replaced by name
+    fn merge(&self, profile: &Settings) -> Settings {
+        let mut defines = self.defines.clone();
+        for (name, value) in profile.defines.iter() {
+            defines.retain(|(existing, _)| existing != name);
+            defines.push((name.clone(), *value));
+        }
+
+        Settings {
+            entry: profile.entry.clone().or_else(|| self.entry.clone()),
+            include: self.include.iter().chain(profile.include.iter()).cloned().collect(),
+            target: profile.target.clone().or_else(|| self.target.clone()),
+            quirks: profile.quirks.clone().or_else(|| self.quirks.clone()),
+            quirk: self.quirk.iter().chain(profile.quirk.iter()).cloned().collect(),
+            formats: if profile.formats.is_empty() { self.formats.clone() } else { profile.formats.clone() },
+            output: profile.output.clone().or_else(|| self.output.clone()),
+            base: profile.base.or(self.base),
+            fill: profile.fill.or(self.fill),
+            optimize: profile.optimize.or(self.optimize),
+            memory_map: profile.memory_map.clone().or_else(|| self.memory_map.clone()),
+            defines
+        }
+    }
+}
+
+/// Everything needed to build a profile, checked and with defaults filled in.
+/// Paths are as written in the manifest, relative to it
+#[derive(Debug, PartialEq)]
+pub struct Build {
+    pub profile: Option<String>,
+    pub entry: String,
+    pub include: Vec<String>,
+    pub target: Target,
+    pub quirks: Quirks,
+    /// each format to write and the file to write it to
+    pub outputs: Vec<(Format, String)>,
+    pub base: u32,
+    pub fill: u8,
+    pub optimize: bool,
+    pub memory_map: Option<String>,
+    pub defines: Vec<(String, u32)>
+}
+
+/// A project manifest, `silica.toml`, written in a subset of TOML:
+///
+/// ```toml
+/// entry = "src/game.asm"
+/// include = ["lib"]
+/// formats = ["binary", "c-header"]
+///
+/// [defines]
+/// LIVES = 3
+///
+/// [profile.schip]
+/// target = "schip"
+/// output = "build/game-schip"
+/// ```
+///
+/// Settings at the top are shared by every profile
+#[derive(Debug, PartialEq, Default)]
+pub struct Manifest {
+    pub settings: Settings,
+    /// profiles in the order they are declared
+    pub profiles: Vec<(String, Settings)>
+}
+
+impl Manifest {
+    pub fn parse(source: &str) -> Result<Manifest, ManifestError> {
+        let mut manifest = Manifest::default();
+        let mut table: Vec<String> = Vec::new();
+        let mut seen = HashSet::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let error = |message: String| ManifestError::new(line_number, message);
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let header = line.split('#').next().unwrap_or("").trim();
+                if !header.ends_with(']') {
+                    return Err(error(String::from("Expected ] at the end of the table name")));
+                }
+                table = header[1..header.len() - 1].split('.').map(|part| String::from(part.trim())).collect();
+                manifest.settings_for(&table).map_err(&error)?;
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let rest = parts.next().ok_or_else(|| error(format!("Expected KEY = VALUE, found {}", line)))?;
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                return Err(error(format!("Invalid key {}", key)));
+            }
+            if !seen.insert((table.clone(), String::from(key))) {
+                return Err(error(format!("Duplicate key {}", key)));
+            }
+
+            let (value, rest) = parse_value(rest).map_err(&error)?;
+            let rest = rest.trim();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(error(format!("Unexpected {} after the value of {}", rest, key)));
+            }
+
+            let is_defines = table.last().map(|name| name == "defines").unwrap_or(false);
+            let settings = manifest.settings_for(&table).map_err(&error)?;
+            if is_defines {
+                match value {
+                    Value::Integer(value) => settings.defines.push((String::from(key), value)),
+                    _ => return Err(error(format!("Define {} must be a number", key)))
+                }
+            }
+            else {
+                set(settings, key, value).map_err(&error)?;
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Settings of the table named by `path`, declaring profiles as they are found
+    fn settings_for(&mut self, path: &[String]) -> Result<&mut Settings, String> {
+        let parts: Vec<&str> = path.iter().map(|part| part.as_ref()).collect();
+        match parts[..] {
+            [] | ["defines"] => Ok(&mut self.settings),
+            ["profile", name] | ["profile", name, "defines"] if !name.is_empty() => {
+                if !self.profiles.iter().any(|(profile, _)| profile == name) {
+                    self.profiles.push((String::from(name), Settings::default()));
+                }
+                Ok(&mut self.profiles.iter_mut().find(|(profile, _)| profile == name).unwrap().1)
+            },
+            _ => Err(format!("Unknown table {}, expected defines, profile.NAME or profile.NAME.defines", path.join(".")))
+        }
+    }
+
+    /// What to build for a profile, or for the settings at the top when no profile is given
+    pub fn build(&self, profile: Option<&str>) -> Result<Build, ManifestError> {
+        let error = |message: String| ManifestError::new(0, message);
+
+        let settings = match profile {
+            Some(name) => match self.profiles.iter().find(|(profile, _)| profile == name) {
+                Some((_, settings)) => self.settings.merge(settings),
+                None => return Err(error(format!("Unknown profile {}", name)))
+            },
+            None => self.settings.clone()
+        };
+        let described = profile.map(|name| format!("Profile {}", name)).unwrap_or_else(|| String::from("The manifest"));
+
+        let entry = settings.entry.clone().ok_or_else(|| error(format!("{} has no entry", described)))?;
+        let target_name = settings.target.clone().unwrap_or_else(|| String::from("chip8"));
+        let target = Target::from_name(&target_name).ok_or_else(|| error(format!("Unknown target: {}", target_name)))?;
+
+        let mut quirks = match settings.quirks {
+            Some(ref name) => Quirks::from_name(name).ok_or_else(|| error(format!("Unknown quirks profile: {}", name)))?,
+            None => target.quirks()
+        };
+        for quirk in settings.quirk.iter() {
+            if !quirks.set(quirk) {
+                return Err(error(format!("Unknown quirk: {}", quirk)));
+            }
+        }
+
+        let base = settings.base.unwrap_or(0x200);
+        if base >= target.memory_size() {
+            return Err(error(format!("Invalid base address: ${:X}", base)));
+        }
+        let fill = settings.fill.unwrap_or(0);
+        if fill > 0xFF {
+            return Err(error(format!("Invalid fill byte: ${:X}", fill)));
+        }
+
+        let formats = if settings.formats.is_empty() { vec![String::from("binary")] } else { settings.formats.clone() };
+        let mut outputs = Vec::new();
+        for name in formats.iter() {
+            let format = Format::from_name(name).ok_or_else(|| error(format!("Unknown output format: {}", name)))?;
+            outputs.push((format, output_file(&settings.output, &entry, format, formats.len() > 1)));
+        }
+
+        Ok(Build {
+            profile: profile.map(String::from),
+            entry,
+            include: settings.include,
+            target,
+            quirks,
+            outputs,
+            base,
+            fill: fill as u8,
+            optimize: settings.optimize.unwrap_or(false),
+            memory_map: settings.memory_map,
+            defines: settings.defines
+        })
+    }
+
+    /// What to build for each of `profiles`. Builds may not write the same file, as the later
+    /// would overwrite the earlier
+    pub fn builds(&self, profiles: &[Option<&str>]) -> Result<Vec<Build>, ManifestError> {
+        let mut builds: Vec<Build> = Vec::new();
+        for profile in profiles.iter() {
+            let build = self.build(*profile)?;
+            for (_, file) in build.outputs.iter() {
+                let earlier = builds.iter().find(|earlier| earlier.outputs.iter().any(|(_, other)| other == file));
+                if let Some(earlier) = earlier {
+                    let name = |build: &Build| build.profile.clone().unwrap_or_else(|| String::from("project"));
+                    return Err(ManifestError::new(0, format!(
+                        "Profiles {} and {} both write {}, give them different outputs", name(earlier), name(&build), file
+                    )));
+                }
+            }
+            builds.push(build);
+        }
+        Ok(builds)
+    }
+}
+
+/// File a format is written to. Outputs are named after the entry unless named in the manifest,
+/// the extension is that of the format when there is no name or several formats share it
+fn output_file(output: &Option<String>, entry: &str, format: Format, several: bool) -> String {
+    let extension = format.extension();
+
+    match *output {
+        Some(ref output) if !several => output.clone(),
+        Some(ref output) => Path::new(output).with_extension(extension).to_string_lossy().into_owned(),
+        None => Path::new(entry).with_extension(extension).to_string_lossy().into_owned()
+    }
+}
+
+/// Value of a setting
+#[derive(Debug, PartialEq, Clone)]
+enum Value {
+    String(String),
+    Integer(u32),
+    Boolean(bool),
+    Array(Vec<Value>)
+}
+
+fn set(settings: &mut Settings, key: &str, value: Value) -> Result<(), String> {
+    match key {
+        "entry" => settings.entry = Some(string(key, value)?),
+        "target" => settings.target = Some(string(key, value)?),
+        "quirks" => settings.quirks = Some(string(key, value)?),
+        "output" => settings.output = Some(string(key, value)?),
+        "memory-map" => settings.memory_map = Some(string(key, value)?),
+        "include" => settings.include = strings(key, value)?,
+        "quirk" => settings.quirk = strings(key, value)?,
+        "formats" => settings.formats = strings(key, value)?,
+        "base" | "fill" => match value {
+            Value::Integer(number) if key == "base" => settings.base = Some(number),
+            Value::Integer(number) => settings.fill = Some(number),
+            _ => return Err(format!("{} must be a number", key))
+        },
+        "optimize" => match value {
+            Value::Boolean(optimize) => settings.optimize = Some(optimize),
+            _ => return Err(format!("{} must be true or false", key))
+        },
+        _ => return Err(format!("Unknown key {}", key))
+    }
+
+    Ok(())
+}
+
+fn string(key: &str, value: Value) -> Result<String, String> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(format!("{} must be a string", key))
+    }
+}
+
+fn strings(key: &str, value: Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Array(values) => values.into_iter().map(|value| string(key, value)).collect::<Result<_, _>>()
+            .map_err(|_| format!("{} must be a list of strings", key)),
+        _ => Err(format!("{} must be a list of strings", key))
+    }
+}
+
+/// Parse a value at the start of `input`, giving what follows it
+fn parse_value(input: &str) -> Result<(Value, &str), String> {
+    let input = input.trim_start();
+
+    if let Some(rest) = input.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(string), &rest[i + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, c)) if c == '"' || c == '\\' => string.push(c),
+                    _ => return Err(String::from("Unknown escape in string"))
+                },
+                c => string.push(c)
+            }
+        }
+        return Err(String::from("Unterminated string"));
+    }
+
+    if let Some(rest) = input.strip_prefix('[') {
+        let mut values = Vec::new();
+        let mut rest = rest.trim_start();
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            let (value, after) = parse_value(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            }
+            else if !rest.starts_with(']') {
+                return Err(String::from("Expected , or ] in list"));
+            }
+        }
+    }
+
+    let end = input.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(input.len());
+    let (word, rest) = input.split_at(end);
+    match word {
+        "true" => Ok((Value::Boolean(true), rest)),
+        "false" => Ok((Value::Boolean(false), rest)),
+        _ => parse_number(&word.replace('_', ""))
+            .map(|number| (Value::Integer(number), rest))
+            .ok_or_else(|| if word.is_empty() { String::from("Expected a value") } else { format!("Invalid value {}", word) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "# Pong\n\
+                            entry = \"src/pong.asm\"\n\
+                            include = [\"lib\"]\n\
+                            formats = [\"binary\", \"c-header\"]\n\
+                            \n\
+                            [defines]\n\
+                            LIVES = 3\n\
+                            SPEED = 0x10\n\
+                            \n\
+                            [profile.vip]\n\
+                            output = \"build/pong\"\n\
+                            \n\
+                            [profile.schip]\n\
+                            target = \"schip\" # bigger screen\n\
+                            quirk = [\"no-jump-vx\"]\n\
+                            formats = [\"binary\"]\n\
+                            optimize = true\n\
+                            \n\
+                            [profile.schip.defines]\n\
+                            LIVES = 5\n";
+
+    #[test]
+    fn test_parse() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+
+        assert_eq!(manifest.settings.entry, Some(String::from("src/pong.asm")));
+        assert_eq!(manifest.settings.defines, vec![(String::from("LIVES"), 3), (String::from("SPEED"), 0x10)]);
+        let names: Vec<&str> = manifest.profiles.iter().map(|(name, _)| name.as_ref()).collect();
+        assert_eq!(names, vec!["vip", "schip"]);
+    }
+
+    #[test]
+    fn test_build_profiles() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+
+        let vip = manifest.build(Some("vip")).unwrap();
+        assert_eq!(vip.target, Target::Chip8);
+        assert_eq!(vip.include, vec![String::from("lib")]);
+        assert_eq!(vip.outputs, vec![
+            (Format::Binary, String::from("build/pong.c8")),
+            (Format::CHeader, String::from("build/pong.h"))
+        ]);
+
+        let schip = manifest.build(Some("schip")).unwrap();
+        assert_eq!(schip.target, Target::SuperChip);
+        assert_eq!(schip.quirks, Quirks::default());
+        assert!(schip.optimize);
+        assert_eq!(schip.outputs, vec![(Format::Binary, String::from("src/pong.c8"))]);
+        assert_eq!(schip.defines, vec![(String::from("SPEED"), 0x10), (String::from("LIVES"), 5)]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |source: &str| Manifest::parse(source).unwrap_err().to_string();
+
+        assert_eq!(error("entry = \"a.asm\"\nentry = \"b.asm\"\n"), "2: Duplicate key entry");
+        assert_eq!(error("entyr = \"a.asm\"\n"), "1: Unknown key entyr");
+        assert_eq!(error("base = \"$200\"\n"), "1: base must be a number");
+        assert_eq!(error("entry = \"a.asm\n"), "1: Unterminated string");
+        assert_eq!(error("[targets]\n"), "1: Unknown table targets, expected defines, profile.NAME or profile.NAME.defines");
+        assert_eq!(error("formats = [\"binary\" \"rust\"]\n"), "1: Expected , or ] in list");
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let manifest = Manifest::parse("target = \"chip9\"\n[profile.a]\nentry = \"a.asm\"\n").unwrap();
+
+        assert_eq!(manifest.build(None).unwrap_err().to_string(), "The manifest has no entry");
+        assert_eq!(manifest.build(Some("a")).unwrap_err().to_string(), "Unknown target: chip9");
+        assert_eq!(manifest.build(Some("b")).unwrap_err().to_string(), "Unknown profile b");
+    }
+
+    #[test]
+    fn test_builds_same_output() {
+        let manifest = Manifest::parse("entry = \"src/pong.asm\"\n[profile.a]\n[profile.b]\ntarget = \"schip\"\n").unwrap();
+
+        assert_eq!(
+            manifest.builds(&[Some("a"), Some("b")]).unwrap_err().to_string(),
+            "Profiles a and b both write src/pong.c8, give them different outputs"
+        );
+        assert_eq!(manifest.builds(&[Some("b")]).unwrap().len(), 1);
+
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        assert_eq!(manifest.builds(&[Some("vip"), Some("schip")]).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,557 @@
+use assembler::{ByteKind, Severity, SourceMapEntry};
+use assembler::diagnostic::{Diagnostic, Location};
+use flow::{ControlFlow, EdgeKind};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Number of bytes written on each line of a source array
+const BYTES_PER_LINE: usize = 12;
+
+/// Formats the assembled program can be written in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Binary,
+    CHeader,
+    Rust
+}
+
+impl Format {
+    /// Get the format from its command line name
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "binary" => Some(Format::Binary),
+            "c-header" => Some(Format::CHeader),
+            "rust" => Some(Format::Rust),
+            _ => None
+        }
+    }
+
+    /// Extension of files written in the format
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Format::Binary => "c8",
+            Format::CHeader => "h",
+            Format::Rust => "rs"
+        }
+    }
+}
+
+/// Render the assembled program in the given format.
+/// `name` is used for the array identifier and is turned into a valid identifier first
+pub fn render(format: Format, name: &str, data: &[u8], symbols: &HashMap<String, u32>) -> Vec<u8> {
+    match format {
+        Format::Binary => data.to_vec(),
+        Format::CHeader => to_c_header(name, data, symbols).into_bytes(),
+        Format::Rust => to_rust(name, data, symbols).into_bytes()
+    }
+}
+/// Render the program as a C header containing a byte array, its length and the label addresses.
+/// The array is static so the header can be included in more than one source file
+/// Render the program as a C header containing a byte array, its length and the label addresses
+pub fn to_c_header(name: &str, data: &[u8], symbols: &HashMap<String, u32>) -> String {
+    let array_name = identifier(name).to_lowercase();
+    let prefix = array_name.to_uppercase();
+
+    let mut out = String::new();
+    writeln!(out, "#ifndef {}_H", prefix).unwrap();
+    writeln!(out, "#define {}_H", prefix).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#include <stdint.h>").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#define {}_LEN {}", prefix, data.len()).unwrap();
+
+    let symbols = sorted_symbols(symbols);
+    if !symbols.is_empty() {
+        writeln!(out).unwrap();
+        for &(label, address) in symbols.iter() {
+            writeln!(out, "#define {}_{} 0x{:03X}", prefix, identifier(label).to_uppercase(), address).unwrap();
+        }
+    }
+
+    writeln!(out).unwrap();
+    writeln!(out, "static const uint8_t {}[] = {{", array_name).unwrap();
+    write_byte_lines(&mut out, data);
+    writeln!(out, "}};").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#endif").unwrap();
+
+    out
+}
+
+/// Render the program as a Rust source file containing a byte array constant and the label addresses
+pub fn to_rust(name: &str, data: &[u8], symbols: &HashMap<String, u32>) -> String {
+    let const_name = identifier(name).to_uppercase();
+
+    let mut out = String::new();
+    for &(label, address) in sorted_symbols(symbols).iter() {
+        writeln!(out, "pub const {}_{}: u16 = 0x{:03X};", const_name, identifier(label).to_uppercase(), address).unwrap();
+    }
+    if !symbols.is_empty() {
+        writeln!(out).unwrap();
+    }
+
+    writeln!(out, "pub const {}: [u8; {}] = [", const_name, data.len()).unwrap();
+    write_byte_lines(&mut out, data);
+    writeln!(out, "];").unwrap();
+
+    out
+}
+
+/// List the label addresses, one `$ADDR name` line each in address order
+pub fn to_symbols(symbols: &HashMap<String, u32>) -> String {
+    let mut out = String::new();
+    for &(label, address) in sorted_symbols(symbols).iter() {
+        writeln!(out, "${:04X} {}", address, label).unwrap();
+    }
+
+    out
+}
+
+/// List each statement with its address and the bytes it wrote, from the source
+/// map. `sources` holds the text of the files by name. Statements writing more
+/// than four bytes continue on the following lines
+pub fn to_listing(source_map: &[SourceMapEntry], data: &[u8], base: u32, sources: &HashMap<String, String>) -> String {
+    let mut out = String::new();
+
+    for entry in source_map.iter() {
+        let start = (entry.address - base) as usize;
+        let bytes = data.get(start..start + entry.size as usize).unwrap_or(&[]);
+        let line = sources.get(&entry.location.file)
+            .and_then(|source| source.lines().nth(entry.location.line - 1))
+            .unwrap_or("")
+            .trim_end();
+
+        for (i, chunk) in bytes.chunks(4).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+            let text = if i == 0 { line } else { "" };
+            // continuation lines have no source and so nothing after the bytes
+            let listed = format!("${:04X}  {:<11}  {}", entry.address as usize + i * 4, hex.join(" "), text);
+            writeln!(out, "{}", listed.trim_end()).unwrap();
+        }
+    }
+
+    out
+}
+
+/// Render the source map as JSON for debuggers. Each entry covers the bytes written by one statement
+pub fn to_source_map_json(source_map: &[SourceMapEntry]) -> String {
+    let mut out = String::new();
+    writeln!(out, "{{").unwrap();
+    writeln!(out, "  \"entries\": [").unwrap();
+
+    for (i, entry) in source_map.iter().enumerate() {
+        let label = match entry.label {
+            Some(ref label) => json_string(label),
+            None => String::from("null")
+        };
+        let kind = match entry.kind {
+            ByteKind::Code => "code",
+            ByteKind::Data => "data"
+        };
+        let separator = if i + 1 < source_map.len() { "," } else { "" };
+
+        writeln!(out, "    {{\"address\": {}, \"size\": {}, \"file\": {}, \"line\": {}, \"column\": {}, \"label\": {}, \"kind\": \"{}\"}}{}",
+            entry.address, entry.size, json_string(&entry.location.file), entry.location.line, entry.location.column,
+            label, kind, separator).unwrap();
+    }
+
+    writeln!(out, "  ]").unwrap();
+    writeln!(out, "}}").unwrap();
+
+    out
+}
+
+/// Render a diagnostic as a single line of JSON, for editors and other tools. The span
+/// ends just past the last column it covers, and each fix replaces the text of the span.
+/// Related locations, such as the earlier of two overlapping writes, have spans of their own
+pub fn to_diagnostic_json(diagnostic: &Diagnostic) -> String {
+    let location = match diagnostic.location {
+        Some(ref location) => json_location(location),
+        None => String::from("\"file\": null, \"span\": null")
+    };
+    let fixes: Vec<String> = diagnostic.fixes.iter()
+        .map(|fix| format!("{{\"message\": {}, \"replacement\": {}}}", json_string(&fix.message), json_string(&fix.replacement)))
+        .collect();
+    let related: Vec<String> = diagnostic.related.iter()
+        .map(|related| format!("{{\"message\": {}, {}}}", json_string(&related.message), json_location(&related.location)))
+        .collect();
+
+    format!("{{{}, \"severity\": \"{}\", \"code\": \"{}\", \"message\": {}, \"fixes\": [{}], \"related\": [{}]}}",
+        location, diagnostic.severity, diagnostic.code, json_string(&diagnostic.message), fixes.join(", "), related.join(", "))
+}
+
+fn json_location(location: &Location) -> String {
+    format!("\"file\": {}, \"span\": {{\"line\": {}, \"column\": {}, \"end_line\": {}, \"end_column\": {}}}",
+        json_string(&location.file), location.line, location.column, location.line, location.end_column)
+}
+
+/// Render a diagnostic for a terminal, with the location in bold and the severity in colour
+pub fn to_colored(diagnostic: &Diagnostic) -> String {
+    let severity = match diagnostic.severity {
+        Severity::Error => format!("\x1b[1;31merror[{}]\x1b[0m", diagnostic.code),
+        Severity::Warning => format!("\x1b[1;33mwarning[{}]\x1b[0m", diagnostic.code)
+    };
+
+    let mut out = match diagnostic.location {
+        Some(ref location) => format!("\x1b[1m{}\x1b[0m: {}: {}", location, severity, diagnostic.message),
+        None => format!("{}: {}", severity, diagnostic.message)
+    };
+    for related in diagnostic.related.iter() {
+        write!(out, "\n\x1b[1m{}\x1b[0m: \x1b[1;36mnote\x1b[0m: {}", related.location, related.message).unwrap();
+    }
+
+    out
+}
+
+/// Render the basic blocks as a Graphviz digraph. Unreachable blocks are dashed
+pub fn to_cfg_dot(flow: &ControlFlow) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph cfg {{").unwrap();
+    writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+    for block in flow.blocks.iter() {
+        let style = if block.reachable { "" } else { ", style=dashed" };
+        writeln!(out, "    b{:03X} [label=\"{}\\n${:03X}-${:03X}\"{}];", block.start, flow.name(block.start), block.start, block.end.wrapping_sub(1), style).unwrap();
+    }
+    for block in flow.blocks.iter() {
+        for edge in block.successors.iter() {
+            // edges leaving the code, into data or nothing, point at a plain address
+            if flow.block_at(edge.target).is_none() {
+                writeln!(out, "    b{:03X} [label=\"${:03X}\", shape=plaintext];", edge.target, edge.target).unwrap();
+            }
+            let label = match edge.kind {
+                EdgeKind::Fallthrough => String::new(),
+                kind => format!(" [label=\"{}\"]", kind.name())
+            };
+            writeln!(out, "    b{:03X} -> b{:03X}{};", block.start, edge.target, label).unwrap();
+        }
+    }
+
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Render the basic blocks as JSON
+pub fn to_cfg_json(flow: &ControlFlow) -> String {
+    let mut out = String::new();
+    writeln!(out, "{{").unwrap();
+    writeln!(out, "  \"entry\": {},", flow.entry).unwrap();
+    writeln!(out, "  \"blocks\": [").unwrap();
+
+    for (i, block) in flow.blocks.iter().enumerate() {
+        let successors: Vec<String> = block.successors.iter()
+            .map(|edge| format!("{{\"target\": {}, \"kind\": \"{}\"}}", edge.target, edge.kind.name()))
+            .collect();
+        let separator = if i + 1 < flow.blocks.len() { "," } else { "" };
+
+        writeln!(out, "    {{\"start\": {}, \"end\": {}, \"name\": {}, \"instructions\": {}, \"reachable\": {}, \"successors\": [{}]}}{}",
+            block.start, block.end, json_string(&flow.name(block.start)), block.instructions.len(), block.reachable,
+            successors.join(", "), separator).unwrap();
+    }
+
+    writeln!(out, "  ]").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Render which subroutines call which as a Graphviz digraph
+pub fn to_call_graph_dot(flow: &ControlFlow) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph calls {{").unwrap();
+
+    for &subroutine in flow.subroutines.iter() {
+        writeln!(out, "    s{:03X} [label={}];", subroutine, json_string(&flow.name(subroutine))).unwrap();
+    }
+
+    let mut edges: Vec<(u16, u16)> = flow.calls.iter().map(|call| (call.caller, call.callee)).collect();
+    edges.sort();
+    edges.dedup();
+    for &(caller, callee) in edges.iter() {
+        writeln!(out, "    s{:03X} -> s{:03X};", caller, callee).unwrap();
+    }
+
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Render the calls as JSON, one entry per CALL instruction
+pub fn to_call_graph_json(flow: &ControlFlow) -> String {
+    let subroutines: Vec<String> = flow.subroutines.iter()
+        .map(|&address| format!("{{\"address\": {}, \"name\": {}}}", address, json_string(&flow.name(address))))
+        .collect();
+
+    let mut out = String::new();
+    writeln!(out, "{{").unwrap();
+    writeln!(out, "  \"subroutines\": [{}],", subroutines.join(", ")).unwrap();
+    writeln!(out, "  \"calls\": [").unwrap();
+
+    for (i, call) in flow.calls.iter().enumerate() {
+        let separator = if i + 1 < flow.calls.len() { "," } else { "" };
+        writeln!(out, "    {{\"address\": {}, \"caller\": {}, \"callee\": {}}}{}",
+            call.address, json_string(&flow.name(call.caller)), json_string(&flow.name(call.callee)), separator).unwrap();
+    }
+
+    writeln!(out, "  ]").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Quote and escape a string for JSON
+pub fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+/// Write the bytes as comma separated hex literals, indented and wrapped
+fn write_byte_lines(out: &mut String, data: &[u8]) {
+    for line in data.chunks(BYTES_PER_LINE) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02X}", b)).collect();
+        writeln!(out, "    {},", bytes.join(", ")).unwrap();
+    }
+}
+
+/// Labels ordered by address, then by name so the output is stable
+fn sorted_symbols(symbols: &HashMap<String, u32>) -> Vec<(&str, u32)> {
+    let mut sorted: Vec<(&str, u32)> = symbols.iter().map(|(label, address)| (label.as_ref(), *address)).collect();
+    sorted.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+
+    sorted
+}
+
+/// Turn an arbitrary name into a C and Rust identifier
+pub fn identifier(name: &str) -> String {
+    let mut ident: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if ident.is_empty() || ident.chars().next().unwrap().is_ascii_digit() {
+        ident.insert(0, '_');
+    }
+
+    ident
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+    use assembler::codes;
+    use assembler::diagnostic::{Fix, Related};
+
+    #[test]
+    fn test_format_from_name() {
+        assert_eq!(Format::from_name("binary"), Some(Format::Binary));
+        assert_eq!(Format::from_name("c-header"), Some(Format::CHeader));
+        assert_eq!(Format::from_name("rust"), Some(Format::Rust));
+        assert_eq!(Format::from_name("hex"), None);
+    }
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(identifier("game"), "game");
+        assert_eq!(identifier("my-game.v2"), "my_game_v2");
+        assert_eq!(identifier("15puzzle"), "_15puzzle");
+    }
+
+    #[test]
+    fn test_to_c_header() {
+        let mut symbols = HashMap::new();
+        symbols.insert(String::from("start"), 0x200);
+
+        let header = to_c_header("game", &[0x00, 0xE0], &symbols);
+
+        assert_eq!(header, "#ifndef GAME_H\n\
+                            #define GAME_H\n\
+                            \n\
+                            #include <stdint.h>\n\
+                            \n\
+                            #define GAME_LEN 2\n\
+                            \n\
+                            #define GAME_START 0x200\n\
+                            \n\
+                            static const uint8_t game[] = {\n    0x00, 0xE0,\n};\n\
+                            \n\
+                            #endif\n");
+    }
+
+    #[test]
+    fn test_to_rust() {
+        let mut symbols = HashMap::new();
+        symbols.insert(String::from("start"), 0x200);
+        symbols.insert(String::from("end"), 0x202);
+
+        let source = to_rust("game", &[0x00, 0xE0, 0x12, 0x02], &symbols);
+
+        assert_eq!(source, "pub const GAME_START: u16 = 0x200;\n\
+                            pub const GAME_END: u16 = 0x202;\n\
+                            \n\
+                            pub const GAME: [u8; 4] = [\n    0x00, 0xE0, 0x12, 0x02,\n];\n");
+    }
+
+    #[test]
+    fn test_to_source_map_json() {
+        let source_map = vec![
+            SourceMapEntry{address: 0x200, size: 2, location: Location{file: String::from("game.asm"), line: 2, column: 2, end_column: 10}, kind: ByteKind::Code, label: Some(String::from("start"))},
+            SourceMapEntry{address: 0x300, size: 1, location: Location{file: String::from("game.asm"), line: 5, column: 2, end_column: 8}, kind: ByteKind::Data, label: None}
+        ];
+
+        assert_eq!(to_source_map_json(&source_map), "{\n  \"entries\": [\n\
+            \x20   {\"address\": 512, \"size\": 2, \"file\": \"game.asm\", \"line\": 2, \"column\": 2, \"label\": \"start\", \"kind\": \"code\"},\n\
+            \x20   {\"address\": 768, \"size\": 1, \"file\": \"game.asm\", \"line\": 5, \"column\": 2, \"label\": null, \"kind\": \"data\"}\n\
+            \x20 ]\n}\n");
+    }
+
+    #[test]
+    fn test_to_symbols() {
+        let mut symbols = HashMap::new();
+        symbols.insert(String::from("sprite"), 0x20A);
+        symbols.insert(String::from("start"), 0x200);
+
+        assert_eq!(to_symbols(&symbols), "$0200 start\n$020A sprite\n");
+    }
+
+    #[test]
+    fn test_to_listing() {
+        let source_map = vec![
+            SourceMapEntry{address: 0x200, size: 2, location: Location{file: String::from("game.asm"), line: 1, column: 7, end_column: 15}, kind: ByteKind::Code, label: Some(String::from("start"))},
+            SourceMapEntry{address: 0x202, size: 6, location: Location{file: String::from("game.asm"), line: 3, column: 2, end_column: 20}, kind: ByteKind::Data, label: None}
+        ];
+        let mut sources = HashMap::new();
+        sources.insert(String::from("game.asm"), String::from("start\tLD V0, 5   \nship\n\tdb 1 2 3 4 5 6 ; ship\n"));
+
+        assert_eq!(to_listing(&source_map, &[0x60, 0x05, 1, 2, 3, 4, 5, 6], 0x200, &sources),
+            "$0200  60 05        start\tLD V0, 5\n\
+             $0202  01 02 03 04  \tdb 1 2 3 4 5 6 ; ship\n\
+             $0206  05 06\n");
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("games\\pong \"v2\".asm"), "\"games\\\\pong \\\"v2\\\".asm\"");
+    }
+
+    fn flow(source: &[u8]) -> ControlFlow {
+        let output = Assembler::new().assemble("game.asm", source).unwrap();
+        ControlFlow::build(&output, 0x200)
+    }
+
+    #[test]
+    fn test_to_cfg_dot() {
+        let dot = to_cfg_dot(&flow(b"start\tSE V0, 1\n\tJP #start\n\tRET\nunused\tRET\n"));
+
+        assert_eq!(dot, "digraph cfg {\n\
+                         \x20   node [shape=box, fontname=\"monospace\"];\n\
+                         \x20   b200 [label=\"start\\n$200-$201\"];\n\
+                         \x20   b202 [label=\"start+2\\n$202-$203\"];\n\
+                         \x20   b204 [label=\"start+4\\n$204-$205\"];\n\
+                         \x20   b206 [label=\"unused\\n$206-$207\", style=dashed];\n\
+                         \x20   b200 -> b202;\n\
+                         \x20   b200 -> b204 [label=\"skip\"];\n\
+                         \x20   b202 -> b200 [label=\"jump\"];\n\
+                         }\n");
+    }
+
+    #[test]
+    fn test_to_cfg_json() {
+        let json = to_cfg_json(&flow(b"start\tJP #start\n"));
+
+        assert_eq!(json, "{\n  \"entry\": 512,\n  \"blocks\": [\n\
+                          \x20   {\"start\": 512, \"end\": 514, \"name\": \"start\", \"instructions\": 1, \"reachable\": true, \"successors\": [{\"target\": 512, \"kind\": \"jump\"}]}\n\
+                          \x20 ]\n}\n");
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let flow = flow(b"start\tCALL #sub\n\tCALL #sub\nend\tJP #end\nsub\tRET\n");
+
+        assert_eq!(to_call_graph_dot(&flow), "digraph calls {\n\
+                                              \x20   s200 [label=\"start\"];\n\
+                                              \x20   s206 [label=\"sub\"];\n\
+                                              \x20   s200 -> s206;\n\
+                                              }\n");
+        assert_eq!(to_call_graph_json(&flow), "{\n  \"subroutines\": [{\"address\": 512, \"name\": \"start\"}, {\"address\": 518, \"name\": \"sub\"}],\n  \"calls\": [\n\
+                                               \x20   {\"address\": 512, \"caller\": \"start\", \"callee\": \"sub\"},\n\
+                                               \x20   {\"address\": 514, \"caller\": \"start\", \"callee\": \"sub\"}\n\
+                                               \x20 ]\n}\n");
+    }
+
+    #[test]
+    fn test_call_graph_interleaved_calls() {
+        let flow = flow(b"start\tCALL #a\n\tCALL #b\n\tCALL #a\nend\tJP #end\na\tRET\nb\tRET\n");
+
+        assert_eq!(to_call_graph_dot(&flow), "digraph calls {\n\
+                                              \x20   s200 [label=\"start\"];\n\
+                                              \x20   s208 [label=\"a\"];\n\
+                                              \x20   s20A [label=\"b\"];\n\
+                                              \x20   s200 -> s208;\n\
+                                              \x20   s200 -> s20A;\n\
+                                              }\n");
+    }
+
+    #[test]
+    fn test_format_extension() {
+        assert_eq!(Format::from_name("binary").unwrap().extension(), "c8");
+        assert_eq!(Format::from_name("c-header").unwrap().extension(), "h");
+        assert_eq!(Format::from_name("rust").unwrap().extension(), "rs");
+    }
+
+    #[test]
+    fn test_byte_lines_wrap() {
+        let source = to_rust("rom", &[0xFF; 13], &HashMap::new());
+        let lines: Vec<&str> = source.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[2], "    0xFF,");
+    }
+
+    #[test]
+    fn test_diagnostic_json() {
+        let location = Location{file: String::from("game.asm"), line: 3, column: 13, end_column: 20};
+        let diagnostic = Diagnostic::new(Severity::Error, codes::UNDEFINED_LABEL, "Undefined label: \"end\"", Some(location));
+
+        assert_eq!(to_diagnostic_json(&diagnostic),
+                   "{\"file\": \"game.asm\", \"span\": {\"line\": 3, \"column\": 13, \"end_line\": 3, \"end_column\": 20}, \"severity\": \"error\", \
+                    \"code\": \"E009\", \"message\": \"Undefined label: \\\"end\\\"\", \"fixes\": [], \"related\": []}");
+        assert_eq!(to_diagnostic_json(&Diagnostic::new(Severity::Warning, codes::UNREACHABLE, "Unused", None)),
+                   "{\"file\": null, \"span\": null, \"severity\": \"warning\", \"code\": \"W010\", \"message\": \"Unused\", \"fixes\": [], \"related\": []}");
+    }
+
+    #[test]
+    fn test_diagnostic_json_fixes() {
+        let diagnostic = Diagnostic::new(Severity::Warning, codes::QUIRK_DEPENDENT, "Shifts V0 in place", None)
+            .with_fixes(vec![Fix::new("Shift V0 in place", "SHR V0, V0")]);
+
+        assert!(to_diagnostic_json(&diagnostic).ends_with("\"fixes\": [{\"message\": \"Shift V0 in place\", \"replacement\": \"SHR V0, V0\"}], \"related\": []}"));
+    }
+
+    #[test]
+    fn test_diagnostic_json_related() {
+        let location = Location{file: String::from("game.asm"), line: 4, column: 1, end_column: 4};
+        let earlier = Location{file: String::from("lib.asm"), line: 2, column: 5, end_column: 8};
+        let diagnostic = Diagnostic::new(Severity::Error, codes::OVERLAPPING_WRITE, "Write to $200 overlaps earlier code", Some(location))
+            .with_related(vec![Related::new("Earlier code writing $200", earlier)]);
+
+        assert!(to_diagnostic_json(&diagnostic).ends_with("\"related\": [{\"message\": \"Earlier code writing $200\", \"file\": \"lib.asm\", \
+                                                           \"span\": {\"line\": 2, \"column\": 5, \"end_line\": 2, \"end_column\": 8}}]}"));
+    }
+
+    #[test]
+    fn test_colored() {
+        let diagnostic = Diagnostic::new(Severity::Warning, codes::UNREACHABLE, "Unused", None);
+
+        assert_eq!(to_colored(&diagnostic), "\x1b[1;33mwarning[W010]\x1b[0m: Unused");
+    }
+}
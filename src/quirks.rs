@@ -0,0 +1,112 @@
+/// Behaviours Chip8 interpreters disagree on. Everything off is the behaviour of
+/// most modern interpreters; the profiles match the machines they are named after
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Quirks {
+    /// SHR and SHL shift Vy into Vx instead of shifting Vx in place
+    pub shift_vy: bool,
+    /// LD [I], Vx and LD Vx, [I] leave I pointing past the last register
+    pub load_store_increment: bool,
+    /// JP V0, addr (BNNN) jumps to NNN + VX, X being the high nibble of the address
+    pub jump_vx: bool,
+    /// OR, AND and XOR reset VF to zero
+    pub vf_reset: bool,
+    /// sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+    /// drawing waits for the next 60Hz frame, so only one sprite is drawn per frame
+    pub display_wait: bool
+}
+
+/// Names of the individual quirks, as used on the command line
+pub const QUIRK_NAMES: [&str; 6] = ["shift-vy", "load-store", "jump-vx", "vf-reset", "wrap", "display-wait"];
+
+impl Quirks {
+    /// Original COSMAC VIP interpreter
+    pub fn vip() -> Self {
+        Quirks {
+            shift_vy: true,
+            load_store_increment: true,
+            vf_reset: true,
+            display_wait: true,
+            ..Quirks::default()
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub fn schip() -> Self {
+        Quirks {
+            jump_vx: true,
+            ..Quirks::default()
+        }
+    }
+
+    /// XO-CHIP
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_vy: true,
+            load_store_increment: true,
+            wrap: true,
+            ..Quirks::default()
+        }
+    }
+
+    /// Get a profile from its command line name. `custom` has every quirk off,
+    /// ready for individual quirks to be turned on
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::vip()),
+            "schip" => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xochip()),
+            "custom" => Some(Quirks::default()),
+            _ => None
+        }
+    }
+
+    /// Turn a quirk on by name, or off when the name is prefixed with `no-`.
+    /// Returns false for unknown names
+    pub fn set(&mut self, name: &str) -> bool {
+        let (name, value) = match name.strip_prefix("no-") {
+            Some(name) => (name, false),
+            None => (name, true)
+        };
+
+        let quirk = match name {
+            "shift-vy" => &mut self.shift_vy,
+            "load-store" => &mut self.load_store_increment,
+            "jump-vx" => &mut self.jump_vx,
+            "vf-reset" => &mut self.vf_reset,
+            "wrap" => &mut self.wrap,
+            "display-wait" => &mut self.display_wait,
+            _ => return false
+        };
+        *quirk = value;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Quirks::from_name("vip"), Some(Quirks::vip()));
+        assert_eq!(Quirks::from_name("custom"), Some(Quirks::default()));
+        assert_eq!(Quirks::from_name("chip48"), None);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut quirks = Quirks::vip();
+
+        assert!(quirks.set("wrap"));
+        assert!(quirks.set("no-display-wait"));
+        assert!(!quirks.set("fast"));
+        assert!(quirks.wrap);
+        assert!(!quirks.display_wait);
+
+        for name in QUIRK_NAMES.iter() {
+            assert!(Quirks::default().set(name));
+        }
+    }
+}
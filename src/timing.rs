@@ -0,0 +1,286 @@
+use assembler::{AssemblyOutput, ByteKind};
+use flow::{self, EdgeKind};
+
+use std::fmt::Write;
+
+/// COSMAC VIP machine cycles in one 60Hz frame (1.76MHz clock, 8 clocks per machine cycle)
+pub const CYCLES_PER_FRAME: u32 = 3668;
+
+/// Fixed cost of DRW before any rows are drawn
+const DRW_BASE_CYCLES: u32 = 68;
+/// Cost of drawing one row of a sprite that straddles two bytes of display memory
+const DRW_ROW_CYCLES: u32 = 46;
+
+/// Worst case COSMAC VIP machine cycles for an instruction, fetch and decode included.
+/// Figures are estimates from published measurements of the original interpreter
+pub fn cycles(opcode: u16) -> u32 {
+    let x = ((opcode >> 8) & 0xF) as u32;
+    let n = (opcode & 0xF) as u32;
+
+    match opcode >> 12 {
+        0x0 if opcode == 0x00E0 => 24,
+        0x0 => 23,
+        0x1 | 0x2 | 0xB => 23,
+        0x3 | 0x4 => 14,
+        0x5 | 0x9 => 18,
+        0x6 => 6,
+        0x7 => 10,
+        0x8 => 44,
+        0xA => 12,
+        0xC => 36,
+        0xD => DRW_BASE_CYCLES + DRW_ROW_CYCLES * n,
+        0xE => 18,
+        _ => match opcode & 0xFF {
+            0x1E => 19,
+            0x29 => 20,
+            0x33 => 204,
+            0x55 | 0x65 => 14 + 8 * (x + 1),
+            _ => 10
+        }
+    }
+}
+
+/// Worst case for DRW. The VIP interpreter waits for the next frame before drawing
+pub fn draw_worst_case(rows: u8) -> u32 {
+    DRW_BASE_CYCLES + DRW_ROW_CYCLES * rows as u32 + CYCLES_PER_FRAME
+}
+
+/// Straight-line run of instructions
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub start: u16,
+    /// address after the last instruction
+    pub end: u16,
+    pub instructions: usize,
+    pub cycles: u32,
+    /// part of a loop closed by a jump back inside the routine
+    pub loop_body: bool
+}
+
+/// A sprite draw and what it costs at worst
+#[derive(Debug, PartialEq, Clone)]
+pub struct Draw {
+    pub address: u16,
+    pub rows: u8,
+    pub cycles: u32
+}
+
+/// Static timing of the code following a label
+#[derive(Debug, PartialEq, Clone)]
+pub struct RoutineTiming {
+    pub label: String,
+    pub start: u16,
+    pub end: u16,
+    pub instructions: usize,
+    pub blocks: Vec<Block>,
+    pub draws: Vec<Draw>,
+    /// the routine blocks on LD Vx, K
+    pub waits_for_key: bool
+}
+
+/// Time every labelled routine of a program whose image starts at `base`.
+/// Code before the first label is reported under its address
+pub fn analyze(output: &AssemblyOutput, base: u16) -> Vec<RoutineTiming> {
+    let mut code: Vec<u16> = output.source_map.iter()
+        .filter(|entry| entry.kind == ByteKind::Code)
+        .flat_map(|entry| (entry.address..entry.address + entry.size).step_by(2).map(|address| address as u16))
+        .collect();
+    code.sort();
+
+    let mut labels: Vec<(String, u16)> = output.symbols.iter().map(|(label, &address)| (label.clone(), address as u16)).collect();
+    labels.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+
+    // routines run from one label to the next
+    let mut starts: Vec<(String, u16)> = Vec::new();
+    if let Some(&first) = code.first() {
+        if labels.first().map(|&(_, address)| address > first).unwrap_or(true) {
+            starts.push((format!("${:03X}", first), first));
+        }
+    }
+    for (label, address) in labels.into_iter() {
+        // of several labels at one address only the first names the routine
+        if starts.last().map(|&(_, last)| last != address).unwrap_or(true) {
+            starts.push((label, address));
+        }
+    }
+
+    let opcode = |address: u16| {
+        let i = (address - base) as usize;
+        ((output.bytes[i] as u16) << 8) | output.bytes[i + 1] as u16
+    };
+
+    let mut routines = Vec::new();
+    for (i, &(ref label, start)) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).map(|&(_, address)| address).unwrap_or(u16::MAX);
+        let instructions: Vec<(u16, u16)> = code.iter()
+            .filter(|&&address| address >= start && address < end)
+            .map(|&address| (address, opcode(address)))
+            .collect();
+
+        if instructions.is_empty() {
+            continue;
+        }
+
+        routines.push(time_routine(label, &instructions));
+    }
+
+    routines
+}
+
+fn time_routine(label: &str, instructions: &[(u16, u16)]) -> RoutineTiming {
+    let start = instructions[0].0;
+    let end = instructions[instructions.len() - 1].0.wrapping_add(2);
+    let basic_blocks = flow::build_blocks(instructions, start);
+
+    // a jump back to an earlier address of the routine closes a loop
+    let back_edges: Vec<(u16, u16)> = basic_blocks.iter()
+        .flat_map(|block| {
+            let jump = block.instructions.last().unwrap().0;
+            block.successors.iter()
+                .filter(move |edge| edge.kind == EdgeKind::Jump && edge.target >= start && edge.target <= jump)
+                .map(move |edge| (edge.target, jump))
+        })
+        .collect();
+
+    let blocks = basic_blocks.iter().map(|block| Block {
+        start: block.start,
+        end: block.end,
+        instructions: block.instructions.len(),
+        cycles: block.instructions.iter().map(|&(_, opcode)| cycles(opcode)).sum(),
+        loop_body: back_edges.iter().any(|&(target, jump)| block.start >= target && block.start <= jump)
+    }).collect();
+
+    let draws = instructions.iter()
+        .filter(|&&(_, opcode)| opcode >> 12 == 0xD)
+        .map(|&(address, opcode)| Draw{address, rows: (opcode & 0xF) as u8, cycles: draw_worst_case((opcode & 0xF) as u8)})
+        .collect();
+
+    RoutineTiming {
+        label: String::from(label),
+        start,
+        end,
+        instructions: instructions.len(),
+        blocks,
+        draws,
+        waits_for_key: instructions.iter().any(|&(_, opcode)| opcode & 0xF0FF == 0xF00A)
+    }
+}
+
+/// Render the timing of each routine as a text report
+pub fn report(routines: &[RoutineTiming]) -> String {
+    let mut out = String::new();
+
+    for routine in routines.iter() {
+        writeln!(out, "{} ${:03X}-${:03X}: {} instructions{}", routine.label, routine.start, routine.end.wrapping_sub(1), routine.instructions,
+            if routine.waits_for_key { ", waits for a key" } else { "" }).unwrap();
+
+        for block in routine.blocks.iter() {
+            writeln!(out, "  ${:03X}-${:03X} {:4} instructions {:6} cycles {:5.2} frames{}",
+                block.start, block.end.wrapping_sub(1), block.instructions, block.cycles, block.cycles as f32 / CYCLES_PER_FRAME as f32,
+                if block.loop_body { "  loop" } else { "" }).unwrap();
+        }
+
+        for draw in routine.draws.iter() {
+            writeln!(out, "  DRW at ${:03X} ({} rows): at worst {} cycles, waiting for the next frame included",
+                draw.address, draw.rows, draw.cycles).unwrap();
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::{Assembler, Target};
+    use flow::ControlFlow;
+
+    fn timing(source: &[u8]) -> Vec<RoutineTiming> {
+        let output = Assembler::new().assemble("game.asm", source).unwrap();
+        analyze(&output, 0x200)
+    }
+
+    #[test]
+    fn test_cycles() {
+        assert_eq!(cycles(0x6005), 6);
+        assert_eq!(cycles(0xF255), 38);
+        assert_eq!(cycles(0xD015), DRW_BASE_CYCLES + 5 * DRW_ROW_CYCLES);
+        assert_eq!(draw_worst_case(5), cycles(0xD015) + CYCLES_PER_FRAME);
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let routines = timing(b"start\tLD V0, 1\n\tADD V0, 2\n\tRET\n");
+
+        assert_eq!(routines.len(), 1);
+        assert_eq!(routines[0].label, "start");
+        assert_eq!(routines[0].instructions, 3);
+        assert_eq!(routines[0].blocks, vec![Block{start: 0x200, end: 0x206, instructions: 3, cycles: 6 + 10 + 23, loop_body: false}]);
+    }
+
+    #[test]
+    fn test_loop() {
+        let routines = timing(b"start\tLD V0, 0\n\
+                               loop\tADD V0, 1\n\
+                               \tSE V0, 10\n\
+                               \tJP #loop\n\
+                               \tRET\n");
+
+        let blocks: Vec<(u16, bool)> = routines[1].blocks.iter().map(|block| (block.start, block.loop_body)).collect();
+
+        assert_eq!(routines[0].label, "start");
+        assert_eq!(routines[1].label, "loop");
+        assert_eq!(blocks, vec![(0x202, true), (0x206, true), (0x208, false)]);
+    }
+
+    #[test]
+    fn test_blocks_match_control_flow() {
+        let output = Assembler::new().assemble("game.asm", b"start\tLD V0, 2\n\tJR $206\n\tCLS\n\tJP $200\n\tRET\n").unwrap();
+        let starts: Vec<u16> = analyze(&output, 0x200)[0].blocks.iter().map(|block| block.start).collect();
+        let flow = ControlFlow::build(&output, 0x200);
+
+        assert_eq!(starts, vec![0x200, 0x204, 0x206, 0x208]);
+        assert_eq!(starts, flow.blocks.iter().map(|block| block.start).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn test_unlabelled_code_and_draws() {
+        let routines = timing(b"\tLD F, V0\n\tDRW V0, V1, 5\n");
+
+        assert_eq!(routines[0].label, "$200");
+        assert_eq!(routines[0].draws, vec![Draw{address: 0x202, rows: 5, cycles: draw_worst_case(5)}]);
+        assert!(!routines[0].waits_for_key);
+    }
+
+    #[test]
+    fn test_waits_for_key() {
+        let routine = time_routine("input", &[(0x200, 0xF00A), (0x202, 0x00EE)]);
+
+        assert!(routine.waits_for_key);
+    }
+
+    #[test]
+    fn test_code_at_end_of_memory() {
+        let output = Assembler::new().target(Target::XoChip).base_address(0).assemble("game.asm", b"\torg $FFFE\n\tCLS\n").unwrap();
+        let routines = analyze(&output, 0);
+
+        assert_eq!(report(&routines), "$FFFE $FFFE-$FFFF: 1 instructions\n  \
+                                       $FFFE-$FFFF    1 instructions     24 cycles  0.01 frames\n");
+    }
+
+    #[test]
+    fn test_data_is_skipped() {
+        let routines = timing(b"start\tCLS\nsprite\n\tdb $F0 $90\n");
+
+        assert_eq!(routines.len(), 1);
+        assert_eq!(routines[0].end, 0x202);
+    }
+
+    #[test]
+    fn test_report() {
+        let report = report(&timing(b"start\tCLS\n\tJP #start\n"));
+
+        assert_eq!(report, "start $200-$203: 2 instructions\n  \
+                            $200-$203    2 instructions     47 cycles  0.01 frames  loop\n");
+    }
+}